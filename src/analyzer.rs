@@ -1,38 +1,379 @@
-use crate::ast::{FunctionCallIterator, HuleFuncCall, HuleProgramAst, HuleStatement};
-use crate::program::HuleProgram;
+use crate::ast::{HuleExpression, HuleProgramAst, HuleStatement, HuleVariableDecl};
+use crate::callgraph::CallGraph;
+use crate::cfg::FunctionFlowGraph;
+use crate::consteval::{ConstEvalError, ConstEvaluator, ConstValue};
+use crate::dataflow;
 
+#[derive(Debug, PartialEq)]
 pub enum AnalyzerError {
     EntryPointMissing,
     FunctionRedefined(String),
+    MissingReturn(String),
+    BreakOutsideLoop(String),
+    ConstantWithoutValue(String),
+    ConstantAssigned(String),
+    ConstantTypeMismatch { name: String, expected: String, found: String },
+    ConstantExpression { name: String, error: ConstEvalError },
+    InvalidArraySize { name: String, size: i32 },
+}
+
+impl AnalyzerError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            AnalyzerError::EntryPointMissing => "No entry point defined.".to_string(),
+            AnalyzerError::FunctionRedefined(name)
+            => format!("Function '{}' is defined more than once.", name),
+            AnalyzerError::MissingReturn(name)
+            => format!("Function '{}' can reach its end without returning a value.", name),
+            AnalyzerError::BreakOutsideLoop(name)
+            => format!("Function '{}' uses 'break' outside of a loop.", name),
+            AnalyzerError::ConstantWithoutValue(name)
+            => format!("Constant '{}' is declared without a value.", name),
+            AnalyzerError::ConstantAssigned(name)
+            => format!("Constant '{}' can not be assigned.", name),
+            AnalyzerError::ConstantTypeMismatch { name, expected, found }
+            => format!("Constant '{}' is declared as '{}' but its value is '{}'.", name, expected, found),
+            AnalyzerError::ConstantExpression { name, error }
+            => format!("Invalid constant expression in '{}': {}", name, error.to_message()),
+            AnalyzerError::InvalidArraySize { name, size }
+            => format!("Array '{}' must have a size greater than 0, but has {}.", name, size),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum AnalyzerWarning {
+    UninitializedRead { function: String, variable: String },
+    UnusedVariable { function: String, variable: String },
+    UnusedParameter { function: String, variable: String },
+    UnreadAssignment { function: String, variable: String },
+    UnusedFunction(String),
+    UnreachableCode { function: String, statement: String },
+}
+
+impl AnalyzerWarning {
+    pub fn to_message(&self) -> String {
+        match &self {
+            AnalyzerWarning::UninitializedRead { function, variable }
+            => format!("Variable '{}' in '{}' may be read before it is assigned.", variable, function),
+            AnalyzerWarning::UnusedVariable { function, variable }
+            => format!("Variable '{}' in '{}' is never read.", variable, function),
+            AnalyzerWarning::UnusedParameter { function, variable }
+            => format!("Parameter '{}' of '{}' is never read.", variable, function),
+            AnalyzerWarning::UnreadAssignment { function, variable }
+            => format!("Value assigned to '{}' in '{}' is never read.", variable, function),
+            AnalyzerWarning::UnusedFunction(name)
+            => format!("Function '{}' is never called.", name),
+            AnalyzerWarning::UnreachableCode { function, statement }
+            => format!("Unreachable {} in '{}'.", statement, function),
+        }
+    }
 }
 
 pub struct Analyzer {
-    program: HuleProgramAst
+    program: HuleProgramAst,
+    call_graph: Option<CallGraph>,
+    warnings: Vec<AnalyzerWarning>,
 }
 
 impl Analyzer {
     pub fn new(program: HuleProgramAst) -> Analyzer {
         Analyzer {
-            program
+            program,
+            call_graph: None,
+            warnings: vec![],
         }
     }
 
-    pub fn get_function_by_name(&self, name: &str) -> Option<HuleFuncCall> {
-        self.program.body.items
-            .iter_function_calls()
-            .find(|p| p.name == name)
+    pub fn contains_function(&self, name: &str) -> bool {
+        self.program.functions()
+            .any(|f| f.name == name)
     }
 
-    pub fn contains_function(&self, name: &str) -> bool {
-        self.get_function_by_name(name)
-            .is_some()
+    pub fn program(&self) -> &HuleProgramAst {
+        &self.program
+    }
+
+    /// Call graph of the analyzed program, available after `analyze`.
+    pub fn call_graph(&self) -> Option<&CallGraph> {
+        self.call_graph.as_ref()
+    }
+
+    pub fn warnings(&self) -> &[AnalyzerWarning] {
+        &self.warnings
     }
 
-    fn analyze_unique_functions() -> Result<(), AnalyzerError> {
-        Err(AnalyzerError::EntryPointMissing)
+    fn analyze_unique_functions(&self) -> Result<(), AnalyzerError> {
+        let mut names: Vec<&str> = vec![];
+        for func in self.program.functions() {
+            if names.contains(&func.name.as_str()) {
+                return Err(AnalyzerError::FunctionRedefined(func.name.clone()));
+            }
+            names.push(&func.name);
+        }
+
+        if !self.contains_function("_entry") {
+            return Err(AnalyzerError::EntryPointMissing);
+        }
+
+        Ok(())
     }
 
-    pub fn analyze(&mut self) {
+    fn analyze_unused_functions(&mut self, call_graph: &CallGraph) {
+        let Some(entry) = call_graph.index_of("_entry") else {
+            return;
+        };
 
+        let reachable = call_graph.reachable_from(entry);
+        for func in self.program.functions() {
+            if let Some(index) = call_graph.index_of(&func.name) {
+                if !reachable[index] {
+                    self.warnings.push(AnalyzerWarning::UnusedFunction(func.name.clone()));
+                }
+            }
+        }
     }
-}
\ No newline at end of file
+
+    fn analyze_control_flow(&mut self, globals: &ConstEvaluator) -> Result<(), AnalyzerError> {
+        for func in self.program.functions() {
+            let flow = FunctionFlowGraph::from_function(func, globals);
+
+            if flow.has_break_outside_loop {
+                return Err(AnalyzerError::BreakOutsideLoop(func.name.clone()));
+            }
+
+            if func.return_type != "void" && flow.can_fall_through() {
+                return Err(AnalyzerError::MissingReturn(func.name.clone()));
+            }
+
+            for statement in flow.unreachable_statements() {
+                self.warnings.push(AnalyzerWarning::UnreachableCode {
+                    function: func.name.clone(),
+                    statement: statement.summary(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates `const` declarations and array sizes and reports constant
+    /// parts of expressions which divide by zero or overflow. Returns the
+    /// global constants.
+    fn analyze_constants(&self) -> Result<ConstEvaluator, AnalyzerError> {
+        let mut globals = ConstEvaluator::new();
+        for statement in &self.program.body.items {
+            if let HuleStatement::VariableDecl(decl) = statement {
+                Self::analyze_constant_decl(decl, &mut globals)?;
+            }
+        }
+
+        for func in self.program.functions() {
+            let mut locals = globals.clone();
+            for parameter in &func.parameters {
+                locals.undefine(&parameter.name);
+            }
+            Self::analyze_constants_in(&func.body, &func.name, &mut locals)?;
+        }
+
+        Ok(globals)
+    }
+
+    fn analyze_constant_decl(decl: &HuleVariableDecl, evaluator: &mut ConstEvaluator) -> Result<(), AnalyzerError> {
+        if let Some(size) = &decl.array_size {
+            let size = evaluator.evaluate(size)
+                .map_err(|error| AnalyzerError::ConstantExpression { name: decl.name.clone(), error })?;
+            match size {
+                ConstValue::Integer(value) if value > 0 => {}
+                ConstValue::Integer(value) => return Err(AnalyzerError::InvalidArraySize {
+                    name: decl.name.clone(),
+                    size: value,
+                }),
+                value => return Err(AnalyzerError::ConstantTypeMismatch {
+                    name: decl.name.clone(),
+                    expected: "int".to_string(),
+                    found: value.type_name().to_string(),
+                }),
+            }
+        }
+
+        if let Some(value) = &decl.value {
+            Self::analyze_constant_errors(value, &decl.name, evaluator)?;
+        }
+
+        if !decl.is_const {
+            evaluator.undefine(&decl.name);
+            return Ok(());
+        }
+
+        let value = decl.value.as_ref()
+            .ok_or_else(|| AnalyzerError::ConstantWithoutValue(decl.name.clone()))?;
+        let value = evaluator.evaluate(value)
+            .map_err(|error| AnalyzerError::ConstantExpression { name: decl.name.clone(), error })?;
+
+        if value.type_name() != decl.data_type {
+            return Err(AnalyzerError::ConstantTypeMismatch {
+                name: decl.name.clone(),
+                expected: decl.data_type.clone(),
+                found: value.type_name().to_string(),
+            });
+        }
+
+        evaluator.define(&decl.name, value);
+        Ok(())
+    }
+
+    fn analyze_constant_errors(expression: &HuleExpression, name: &str, evaluator: &ConstEvaluator) -> Result<(), AnalyzerError> {
+        match evaluator.find_error(expression) {
+            Some(error) => Err(AnalyzerError::ConstantExpression { name: name.to_string(), error }),
+            None => Ok(()),
+        }
+    }
+
+    fn analyze_constants_in(statement: &HuleStatement, function: &str, evaluator: &mut ConstEvaluator) -> Result<(), AnalyzerError> {
+        match statement {
+            HuleStatement::VariableDecl(decl) => Self::analyze_constant_decl(decl, evaluator)?,
+            HuleStatement::VariableDef(def) => {
+                if evaluator.get(&def.name).is_some() {
+                    return Err(AnalyzerError::ConstantAssigned(def.name.clone()));
+                }
+                Self::analyze_constant_errors(&def.value, function, evaluator)?;
+            }
+            HuleStatement::IfStatement(if_statement) => {
+                Self::analyze_constant_errors(&if_statement.condition, function, evaluator)?;
+                Self::analyze_constants_in(&if_statement.body, function, evaluator)?;
+                if let Some(else_body) = &if_statement.else_body {
+                    Self::analyze_constants_in(else_body, function, evaluator)?;
+                }
+            }
+            HuleStatement::WhileLoop(while_loop) => {
+                Self::analyze_constant_errors(&while_loop.condition, function, evaluator)?;
+                Self::analyze_constants_in(&while_loop.body, function, evaluator)?;
+            }
+            HuleStatement::FunctionCall(call) => {
+                for parameter in &call.parameters {
+                    Self::analyze_constant_errors(parameter, function, evaluator)?;
+                }
+            }
+            HuleStatement::Body(body) => {
+                // constants declared in the body end with it
+                let mut scope = evaluator.clone();
+                for item in &body.items {
+                    Self::analyze_constants_in(item, function, &mut scope)?;
+                }
+            }
+            HuleStatement::Return(value) => Self::analyze_constant_errors(value, function, evaluator)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn analyze(&mut self) -> Result<(), AnalyzerError> {
+        self.analyze_unique_functions()?;
+        let globals = self.analyze_constants()?;
+        self.analyze_control_flow(&globals)?;
+
+        let call_graph = CallGraph::from_ast(&self.program);
+        self.analyze_unused_functions(&call_graph);
+        self.call_graph = Some(call_graph);
+
+        for func in self.program.functions() {
+            self.warnings.extend(dataflow::analyze_function(func));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> Result<Vec<AnalyzerWarning>, AnalyzerError> {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut analyzer = Analyzer::new(ast);
+        analyzer.analyze()?;
+        Ok(analyzer.warnings)
+    }
+
+    #[test]
+    fn test_missing_return() {
+        assert_eq!(prepare_test("int f(int a) { if (a == 1) { return 1; } } entry { f(1); }").err(),
+            Some(AnalyzerError::MissingReturn("f".to_string())));
+        assert!(prepare_test("int f(int a) { if (a == 1) { return 1; } return 2; } entry { f(1); }").is_ok());
+    }
+
+    #[test]
+    fn test_endless_loop_needs_no_return() {
+        assert!(prepare_test("int f() { while (true) { return 1; } } entry { f(); }").is_ok());
+        assert!(prepare_test("const bool RUN = true; int f() { while (RUN) { return 1; } } entry { f(); }").is_ok());
+        assert_eq!(prepare_test("int f() { while (true) { break; } } entry { f(); }").err(),
+            Some(AnalyzerError::MissingReturn("f".to_string())));
+        assert_eq!(prepare_test("const bool RUN = true; int f(bool RUN) { while (RUN) { return 1; } } entry { f(false); }").err(),
+            Some(AnalyzerError::MissingReturn("f".to_string())));
+    }
+
+    #[test]
+    fn test_break_outside_loop() {
+        assert_eq!(prepare_test("entry { if (1 == 1) { break; } }").err(),
+            Some(AnalyzerError::BreakOutsideLoop("_entry".to_string())));
+        assert!(prepare_test("entry { while (1 == 1) { if (1 == 1) { break; } } }").is_ok());
+    }
+
+    #[test]
+    fn test_constant_assigned() {
+        assert_eq!(prepare_test("const int MAX = 3; entry { MAX = 4; }").err(),
+            Some(AnalyzerError::ConstantAssigned("MAX".to_string())));
+        assert_eq!(prepare_test("entry { const int MAX = 3; if (1 == 1) { MAX = 4; } }").err(),
+            Some(AnalyzerError::ConstantAssigned("MAX".to_string())));
+    }
+
+    #[test]
+    fn test_constant_scope() {
+        assert!(prepare_test("int A = 0; entry { if (1 == 1) { const int A = 1; } else { A = 2; } A = 3; }").is_ok());
+        assert!(prepare_test("const int A = 1; entry { if (1 == 1) { int A = 0; A = 2; } }").is_ok());
+        assert_eq!(prepare_test("const int A = 1; entry { if (1 == 1) { int A = 0; } A = 2; }").err(),
+            Some(AnalyzerError::ConstantAssigned("A".to_string())));
+    }
+
+    #[test]
+    fn test_constant_without_value() {
+        assert_eq!(prepare_test("const int MAX; entry { }").err(),
+            Some(AnalyzerError::ConstantWithoutValue("MAX".to_string())));
+    }
+
+    #[test]
+    fn test_constant_type_mismatch() {
+        assert_eq!(prepare_test("const int MAX = \"3\"; entry { }").err(), Some(AnalyzerError::ConstantTypeMismatch {
+            name: "MAX".to_string(),
+            expected: "int".to_string(),
+            found: "string".to_string(),
+        }));
+        assert_eq!(prepare_test("entry { int values[\"3\"]; }").err(), Some(AnalyzerError::ConstantTypeMismatch {
+            name: "values".to_string(),
+            expected: "int".to_string(),
+            found: "string".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_invalid_array_size() {
+        assert_eq!(prepare_test("const int SIZE = 2 - 2; entry { int values[SIZE]; }").err(),
+            Some(AnalyzerError::InvalidArraySize { name: "values".to_string(), size: 0 }));
+    }
+
+    #[test]
+    fn test_constant_expression() {
+        assert_eq!(prepare_test("const int MAX = 1 / 0; entry { }").err(), Some(AnalyzerError::ConstantExpression {
+            name: "MAX".to_string(),
+            error: ConstEvalError::DivisionByZero,
+        }));
+        assert_eq!(prepare_test("entry { int a = 0; a = a + 1 / 0; }").err(), Some(AnalyzerError::ConstantExpression {
+            name: "_entry".to_string(),
+            error: ConstEvalError::DivisionByZero,
+        }));
+    }
+}
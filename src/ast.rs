@@ -26,6 +26,38 @@ pub enum Operator {
     Divide,
 }
 
+impl Operator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Operator::Equal => "==",
+            Operator::NotEqual => "!=",
+            Operator::GreaterThan => ">",
+            Operator::GreaterEqualThan => ">=",
+            Operator::LowerThan => "<",
+            Operator::LowerEqualThan => "<=",
+            Operator::And => "&&",
+            Operator::Or => "||",
+            Operator::Plus => "+",
+            Operator::Minus => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+        }
+    }
+
+    /// Binding strength of the operator, higher values bind tighter.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Operator::Or => 1,
+            Operator::And => 2,
+            Operator::Equal | Operator::NotEqual => 3,
+            Operator::GreaterThan | Operator::GreaterEqualThan
+            | Operator::LowerThan | Operator::LowerEqualThan => 4,
+            Operator::Plus | Operator::Minus => 5,
+            Operator::Multiply | Operator::Divide => 6,
+        }
+    }
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub enum HuleExpression {
     Undefined,
@@ -42,6 +74,27 @@ pub enum HuleExpression {
     },
 }
 
+impl HuleExpression {
+    /// Calls `visitor` for every function call within this expression,
+    /// including calls nested in parameters.
+    pub fn visit_calls<F: FnMut(&str, &[HuleExpression])>(&self, visitor: &mut F) {
+        match self {
+            HuleExpression::Bracketed(inner) => inner.visit_calls(visitor),
+            HuleExpression::Call(name, parameters) => {
+                visitor(name, parameters);
+                for parameter in parameters {
+                    parameter.visit_calls(visitor);
+                }
+            }
+            HuleExpression::Binary { left, right, .. } => {
+                left.visit_calls(visitor);
+                right.visit_calls(visitor);
+            }
+            _ => {}
+        }
+    }
+}
+
 pub trait HuleExpressionResultExt {
     fn or_reset(self, program : &mut AstParser, index : usize) -> Self;
 }
@@ -109,8 +162,8 @@ impl HuleExpressionResultExt for Result<Token, AstParserError> {
 #[derive(Debug, Clone)]
 
 pub struct HuleParameter {
-    data_type: String,
-    name: String,
+    pub data_type: String,
+    pub name: String,
 }
 
 impl HuleParameter {
@@ -134,18 +187,29 @@ pub struct HuleFuncDef {
     pub parameters: Vec<HuleParameter>,
     pub return_type : String,
     pub body: Box<HuleStatement>,
+    /// Declared with the `inline` modifier
+    pub is_inline: bool,
 }
 
 #[derive(Clone, Debug)]
 pub struct  HuleIfStatement {
     pub condition: HuleExpression,
     pub body: Box<HuleStatement>,
+    pub else_body: Option<Box<HuleStatement>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct  HuleWhileLoop {
+    pub condition: HuleExpression,
+    pub body: Box<HuleStatement>,
 }
 #[derive(Clone, Debug)]
 pub struct  HuleVariableDecl {
     pub data_type: String,
     pub name: String,
     pub value: Option<HuleExpression>,
+    pub is_const: bool,
+    pub array_size: Option<HuleExpression>,
 }
 
 #[derive(Clone, Debug)]
@@ -165,17 +229,39 @@ pub struct HuleStructDef {
     pub name: String,
     pub members: Vec<String>,
 }
+/// Line and column in the hyperULE source, both start at 1.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct SourcePosition {
+    pub line: usize,
+    pub column: usize,
+}
+
 #[derive(Clone, Debug)]
 pub struct HuleBody {
-    pub items: Vec<HuleStatement>
+    pub items: Vec<HuleStatement>,
+    /// Position of the first token of every item, empty for bodies the
+    /// parser did not read from the source
+    pub positions: Vec<SourcePosition>,
 }
 
 impl HuleBody {
     pub fn new(items : Vec<HuleStatement>) -> HuleBody {
         HuleBody {
-            items
+            items,
+            positions: vec![],
+        }
+    }
+
+    pub fn with_positions(items: Vec<HuleStatement>, positions: Vec<SourcePosition>) -> HuleBody {
+        HuleBody {
+            items,
+            positions,
         }
     }
+
+    pub fn position(&self, index: usize) -> Option<SourcePosition> {
+        self.positions.get(index).copied()
+    }
 }
 
 /// Statements
@@ -188,10 +274,70 @@ pub enum HuleStatement {
     StructDecl(HuleStructDecl),
     StructDef(HuleStructDef),
     IfStatement(HuleIfStatement),
+    WhileLoop(HuleWhileLoop),
     FunctionDef(HuleFuncDef),
     FunctionCall(HuleFuncCall),
     Body(HuleBody),
     Return(HuleExpression),
+    Break,
+}
+
+impl HuleStatement {
+    /// Short description of the statement for diagnostics.
+    pub fn summary(&self) -> String {
+        match self {
+            HuleStatement::Undefined => "undefined statement".to_string(),
+            HuleStatement::VariableDecl(decl) => format!("declaration of '{}'", decl.name),
+            HuleStatement::VariableDef(def) => format!("assignment to '{}'", def.name),
+            HuleStatement::StructDecl(decl) => format!("declaration of struct '{}'", decl.name),
+            HuleStatement::StructDef(def) => format!("definition of struct '{}'", def.name),
+            HuleStatement::IfStatement(_) => "if statement".to_string(),
+            HuleStatement::WhileLoop(_) => "while loop".to_string(),
+            HuleStatement::FunctionDef(func) => format!("definition of function '{}'", func.name),
+            HuleStatement::FunctionCall(call) => format!("call to '{}'", call.name),
+            HuleStatement::Body(_) => "block".to_string(),
+            HuleStatement::Return(_) => "return statement".to_string(),
+            HuleStatement::Break => "break statement".to_string(),
+        }
+    }
+
+    /// Calls `visitor` for every function call within this statement, both
+    /// call statements and calls used inside expressions.
+    pub fn visit_calls<F: FnMut(&str, &[HuleExpression])>(&self, visitor: &mut F) {
+        match self {
+            HuleStatement::VariableDecl(decl) => {
+                if let Some(value) = &decl.value {
+                    value.visit_calls(visitor);
+                }
+            }
+            HuleStatement::VariableDef(def) => def.value.visit_calls(visitor),
+            HuleStatement::IfStatement(if_statement) => {
+                if_statement.condition.visit_calls(visitor);
+                if_statement.body.visit_calls(visitor);
+                if let Some(else_body) = &if_statement.else_body {
+                    else_body.visit_calls(visitor);
+                }
+            }
+            HuleStatement::WhileLoop(while_loop) => {
+                while_loop.condition.visit_calls(visitor);
+                while_loop.body.visit_calls(visitor);
+            }
+            HuleStatement::FunctionDef(func) => func.body.visit_calls(visitor),
+            HuleStatement::FunctionCall(call) => {
+                visitor(&call.name, &call.parameters);
+                for parameter in &call.parameters {
+                    parameter.visit_calls(visitor);
+                }
+            }
+            HuleStatement::Body(body) => {
+                for item in &body.items {
+                    item.visit_calls(visitor);
+                }
+            }
+            HuleStatement::Return(value) => value.visit_calls(visitor),
+            _ => {}
+        }
+    }
 }
 
 
@@ -214,4 +360,16 @@ impl FunctionCallIterator for Vec<HuleStatement> {
 #[derive(Debug)]
 pub struct HuleProgramAst {
     pub body: HuleBody,
+}
+
+impl HuleProgramAst {
+    pub fn functions(&self) -> impl Iterator<Item = &HuleFuncDef> {
+        self.body.items.iter().filter_map(|statement| {
+            if let HuleStatement::FunctionDef(func) = statement {
+                Some(func)
+            } else {
+                None
+            }
+        })
+    }
 }
\ No newline at end of file
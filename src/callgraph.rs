@@ -0,0 +1,268 @@
+//! hyperULE Call Graph
+//!
+//! Records which function calls which. The analyzer builds it from the
+//! `HuleFuncDef` bodies to warn about unused functions and for
+//! `--emit=callgraph`, the optimizer passes and the backend build it from
+//! the IR to find the functions reachable from `_entry` and the ones taking
+//! part in direct or mutual recursion.
+
+use crate::ast::HuleProgramAst;
+use crate::program::{HuleProgram, Terminator};
+
+pub struct CallGraph {
+    functions: Vec<String>,
+    callees: Vec<Vec<usize>>,
+    components: Vec<usize>,
+    recursive: Vec<bool>,
+}
+
+impl CallGraph {
+    pub fn from_ast(program: &HuleProgramAst) -> CallGraph {
+        let functions: Vec<String> = program.functions()
+            .map(|func| func.name.clone())
+            .collect();
+
+        let mut callees = vec![];
+
+        for func in program.functions() {
+            let mut func_callees: Vec<usize> = vec![];
+
+            // calls to functions which are not defined in the program are
+            // ULE built-ins and never take part in recursion
+            func.body.visit_calls(&mut |name, _| {
+                if let Some(index) = functions.iter().position(|f| f == name) {
+                    if !func_callees.contains(&index) {
+                        func_callees.push(index);
+                    }
+                }
+            });
+
+            callees.push(func_callees);
+        }
+
+        CallGraph::new(functions, callees)
+    }
+
+    /// Call graph of the IR, the index of a function is its `FunctionId`.
+    /// Calls of hyperULE functions are always terminators.
+    pub fn from_program(program: &HuleProgram) -> CallGraph {
+        let functions = program.functions().iter()
+            .map(|function| function.name.clone())
+            .collect();
+
+        let callees = program.functions().iter()
+            .map(|function| {
+                let mut func_callees: Vec<usize> = vec![];
+                for block in &function.blocks {
+                    if let Terminator::Call { function: callee, .. } | Terminator::TailCall { function: callee, .. } = block.terminator {
+                        if !func_callees.contains(&callee.0) {
+                            func_callees.push(callee.0);
+                        }
+                    }
+                }
+                func_callees
+            })
+            .collect();
+
+        CallGraph::new(functions, callees)
+    }
+
+    fn new(functions: Vec<String>, callees: Vec<Vec<usize>>) -> CallGraph {
+        let mut graph = CallGraph {
+            functions,
+            callees,
+            components: vec![],
+            recursive: vec![],
+        };
+        graph.detect_recursion();
+        graph
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.functions.iter().position(|f| f == name)
+    }
+
+    /// Marks every function which can be reached by calls starting at the
+    /// function at `index`, including the function itself.
+    pub fn reachable_from(&self, index: usize) -> Vec<bool> {
+        let mut reachable = vec![false; self.functions.len()];
+        let mut pending = vec![index];
+
+        while let Some(current) = pending.pop() {
+            if reachable[current] {
+                continue;
+            }
+
+            reachable[current] = true;
+            pending.extend(self.callees[current].iter().filter(|c| !reachable[**c]));
+        }
+
+        reachable
+    }
+
+    /// Whether the function at `to` can be reached by calls starting at the
+    /// function at `from`, including `from` itself.
+    pub fn reaches(&self, from: usize, to: usize) -> bool {
+        self.reachable_from(from)[to]
+    }
+
+    /// Whether the function at `index` can call itself directly or
+    /// indirectly.
+    pub fn is_recursive(&self, index: usize) -> bool {
+        self.recursive[index]
+    }
+
+    /// Renders the call graph in Graphviz DOT format. Recursive functions
+    /// and the calls between them are highlighted.
+    pub fn to_dot(&self) -> String {
+        let mut result = String::from("digraph calls {\n");
+
+        for (index, name) in self.functions.iter().enumerate() {
+            if self.recursive[index] {
+                result.push_str(&format!("    \"{}\" [color=red];\n", name));
+            } else {
+                result.push_str(&format!("    \"{}\";\n", name));
+            }
+        }
+
+        for (index, name) in self.functions.iter().enumerate() {
+            for callee in &self.callees[index] {
+                if self.components[index] == self.components[*callee] && self.recursive[index] {
+                    result.push_str(&format!("    \"{}\" -> \"{}\" [color=red];\n", name, self.functions[*callee]));
+                } else {
+                    result.push_str(&format!("    \"{}\" -> \"{}\";\n", name, self.functions[*callee]));
+                }
+            }
+        }
+
+        result.push_str("}\n");
+        result
+    }
+
+    /// Tarjan's algorithm for strongly connected components. Every function
+    /// in a component with more than one member, or with a call to itself,
+    /// is recursive.
+    fn detect_recursion(&mut self) {
+        let count = self.functions.len();
+        let mut state = TarjanState {
+            index: 0,
+            indices: vec![None; count],
+            low_links: vec![0; count],
+            on_stack: vec![false; count],
+            stack: vec![],
+            components: vec![0; count],
+            component_count: 0,
+        };
+
+        for node in 0..count {
+            if state.indices[node].is_none() {
+                self.strong_connect(node, &mut state);
+            }
+        }
+
+        self.components = state.components;
+        self.recursive = (0..count)
+            .map(|node| {
+                self.callees[node].contains(&node)
+                    || (0..count).any(|other| other != node && self.components[other] == self.components[node])
+            })
+            .collect();
+    }
+
+    fn strong_connect(&self, node: usize, state: &mut TarjanState) {
+        state.indices[node] = Some(state.index);
+        state.low_links[node] = state.index;
+        state.index += 1;
+        state.stack.push(node);
+        state.on_stack[node] = true;
+
+        for callee in &self.callees[node] {
+            match state.indices[*callee] {
+                None => {
+                    self.strong_connect(*callee, state);
+                    state.low_links[node] = state.low_links[node].min(state.low_links[*callee]);
+                }
+                Some(callee_index) => {
+                    if state.on_stack[*callee] {
+                        state.low_links[node] = state.low_links[node].min(callee_index);
+                    }
+                }
+            }
+        }
+
+        if Some(state.low_links[node]) == state.indices[node] {
+            while let Some(member) = state.stack.pop() {
+                state.on_stack[member] = false;
+                state.components[member] = state.component_count;
+                if member == node {
+                    break;
+                }
+            }
+            state.component_count += 1;
+        }
+    }
+}
+
+struct TarjanState {
+    index: usize,
+    indices: Vec<Option<usize>>,
+    low_links: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    components: Vec<usize>,
+    component_count: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> CallGraph {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        CallGraph::from_ast(&ast)
+    }
+
+    #[test]
+    fn test_no_recursion() {
+        let graph = prepare_test("string greet(string name) { return name; } entry { OUT1.Data = greet(OUT1.Data); }");
+
+        assert_eq!(graph.functions.len(), 2);
+        assert!(!graph.recursive.contains(&true));
+        assert_eq!(graph.callees[graph.index_of("_entry").unwrap()], vec![0]);
+    }
+
+    #[test]
+    fn test_direct_recursion() {
+        let graph = prepare_test("int count(int n) { return count(n - 1); } entry { count(3); }");
+
+        assert!(graph.is_recursive(graph.index_of("count").unwrap()));
+        assert!(!graph.is_recursive(graph.index_of("_entry").unwrap()));
+    }
+
+    #[test]
+    fn test_mutual_recursion() {
+        let graph = prepare_test("int a(int n) { return b(n); } int b(int n) { return a(n); } entry { a(1); }");
+
+        assert!(graph.is_recursive(0) && graph.is_recursive(1));
+        assert_eq!(graph.components[0], graph.components[1]);
+        assert!(graph.reaches(0, 1) && !graph.reaches(0, 2));
+    }
+
+    #[test]
+    fn test_program() {
+        let input = "int a(int n) { return a(n); } int b() { return 1; } int unused() { return a(1); } \
+            entry { OUT1.Data = IntToStr(a(b())); }";
+        let mut parser = AstParser::new(input);
+        let program = lower(&parser.parse(input).unwrap()).unwrap();
+        let graph = CallGraph::from_program(&program);
+        let index = |name: &str| graph.index_of(name).unwrap();
+
+        assert_eq!(index("_entry"), 0);
+        assert!(graph.is_recursive(index("a")) && !graph.is_recursive(index("b")));
+        assert_eq!(graph.reachable_from(0), vec![true, true, true, false]);
+        assert!(graph.reaches(index("unused"), index("a")));
+    }
+}
@@ -0,0 +1,354 @@
+//! hyperULE Control Flow Graph
+//!
+//! Generic control flow graph used by the analyzer to find unreachable code
+//! and functions which can fall off their end. The `FlowGraph` trait only
+//! needs the edges of a graph, so the reachability helpers can be shared
+//! with later passes working on other block types.
+
+use crate::ast::{HuleFuncDef, HuleStatement};
+use crate::consteval::{ConstEvaluator, ConstValue};
+
+pub trait FlowGraph {
+    fn entry_block(&self) -> usize;
+    fn block_count(&self) -> usize;
+    fn successors(&self, block: usize) -> Vec<usize>;
+
+    /// Marks every block which can be reached from the entry block.
+    fn reachable_blocks(&self) -> Vec<bool> {
+        let mut reachable = vec![false; self.block_count()];
+        let mut pending = vec![self.entry_block()];
+
+        while let Some(block) = pending.pop() {
+            if reachable[block] {
+                continue;
+            }
+
+            reachable[block] = true;
+            pending.extend(self.successors(block).into_iter().filter(|b| !reachable[*b]));
+        }
+
+        reachable
+    }
+
+    /// `result[b][d]` is true if block `d` dominates block `b`, which means
+    /// every path from the entry block to `b` passes `d`. Unreachable blocks
+    /// are dominated by every block.
+    fn dominators(&self) -> Vec<Vec<bool>> {
+        let count = self.block_count();
+        let mut predecessors = vec![vec![]; count];
+        for block in 0..count {
+            for successor in self.successors(block) {
+                predecessors[successor].push(block);
+            }
+        }
+
+        let entry = self.entry_block();
+        let mut dominators = vec![vec![true; count]; count];
+        dominators[entry] = (0..count).map(|block| block == entry).collect();
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in (0..count).filter(|block| *block != entry) {
+                let mut next = vec![true; count];
+                for predecessor in &predecessors[block] {
+                    for (dominator, is_dominator) in next.iter_mut().enumerate() {
+                        *is_dominator &= dominators[*predecessor][dominator];
+                    }
+                }
+                next[block] = true;
+
+                if next != dominators[block] {
+                    dominators[block] = next;
+                    changed = true;
+                }
+            }
+        }
+
+        dominators
+    }
+}
+
+pub struct FlowBlock<T> {
+    pub items: Vec<T>,
+    pub successors: Vec<usize>,
+}
+
+pub struct ControlFlowGraph<T> {
+    pub blocks: Vec<FlowBlock<T>>,
+}
+
+impl<T> ControlFlowGraph<T> {
+    pub fn new() -> ControlFlowGraph<T> {
+        ControlFlowGraph {
+            blocks: vec![FlowBlock { items: vec![], successors: vec![] }],
+        }
+    }
+
+    pub fn add_block(&mut self) -> usize {
+        self.blocks.push(FlowBlock { items: vec![], successors: vec![] });
+        self.blocks.len() - 1
+    }
+
+    pub fn add_edge(&mut self, from: usize, to: usize) {
+        if !self.blocks[from].successors.contains(&to) {
+            self.blocks[from].successors.push(to);
+        }
+    }
+}
+
+impl<T> FlowGraph for ControlFlowGraph<T> {
+    fn entry_block(&self) -> usize {
+        0
+    }
+
+    fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn successors(&self, block: usize) -> Vec<usize> {
+        self.blocks[block].successors.clone()
+    }
+}
+
+/// Control flow graph of a function body with one block per straight-line
+/// run of statements. Compound statements are placed in the block which
+/// evaluates their condition.
+pub struct FunctionFlowGraph<'a> {
+    pub graph: ControlFlowGraph<&'a HuleStatement>,
+    /// Block which is left by falling off the end of the function body
+    pub fall_through_block: usize,
+    /// Every statement in source order together with its block
+    pub statement_blocks: Vec<(&'a HuleStatement, usize)>,
+    pub has_break_outside_loop: bool,
+}
+
+impl<'a> FunctionFlowGraph<'a> {
+    /// Builds the graph of the function. Loops whose condition is constant
+    /// `true` with the given global constants can only be left by `break`.
+    pub fn from_function(func: &'a HuleFuncDef, constants: &ConstEvaluator) -> FunctionFlowGraph<'a> {
+        let mut builder = FlowGraphBuilder {
+            graph: ControlFlowGraph::new(),
+            current: 0,
+            return_block: 0,
+            loop_exits: vec![],
+            statement_blocks: vec![],
+            has_break_outside_loop: false,
+            constants: constants.clone(),
+        };
+        for parameter in &func.parameters {
+            builder.constants.undefine(&parameter.name);
+        }
+        builder.return_block = builder.graph.add_block();
+
+        let body_block = builder.graph.add_block();
+        builder.graph.add_edge(0, body_block);
+        builder.current = body_block;
+        builder.build_statement(&func.body);
+
+        FunctionFlowGraph {
+            fall_through_block: builder.current,
+            statement_blocks: builder.statement_blocks,
+            has_break_outside_loop: builder.has_break_outside_loop,
+            graph: builder.graph,
+        }
+    }
+
+    /// Whether the end of the function body can be reached without passing
+    /// a `return` statement.
+    pub fn can_fall_through(&self) -> bool {
+        self.graph.reachable_blocks()[self.fall_through_block]
+    }
+
+    /// First statement of every run of statements which can never be
+    /// executed.
+    pub fn unreachable_statements(&self) -> Vec<&'a HuleStatement> {
+        let reachable = self.graph.reachable_blocks();
+        let mut result = vec![];
+        let mut previous_reachable = true;
+
+        for (statement, block) in &self.statement_blocks {
+            if !reachable[*block] && previous_reachable {
+                result.push(*statement);
+            }
+            previous_reachable = reachable[*block];
+        }
+
+        result
+    }
+}
+
+struct FlowGraphBuilder<'a> {
+    graph: ControlFlowGraph<&'a HuleStatement>,
+    current: usize,
+    return_block: usize,
+    loop_exits: Vec<usize>,
+    statement_blocks: Vec<(&'a HuleStatement, usize)>,
+    has_break_outside_loop: bool,
+    /// Constants visible at the current statement
+    constants: ConstEvaluator,
+}
+
+impl<'a> FlowGraphBuilder<'a> {
+    fn push(&mut self, statement: &'a HuleStatement) {
+        self.graph.blocks[self.current].items.push(statement);
+        self.statement_blocks.push((statement, self.current));
+    }
+
+    /// Ends the current block with a jump to `target`. Statements following
+    /// the jump start a new block without predecessors.
+    fn jump(&mut self, target: usize) {
+        self.graph.add_edge(self.current, target);
+        self.current = self.graph.add_block();
+    }
+
+    fn build_statement(&mut self, statement: &'a HuleStatement) {
+        match statement {
+            HuleStatement::Body(body) => {
+                let outer = self.constants.clone();
+                for item in &body.items {
+                    self.build_statement(item);
+                }
+                self.constants = outer;
+            }
+            HuleStatement::IfStatement(if_statement) => {
+                self.push(statement);
+                let condition_block = self.current;
+                let join_block = self.graph.add_block();
+
+                let then_block = self.graph.add_block();
+                self.graph.add_edge(condition_block, then_block);
+                self.current = then_block;
+                self.build_statement(&if_statement.body);
+                self.graph.add_edge(self.current, join_block);
+
+                match &if_statement.else_body {
+                    Some(else_body) => {
+                        let else_block = self.graph.add_block();
+                        self.graph.add_edge(condition_block, else_block);
+                        self.current = else_block;
+                        self.build_statement(else_body);
+                        self.graph.add_edge(self.current, join_block);
+                    }
+                    None => self.graph.add_edge(condition_block, join_block),
+                }
+
+                self.current = join_block;
+            }
+            HuleStatement::WhileLoop(while_loop) => {
+                let condition_block = self.graph.add_block();
+                self.graph.add_edge(self.current, condition_block);
+                self.current = condition_block;
+                self.push(statement);
+
+                let body_block = self.graph.add_block();
+                let exit_block = self.graph.add_block();
+                self.graph.add_edge(condition_block, body_block);
+                if self.constants.evaluate(&while_loop.condition) != Ok(ConstValue::Boolean(true)) {
+                    self.graph.add_edge(condition_block, exit_block);
+                }
+
+                self.loop_exits.push(exit_block);
+                self.current = body_block;
+                self.build_statement(&while_loop.body);
+                self.graph.add_edge(self.current, condition_block);
+                self.loop_exits.pop();
+
+                self.current = exit_block;
+            }
+            HuleStatement::Return(_) => {
+                self.push(statement);
+                self.jump(self.return_block);
+            }
+            HuleStatement::Break => {
+                self.push(statement);
+                match self.loop_exits.last() {
+                    Some(exit_block) => self.jump(*exit_block),
+                    None => self.has_break_outside_loop = true,
+                }
+            }
+            HuleStatement::VariableDecl(decl) => {
+                self.push(statement);
+                // locals hide constants of the same name
+                match decl.value.as_ref().filter(|_| decl.is_const).map(|value| self.constants.evaluate(value)) {
+                    Some(Ok(value)) => self.constants.define(&decl.name, value),
+                    _ => self.constants.undefine(&decl.name),
+                }
+            }
+            _ => self.push(statement),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str, test: impl Fn(&FunctionFlowGraph)) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let func = ast.functions().next().unwrap();
+        test(&FunctionFlowGraph::from_function(func, &ConstEvaluator::new()));
+    }
+
+    #[test]
+    fn test_return_in_both_branches() {
+        prepare_test("int f(int a) { if (a == 1) { return 1; } else { return 2; } }", |flow| {
+            assert!(!flow.can_fall_through());
+            assert!(flow.unreachable_statements().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_return_in_one_branch() {
+        prepare_test("int f(int a) { if (a == 1) { return 1; } }", |flow| {
+            assert!(flow.can_fall_through());
+        });
+    }
+
+    #[test]
+    fn test_statements_after_return() {
+        prepare_test("int f(int a) { return a; a = 1; f(a); }", |flow| {
+            assert_eq!(flow.unreachable_statements().len(), 1);
+            assert_eq!(flow.unreachable_statements()[0].summary(), "assignment to 'a'");
+        });
+    }
+
+    #[test]
+    fn test_statements_after_break() {
+        prepare_test("entry { while (1 == 1) { break; OUT1.Data = \"x\"; } }", |flow| {
+            assert_eq!(flow.unreachable_statements().len(), 1);
+            assert!(flow.can_fall_through());
+        });
+    }
+
+    #[test]
+    fn test_constant_true_loop() {
+        prepare_test("int f() { while (true) { return 1; } }", |flow| {
+            assert!(!flow.can_fall_through());
+        });
+        prepare_test("int f() { const bool RUN = true; while (RUN) { if (RUN) { break; } } }", |flow| {
+            assert!(flow.can_fall_through());
+        });
+    }
+
+    #[test]
+    fn test_dominators() {
+        // 0 -> 1 -> 3, 0 -> 2 -> 3
+        let mut graph: ControlFlowGraph<()> = ControlFlowGraph::new();
+        for _ in 0..3 {
+            graph.add_block();
+        }
+        graph.add_edge(0, 1);
+        graph.add_edge(0, 2);
+        graph.add_edge(1, 3);
+        graph.add_edge(2, 3);
+
+        let dominators = graph.dominators();
+        assert!(dominators[3][0]);
+        assert!(!dominators[3][1]);
+        assert!(dominators[1][1]);
+        assert!(!dominators[0][1]);
+    }
+}
@@ -0,0 +1,255 @@
+//! hyperULE Compiler
+//! Command line options
+//!
+//! hyperULECompiler <input file> [-o <output file>] [--emit=<kind>] [-Werror]
+//!                  [-O0|-O1|-O2|-O3] [-Cpass=+<pass>,-<pass>] [--print-after=<pass>]
+//!                  [--verbose] [--size-report] [--max-size <bytes>] [--minify]
+//!                  [--frame-width <digits>] [--heap-size <bytes>] [--heap-error <field>]
+//!                  [--ule-style=pretty|compact] [--source-map <file>]
+//! hyperULECompiler map <file.map> <line>[:<column>]
+
+use crate::optimizer::{OptimizerLevel, PassOverride};
+use crate::ule::{DEFAULT_HEAP_ERROR_FIELD, MAX_FRAME_WIDTH, MAX_HEAP_SIZE};
+use crate::writer::UleStyle;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EmitKind {
+    Ast,
+    CallGraph,
+    Ir,
+    Ule,
+}
+
+impl EmitKind {
+    fn from_name(name: &str) -> Option<EmitKind> {
+        match name {
+            "ast" => Some(EmitKind::Ast),
+            "callgraph" => Some(EmitKind::CallGraph),
+            "ir" => Some(EmitKind::Ir),
+            "ule" => Some(EmitKind::Ule),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    MissingValue(String),
+    UnknownOption(String),
+    UnknownEmitKind(String),
+    UnknownUleStyle(String),
+    InvalidPassOverride(String),
+    InvalidNumber(String, String),
+    NumberOutOfRange { option: String, value: usize, min: usize, max: usize },
+    InvalidField(String, String),
+}
+
+impl CliError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            CliError::MissingValue(option) => format!("Option '{}' requires a value.", option),
+            CliError::UnknownOption(option) => format!("Unknown option '{}'.", option),
+            CliError::UnknownEmitKind(kind)
+            => format!("Unknown emit kind '{}', expected one of: ast, callgraph, ir, ule.", kind),
+            CliError::UnknownUleStyle(style)
+            => format!("Unknown ULE style '{}', expected pretty or compact.", style),
+            CliError::InvalidPassOverride(value)
+            => format!("Invalid pass override '{}', expected '+<pass>' or '-<pass>'.", value),
+            CliError::InvalidNumber(option, value)
+            => format!("Option '{}' expects a number, got '{}'.", option, value),
+            CliError::NumberOutOfRange { option, value, min, max }
+            => format!("Option '{}' expects a number from {} to {}, got {}.", option, min, max, value),
+            CliError::InvalidField(option, value)
+            => format!("Option '{}' expects a device field like 'OUT1.Data', got '{}'.", option, value),
+        }
+    }
+}
+
+/// Lookup of a position of the generated ULE code in a source map
+pub struct MapLookup {
+    pub map: String,
+    pub line: usize,
+    pub column: Option<usize>,
+}
+
+pub struct CompilerOptions {
+    pub input: Option<String>,
+    pub output: Option<String>,
+    pub emit: EmitKind,
+    pub warnings_as_errors: bool,
+    pub optimizer_level: OptimizerLevel,
+    pub pass_overrides: Vec<PassOverride>,
+    pub print_after: Vec<String>,
+    pub verbose: bool,
+    pub size_report: bool,
+    pub max_size: Option<usize>,
+    pub minify: bool,
+    pub frame_width: Option<usize>,
+    pub heap_size: Option<usize>,
+    pub heap_error: String,
+    pub ule_style: UleStyle,
+    pub source_map: Option<String>,
+    pub lookup: Option<MapLookup>,
+}
+
+impl CompilerOptions {
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<CompilerOptions, CliError> {
+        let mut options = CompilerOptions {
+            input: None,
+            output: None,
+            emit: EmitKind::Ule,
+            warnings_as_errors: false,
+            optimizer_level: OptimizerLevel::O0,
+            pass_overrides: vec![],
+            print_after: vec![],
+            verbose: false,
+            size_report: false,
+            max_size: None,
+            minify: false,
+            frame_width: None,
+            heap_size: None,
+            heap_error: DEFAULT_HEAP_ERROR_FIELD.to_string(),
+            ule_style: UleStyle::Pretty,
+            source_map: None,
+            lookup: None,
+        };
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-i" => options.input = Some(option_value(&mut args, &arg)?),
+                "-o" => options.output = Some(option_value(&mut args, &arg)?),
+                "-Werror" => options.warnings_as_errors = true,
+                "--verbose" => options.verbose = true,
+                "--size-report" => options.size_report = true,
+                "--minify" => options.minify = true,
+                "--max-size" => {
+                    let value = option_value(&mut args, &arg)?;
+                    options.max_size = Some(value.parse().map_err(|_| CliError::InvalidNumber(arg, value))?);
+                }
+                "--frame-width" => {
+                    let value = option_value(&mut args, &arg)?;
+                    let width = value.parse().map_err(|_| CliError::InvalidNumber(arg.clone(), value))?;
+                    if !(1..=MAX_FRAME_WIDTH).contains(&width) {
+                        return Err(CliError::NumberOutOfRange { option: arg, value: width, min: 1, max: MAX_FRAME_WIDTH });
+                    }
+                    options.frame_width = Some(width);
+                }
+                "--source-map" => options.source_map = Some(option_value(&mut args, &arg)?),
+                "map" if options.input.is_none() && options.lookup.is_none() => {
+                    let map = option_value(&mut args, &arg)?;
+                    let position = option_value(&mut args, &arg)?;
+                    options.lookup = Some(parse_lookup(map, &arg, position)?);
+                }
+                "--heap-size" => {
+                    let value = option_value(&mut args, &arg)?;
+                    let size = value.parse().map_err(|_| CliError::InvalidNumber(arg.clone(), value))?;
+                    if !(1..=MAX_HEAP_SIZE).contains(&size) {
+                        return Err(CliError::NumberOutOfRange { option: arg, value: size, min: 1, max: MAX_HEAP_SIZE });
+                    }
+                    options.heap_size = Some(size);
+                }
+                "--heap-error" => {
+                    let field = option_value(&mut args, &arg)?;
+                    // fields of the device are the only names with a dot
+                    if !field.contains('.') {
+                        return Err(CliError::InvalidField(arg, field));
+                    }
+                    options.heap_error = field;
+                }
+                _ => {
+                    if let Some(kind) = arg.strip_prefix("--emit=") {
+                        options.emit = EmitKind::from_name(kind)
+                            .ok_or_else(|| CliError::UnknownEmitKind(kind.to_string()))?;
+                    } else if let Some(style) = arg.strip_prefix("--ule-style=") {
+                        options.ule_style = UleStyle::from_name(style)
+                            .ok_or_else(|| CliError::UnknownUleStyle(style.to_string()))?;
+                    } else if let Some(level) = arg.strip_prefix("-O").and_then(OptimizerLevel::from_name) {
+                        options.optimizer_level = level;
+                    } else if let Some(passes) = arg.strip_prefix("-Cpass=") {
+                        for value in passes.split(',') {
+                            options.pass_overrides.push(parse_pass_override(value)?);
+                        }
+                    } else if let Some(pass) = arg.strip_prefix("--print-after=") {
+                        options.print_after.push(pass.to_string());
+                    } else if arg.starts_with('-') {
+                        return Err(CliError::UnknownOption(arg));
+                    } else {
+                        options.input = Some(arg);
+                    }
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+/// Value of the option, which is never another option. A value like
+/// `--ule-style=pretty` means the value itself is missing.
+fn option_value<I: Iterator<Item = String>>(args: &mut I, option: &str) -> Result<String, CliError> {
+    args.next()
+        .filter(|value| !value.starts_with("--"))
+        .ok_or_else(|| CliError::MissingValue(option.to_string()))
+}
+
+/// `<line>` or `<line>:<column>`
+fn parse_lookup(map: String, option: &str, position: String) -> Result<MapLookup, CliError> {
+    let invalid = || CliError::InvalidNumber(option.to_string(), position.clone());
+    let (line, column) = match position.split_once(':') {
+        Some((line, column)) => (line, Some(column.parse().map_err(|_| invalid())?)),
+        None => (position.as_str(), None),
+    };
+    Ok(MapLookup { map, line: line.parse().map_err(|_| invalid())?, column })
+}
+
+fn parse_pass_override(value: &str) -> Result<PassOverride, CliError> {
+    match (value.strip_prefix('+'), value.strip_prefix('-')) {
+        (Some(name), _) if !name.is_empty() => Ok(PassOverride::Enable(name.to_string())),
+        (_, Some(name)) if !name.is_empty() => Ok(PassOverride::Disable(name.to_string())),
+        _ => Err(CliError::InvalidPassOverride(value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepare_test(args: &[&str]) -> Result<CompilerOptions, CliError> {
+        CompilerOptions::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_heap_options() {
+        assert_eq!(prepare_test(&["main.hule"]).unwrap().heap_error, "OUT1.Data");
+
+        let options = prepare_test(&["main.hule", "--heap-size", "2147483647", "--heap-error", "OUT2.Data"]).unwrap();
+        assert_eq!(options.heap_size, Some(MAX_HEAP_SIZE));
+        assert_eq!(options.heap_error, "OUT2.Data");
+
+        let out_of_range = |value: usize| CliError::NumberOutOfRange { option: "--heap-size".to_string(), value, min: 1, max: MAX_HEAP_SIZE };
+        assert_eq!(prepare_test(&["--heap-size", "0"]).err(), Some(out_of_range(0)));
+        assert_eq!(prepare_test(&["--heap-size", "2147483648"]).err(), Some(out_of_range(2147483648)));
+        assert_eq!(prepare_test(&["--heap-error", "hx"]).err(), Some(CliError::InvalidField("--heap-error".to_string(), "hx".to_string())));
+    }
+
+    #[test]
+    fn test_option_values() {
+        let options = prepare_test(&["main.hule", "--source-map", "main.map", "--ule-style=compact"]).unwrap();
+        assert_eq!(options.source_map, Some("main.map".to_string()));
+        assert_eq!(options.ule_style, UleStyle::Compact);
+
+        assert_eq!(prepare_test(&["main.hule", "--source-map", "--ule-style=pretty"]).err(),
+            Some(CliError::MissingValue("--source-map".to_string())));
+        assert_eq!(prepare_test(&["main.hule", "-o", "--minify"]).err(), Some(CliError::MissingValue("-o".to_string())));
+        assert_eq!(prepare_test(&["main.hule", "--heap-size"]).err(), Some(CliError::MissingValue("--heap-size".to_string())));
+    }
+
+    #[test]
+    fn test_frame_width_option() {
+        assert_eq!(prepare_test(&["main.hule", "--frame-width", "10"]).unwrap().frame_width, Some(MAX_FRAME_WIDTH));
+
+        let out_of_range = |value: usize| CliError::NumberOutOfRange { option: "--frame-width".to_string(), value, min: 1, max: MAX_FRAME_WIDTH };
+        assert_eq!(prepare_test(&["--frame-width", "0"]).err(), Some(out_of_range(0)));
+        assert_eq!(prepare_test(&["--frame-width", "40"]).err(), Some(out_of_range(40)));
+    }
+}
@@ -0,0 +1,183 @@
+//! hyperULE String Concatenation Simplifier
+//!
+//! Code generation and inlining leave behind chains like
+//! `name + "" + "Hello " + "World"`. Every operand costs output size, so
+//! string concatenation chains are flattened and
+//! - `""` operands are dropped,
+//! - adjacent string literals are merged and
+//! - consecutive appends to the same variable like `x = x + a; x = x + b;`
+//!   are collapsed into a single `x = x + a + b;`. Device fields may react
+//!   to being written, so every write to them is kept.
+
+use crate::ast::Operator;
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct ConcatSimplificationPass;
+
+impl OptimizerPass for ConcatSimplificationPass {
+    fn name(&self) -> &'static str {
+        "concat"
+    }
+
+    fn run(&self, program: &mut HuleProgram, _notes: &mut Vec<String>) {
+        for id in program.function_ids().collect::<Vec<_>>() {
+            let mut blocks = std::mem::take(&mut program.function_mut(id).blocks);
+            for block in &mut blocks {
+                for expression in block.expressions_mut() {
+                    *expression = simplify(program, expression);
+                }
+                collapse_appends(program, block);
+            }
+            program.function_mut(id).blocks = blocks;
+        }
+    }
+}
+
+/// Operands of a chain of string concatenations, in order.
+fn flatten(program: &HuleProgram, expression: &IrExpression, operands: &mut Vec<IrExpression>) {
+    match expression {
+        IrExpression::Binary { left, operator: Operator::Plus, right }
+        if program.expression_type(left) == HuleType::String => {
+            flatten(program, left, operands);
+            flatten(program, right, operands);
+        }
+        _ => operands.push(expression.clone()),
+    }
+}
+
+fn concatenate(operands: Vec<IrExpression>) -> IrExpression {
+    operands.into_iter()
+        .reduce(|left, right| IrExpression::Binary {
+            left: Box::new(left),
+            operator: Operator::Plus,
+            right: Box::new(right),
+        })
+        .unwrap_or_else(|| IrExpression::String(String::new()))
+}
+
+/// Removes empty string literals and merges adjacent ones. At least one
+/// operand has to stay, so the chain stays a string.
+fn simplify_operands(operands: Vec<IrExpression>) -> Vec<IrExpression> {
+    let mut result: Vec<IrExpression> = vec![];
+    for operand in operands {
+        match (result.last_mut(), operand) {
+            (_, IrExpression::String(value)) if value.is_empty() => {}
+            (Some(IrExpression::String(previous)), IrExpression::String(value)) => previous.push_str(&value),
+            (_, operand) => result.push(operand),
+        }
+    }
+
+    result
+}
+
+pub fn simplify(program: &HuleProgram, expression: &IrExpression) -> IrExpression {
+    match expression {
+        IrExpression::Binary { operator: Operator::Plus, .. }
+        if program.expression_type(expression) == HuleType::String => {
+            let mut operands = vec![];
+            flatten(program, expression, &mut operands);
+            let operands = operands.iter().map(|operand| simplify(program, operand)).collect();
+            concatenate(simplify_operands(operands))
+        }
+        IrExpression::Binary { left, operator, right } => IrExpression::Binary {
+            left: Box::new(simplify(program, left)),
+            operator: operator.clone(),
+            right: Box::new(simplify(program, right)),
+        },
+        IrExpression::Builtin(name, arguments) => IrExpression::Builtin(
+            name.clone(),
+            arguments.iter().map(|argument| simplify(program, argument)).collect(),
+        ),
+        IrExpression::Call(function, arguments) => IrExpression::Call(
+            *function,
+            arguments.iter().map(|argument| simplify(program, argument)).collect(),
+        ),
+        _ => expression.clone(),
+    }
+}
+
+/// Collapses `x = a; x = x + b;` into `x = a + b;` if `x` is a string
+/// variable and the second value reads `x` only as its first operand.
+fn collapse_appends(program: &HuleProgram, block: &mut BasicBlock) {
+    let mut result: Vec<IrInstruction> = vec![];
+
+    for instruction in block.instructions.drain(..) {
+        let previous = result.iter_mut().rev().find(|previous| !previous.is_location());
+        if let (Some(IrInstruction::Assign(previous_target, previous_value)), IrInstruction::Assign(target, value))
+            = (previous, &instruction) {
+            let mut operands = vec![];
+            flatten(program, value, &mut operands);
+
+            let appends_to_target = previous_target == target
+                && matches!(target, IrTarget::Variable(_))
+                && operands.len() > 1
+                && operands[0].reads_target(target)
+                && !operands[1..].iter().any(|operand| operand.reads_target(target));
+            if appends_to_target && program.expression_type(previous_value) == HuleType::String {
+                let mut merged = vec![];
+                flatten(program, previous_value, &mut merged);
+                merged.extend(operands.into_iter().skip(1));
+                *previous_value = concatenate(simplify_operands(merged));
+                continue;
+            }
+        }
+
+        result.push(instruction);
+    }
+
+    block.instructions = result;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> HuleProgram {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        ConcatSimplificationPass.run(&mut program, &mut vec![]);
+        program
+    }
+
+    fn instruction_text(program: &HuleProgram) -> Vec<String> {
+        let text = program.to_ir_text();
+        text.lines()
+            .skip_while(|line| !line.starts_with("  bb0:"))
+            .skip(1)
+            .take_while(|line| !line.trim().starts_with("return"))
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_empty_and_adjacent_literals() {
+        let program = prepare_test("entry { OUT1.Data = OUT1.Data + \"\" + \"a\" + \"b\" + IntToStr(1 + 2); }");
+
+        assert_eq!(instruction_text(&program), vec!["OUT1.Data = ((OUT1.Data + \"ab\") + IntToStr((1 + 2)))"]);
+    }
+
+    #[test]
+    fn test_collapse_appends() {
+        let program = prepare_test("entry { string x = \"a\"; x = x + OUT1.Data; x = x + \"b\"; OUT1.Data = x; }");
+
+        assert_eq!(instruction_text(&program), vec!["%x = ((\"a\" + OUT1.Data) + \"b\")", "OUT1.Data = %x"]);
+    }
+
+    #[test]
+    fn test_no_collapse_of_device_fields() {
+        let program = prepare_test("entry { OUT1.Data = OUT1.Data + \"a\"; OUT1.Data = OUT1.Data + \"b\"; }");
+
+        assert_eq!(instruction_text(&program), vec!["OUT1.Data = (OUT1.Data + \"a\")", "OUT1.Data = (OUT1.Data + \"b\")"]);
+    }
+
+    #[test]
+    fn test_no_collapse_when_target_is_read_again() {
+        let program = prepare_test("entry { string x = \"a\"; x = x + x; OUT1.Data = x; }");
+
+        assert_eq!(instruction_text(&program).len(), 3);
+    }
+}
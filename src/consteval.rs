@@ -0,0 +1,214 @@
+//! hyperULE Constant Evaluator
+//!
+//! Evaluates `HuleExpression`s at compile time. Supports integer arithmetic,
+//! string concatenation, comparisons and logical operators on literals and
+//! `const` declarations.
+
+use std::collections::HashMap;
+use crate::ast::{HuleExpression, Operator};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstValue {
+    Integer(i32),
+    String(String),
+    Boolean(bool),
+}
+
+impl ConstValue {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ConstValue::Integer(_) => "int",
+            ConstValue::String(_) => "string",
+            ConstValue::Boolean(_) => "bool",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConstEvalError {
+    NotConstant(String),
+    DivisionByZero,
+    Overflow,
+    InvalidOperands(&'static str, Operator, &'static str),
+}
+
+impl ConstEvalError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            ConstEvalError::NotConstant(expression)
+            => format!("'{}' is not a constant expression.", expression),
+            ConstEvalError::DivisionByZero => "Division by zero.".to_string(),
+            ConstEvalError::Overflow => "Integer overflow.".to_string(),
+            ConstEvalError::InvalidOperands(left, operator, right)
+            => format!("Operator '{}' can not be applied to '{}' and '{}'.", operator.symbol(), left, right),
+        }
+    }
+
+    /// Errors which make an expression invalid even when it is not
+    /// required to be constant.
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ConstEvalError::DivisionByZero | ConstEvalError::Overflow)
+    }
+}
+
+#[derive(Clone)]
+pub struct ConstEvaluator {
+    constants: HashMap<String, ConstValue>,
+}
+
+impl ConstEvaluator {
+    pub fn new() -> ConstEvaluator {
+        ConstEvaluator {
+            constants: HashMap::new(),
+        }
+    }
+
+    pub fn define(&mut self, name: &str, value: ConstValue) {
+        self.constants.insert(name.to_string(), value);
+    }
+
+    pub fn undefine(&mut self, name: &str) {
+        self.constants.remove(name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ConstValue> {
+        self.constants.get(name)
+    }
+
+    pub fn evaluate(&self, expression: &HuleExpression) -> Result<ConstValue, ConstEvalError> {
+        match expression {
+            HuleExpression::Integer(value) => Ok(ConstValue::Integer(*value)),
+            HuleExpression::String(value) => Ok(ConstValue::String(value.clone())),
+            HuleExpression::Boolean(value) => Ok(ConstValue::Boolean(*value)),
+            HuleExpression::Bracketed(inner) => self.evaluate(inner),
+            HuleExpression::Identifier(name) => self.constants.get(name)
+                .cloned()
+                .ok_or_else(|| ConstEvalError::NotConstant(name.clone())),
+            HuleExpression::Call(name, _) => Err(ConstEvalError::NotConstant(format!("{}(...)", name))),
+            HuleExpression::Undefined => Err(ConstEvalError::NotConstant("undefined".to_string())),
+            HuleExpression::Binary { left, operator, right } => {
+                let left = self.evaluate(left)?;
+                let right = self.evaluate(right)?;
+                evaluate_binary(left, operator, right)
+            }
+        }
+    }
+
+    /// Finds division by zero or overflow in any constant part of the
+    /// expression, even if the expression as a whole is not constant.
+    pub fn find_error(&self, expression: &HuleExpression) -> Option<ConstEvalError> {
+        match expression {
+            HuleExpression::Bracketed(inner) => self.find_error(inner),
+            HuleExpression::Call(_, parameters) => parameters.iter()
+                .find_map(|parameter| self.find_error(parameter)),
+            HuleExpression::Binary { left, right, .. } => self.find_error(left)
+                .or_else(|| self.find_error(right))
+                .or_else(|| self.evaluate(expression).err().filter(|err| err.is_fatal())),
+            _ => None,
+        }
+    }
+}
+
+pub fn evaluate_binary(left: ConstValue, operator: &Operator, right: ConstValue) -> Result<ConstValue, ConstEvalError> {
+    match (&left, &right) {
+        (ConstValue::Integer(l), ConstValue::Integer(r)) => {
+            let (l, r) = (*l, *r);
+            match operator {
+                Operator::Plus => l.checked_add(r).map(ConstValue::Integer).ok_or(ConstEvalError::Overflow),
+                Operator::Minus => l.checked_sub(r).map(ConstValue::Integer).ok_or(ConstEvalError::Overflow),
+                Operator::Multiply => l.checked_mul(r).map(ConstValue::Integer).ok_or(ConstEvalError::Overflow),
+                Operator::Divide => {
+                    if r == 0 {
+                        return Err(ConstEvalError::DivisionByZero);
+                    }
+                    l.checked_div(r).map(ConstValue::Integer).ok_or(ConstEvalError::Overflow)
+                }
+                Operator::Equal => Ok(ConstValue::Boolean(l == r)),
+                Operator::NotEqual => Ok(ConstValue::Boolean(l != r)),
+                Operator::GreaterThan => Ok(ConstValue::Boolean(l > r)),
+                Operator::GreaterEqualThan => Ok(ConstValue::Boolean(l >= r)),
+                Operator::LowerThan => Ok(ConstValue::Boolean(l < r)),
+                Operator::LowerEqualThan => Ok(ConstValue::Boolean(l <= r)),
+                Operator::And | Operator::Or => Err(invalid_operands(&left, operator, &right)),
+            }
+        }
+        (ConstValue::String(l), ConstValue::String(r)) => match operator {
+            Operator::Plus => Ok(ConstValue::String(format!("{}{}", l, r))),
+            Operator::Equal => Ok(ConstValue::Boolean(l == r)),
+            Operator::NotEqual => Ok(ConstValue::Boolean(l != r)),
+            _ => Err(invalid_operands(&left, operator, &right)),
+        },
+        (ConstValue::Boolean(l), ConstValue::Boolean(r)) => match operator {
+            Operator::And => Ok(ConstValue::Boolean(*l && *r)),
+            Operator::Or => Ok(ConstValue::Boolean(*l || *r)),
+            Operator::Equal => Ok(ConstValue::Boolean(l == r)),
+            Operator::NotEqual => Ok(ConstValue::Boolean(l != r)),
+            _ => Err(invalid_operands(&left, operator, &right)),
+        },
+        _ => Err(invalid_operands(&left, operator, &right)),
+    }
+}
+
+fn invalid_operands(left: &ConstValue, operator: &Operator, right: &ConstValue) -> ConstEvalError {
+    ConstEvalError::InvalidOperands(left.type_name(), operator.clone(), right.type_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::HuleStatement;
+    use crate::parser::{AstParser, AstParserError};
+
+    fn prepare_test(input: &str) -> Result<ConstValue, ConstEvalError> {
+        let source = format!("const int test = {};", input);
+        let mut parser = AstParser::new(&source);
+        let ast = parser.parse(&source).unwrap();
+
+        let mut evaluator = ConstEvaluator::new();
+        evaluator.define("MAX", ConstValue::Integer(20));
+
+        match &ast.body.items[0] {
+            HuleStatement::VariableDecl(decl) => evaluator.evaluate(decl.value.as_ref().unwrap()),
+            _ => panic!("declaration expected"),
+        }
+    }
+
+    #[test]
+    fn test_integer_precedence() {
+        assert_eq!(prepare_test("2 + 3 * 4"), Ok(ConstValue::Integer(14)));
+        assert_eq!(prepare_test("(2 + 3) * 4"), Ok(ConstValue::Integer(20)));
+        assert_eq!(prepare_test("10 - 4 - 3"), Ok(ConstValue::Integer(3)));
+        assert_eq!(prepare_test("MAX / 2 - -1"), Ok(ConstValue::Integer(11)));
+    }
+
+    #[test]
+    fn test_string_concatenation() {
+        assert_eq!(prepare_test("\"Hello \" + \"World\""), Ok(ConstValue::String("Hello World".to_string())));
+    }
+
+    #[test]
+    fn test_comparison() {
+        assert_eq!(prepare_test("MAX > 10 && 1 == 1"), Ok(ConstValue::Boolean(true)));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(prepare_test("MAX / 0"), Err(ConstEvalError::DivisionByZero));
+        assert_eq!(prepare_test("2147483647 + 1"), Err(ConstEvalError::Overflow));
+        assert!(matches!(prepare_test("\"a\" + 1"), Err(ConstEvalError::InvalidOperands(..))));
+        assert!(matches!(prepare_test("other + 1"), Err(ConstEvalError::NotConstant(_))));
+    }
+
+    #[test]
+    fn test_literal_range() {
+        assert_eq!(prepare_test("2147483647"), Ok(ConstValue::Integer(2147483647)));
+        assert_eq!(prepare_test("-2147483648"), Ok(ConstValue::Integer(i32::MIN)));
+        assert_eq!(prepare_test("- -2147483648"), Err(ConstEvalError::Overflow));
+
+        for source in ["const int test = 2147483648;", "entry { OUT1.Data = IntToStr(2147483648); }"] {
+            let mut parser = AstParser::new(source);
+            assert_eq!(parser.parse(source).err(),
+                Some(AstParserError::InvalidInteger("2147483648".to_string(), ConstEvalError::Overflow)));
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! hyperULE Constant Folding
+//!
+//! Evaluates integer, string and boolean operations on literals at compile
+//! time and propagates constants into the places they are read:
+//! - `const` globals and globals which are never assigned,
+//! - locals which are assigned a literal exactly once, if the assignment
+//!   dominates every read.
+//!
+//! Branches on a constant condition become jumps, so the code of the branch
+//! which is never taken is dropped.
+
+use std::collections::HashMap;
+use crate::ast::Operator;
+use crate::cfg::FlowGraph;
+use crate::consteval::{evaluate_binary, ConstValue};
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct ConstantFoldingPass;
+
+impl OptimizerPass for ConstantFoldingPass {
+    fn name(&self) -> &'static str {
+        "const-fold"
+    }
+
+    fn run(&self, program: &mut HuleProgram, _notes: &mut Vec<String>) {
+        for global in program.globals().to_vec() {
+            if let Some(initializer) = &mut program.variable_mut(global).initializer {
+                *initializer = fold_expression(initializer);
+            }
+        }
+
+        let globals = constant_globals(program);
+        for id in program.function_ids().collect::<Vec<_>>() {
+            let function = program.function_mut(id);
+            loop {
+                let mut constants = globals.clone();
+                constants.extend(constant_locals(function));
+                if !fold_function(function, &constants) {
+                    break;
+                }
+            }
+            function.remove_unreachable_blocks();
+        }
+    }
+}
+
+/// Globals whose value is known at compile time.
+fn constant_globals(program: &HuleProgram) -> HashMap<VarId, IrExpression> {
+    let mut assigned = vec![];
+    for function in program.functions() {
+        for block in &function.blocks {
+            assigned.extend(block.defined_variables());
+        }
+    }
+
+    program.globals().iter()
+        .filter_map(|global| {
+            let variable = program.variable(*global);
+            let value = match &variable.constant {
+                Some(constant) => IrExpression::from_constant(constant.clone()),
+                None => variable.initializer.clone().filter(|value| value.as_constant().is_some())?,
+            };
+
+            if assigned.contains(global) {
+                return None;
+            }
+            Some((*global, value))
+        })
+        .collect()
+}
+
+/// Locals which are assigned a literal once, before any of their reads.
+fn constant_locals(function: &HuleFunction) -> HashMap<VarId, IrExpression> {
+    // position of every write as (block, instruction), the result of a call
+    // is written after all instructions of its block
+    let mut definitions: HashMap<VarId, Vec<(usize, usize, Option<IrExpression>)>> = HashMap::new();
+    for (index, block) in function.blocks.iter().enumerate() {
+        for (position, instruction) in block.instructions.iter().enumerate() {
+            if let IrInstruction::Assign(IrTarget::Variable(variable), value) = instruction {
+                definitions.entry(*variable).or_default().push((index, position, value.as_constant().map(|_| value.clone())));
+            }
+        }
+        if let Terminator::Call { result: Some(IrTarget::Variable(variable)), .. } = &block.terminator {
+            definitions.entry(*variable).or_default().push((index, block.instructions.len(), None));
+        }
+    }
+
+    let dominators = function.dominators();
+    let mut constants = HashMap::new();
+    for local in &function.locals {
+        let (block, position, value) = match definitions.get(local).map(|d| d.as_slice()) {
+            Some([(block, position, Some(value))]) => (*block, *position, value),
+            _ => continue,
+        };
+
+        let mut dominates_reads = true;
+        for (index, other) in function.blocks.iter().enumerate() {
+            let reads = expression_positions(other).into_iter()
+                .filter(|(_, expression)| {
+                    let mut reads_local = false;
+                    expression.visit_variables(&mut |variable| reads_local |= variable == *local);
+                    reads_local
+                });
+
+            for (read_position, _) in reads {
+                let dominated = if index == block { read_position > position } else { dominators[index][block] };
+                dominates_reads &= dominated;
+            }
+        }
+
+        if dominates_reads {
+            constants.insert(*local, value.clone());
+        }
+    }
+
+    constants
+}
+
+/// Every expression of the block with the index of the instruction it
+/// belongs to, terminator expressions come after all instructions.
+fn expression_positions(block: &BasicBlock) -> Vec<(usize, &IrExpression)> {
+    let mut result: Vec<(usize, &IrExpression)> = block.instructions.iter()
+        .enumerate()
+        .filter_map(|(position, instruction)| instruction.value().map(|value| (position, value)))
+        .collect();
+
+    let position = block.instructions.len();
+    match &block.terminator {
+        Terminator::Branch { condition, .. } => result.push((position, condition)),
+        Terminator::Call { arguments, .. } | Terminator::TailCall { arguments, .. } => result.extend(arguments.iter().map(|a| (position, a))),
+        Terminator::Return(Some(value)) => result.push((position, value)),
+        _ => {}
+    }
+
+    result
+}
+
+/// Propagates the constants into the function and folds every expression.
+/// Returns whether anything changed.
+fn fold_function(function: &mut HuleFunction, constants: &HashMap<VarId, IrExpression>) -> bool {
+    let mut changed = false;
+
+    for block in &mut function.blocks {
+        for expression in block.expressions_mut() {
+            let folded = fold_expression(&substitute(expression, constants));
+            if folded != *expression {
+                *expression = folded;
+                changed = true;
+            }
+        }
+
+        if let Terminator::Branch { condition: IrExpression::Boolean(condition), then_block, else_block } = block.terminator {
+            block.terminator = Terminator::Goto(if condition { then_block } else { else_block });
+            changed = true;
+        }
+    }
+
+    changed
+}
+
+fn substitute(expression: &IrExpression, constants: &HashMap<VarId, IrExpression>) -> IrExpression {
+    match expression {
+        IrExpression::Variable(variable) => constants.get(variable)
+            .cloned()
+            .unwrap_or_else(|| expression.clone()),
+        IrExpression::Call(function, arguments) => IrExpression::Call(
+            *function,
+            arguments.iter().map(|argument| substitute(argument, constants)).collect(),
+        ),
+        IrExpression::Builtin(name, arguments) => IrExpression::Builtin(
+            name.clone(),
+            arguments.iter().map(|argument| substitute(argument, constants)).collect(),
+        ),
+        IrExpression::Binary { left, operator, right } => IrExpression::Binary {
+            left: Box::new(substitute(left, constants)),
+            operator: operator.clone(),
+            right: Box::new(substitute(right, constants)),
+        },
+        _ => expression.clone(),
+    }
+}
+
+pub fn fold_expression(expression: &IrExpression) -> IrExpression {
+    match expression {
+        IrExpression::Binary { left, operator, right } => {
+            let left = fold_expression(left);
+            let right = fold_expression(right);
+
+            if let (Some(l), Some(r)) = (left.as_constant(), right.as_constant()) {
+                // errors like a division by zero are kept for the runtime
+                if let Ok(value) = evaluate_binary(l, operator, r) {
+                    return IrExpression::from_constant(value);
+                }
+            }
+
+            // expressions never have side effects, so operands can be dropped
+            match (operator, &left, &right) {
+                (Operator::And, IrExpression::Boolean(true), other)
+                | (Operator::And, other, IrExpression::Boolean(true))
+                | (Operator::Or, IrExpression::Boolean(false), other)
+                | (Operator::Or, other, IrExpression::Boolean(false)) => other.clone(),
+                (Operator::And, IrExpression::Boolean(false), _)
+                | (Operator::And, _, IrExpression::Boolean(false)) => IrExpression::Boolean(false),
+                (Operator::Or, IrExpression::Boolean(true), _)
+                | (Operator::Or, _, IrExpression::Boolean(true)) => IrExpression::Boolean(true),
+                _ => IrExpression::Binary {
+                    left: Box::new(left),
+                    operator: operator.clone(),
+                    right: Box::new(right),
+                },
+            }
+        }
+        IrExpression::Builtin(name, arguments) => {
+            let arguments: Vec<IrExpression> = arguments.iter().map(fold_expression).collect();
+            let constants: Vec<Option<ConstValue>> = arguments.iter().map(|a| a.as_constant()).collect();
+
+            match (name.as_str(), constants.as_slice()) {
+                ("IntToStr", [Some(ConstValue::Integer(value))]) => IrExpression::String(value.to_string()),
+                ("StrLen", [Some(ConstValue::String(value))]) => IrExpression::Integer(value.chars().count() as i32),
+                _ => IrExpression::Builtin(name.clone(), arguments),
+            }
+        }
+        IrExpression::Call(function, arguments) => IrExpression::Call(
+            *function,
+            arguments.iter().map(fold_expression).collect(),
+        ),
+        _ => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> HuleProgram {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        ConstantFoldingPass.run(&mut program, &mut vec![]);
+        program
+    }
+
+    fn assigned_value(program: &HuleProgram, block: usize) -> IrExpression {
+        match program.function(FunctionId(0)).blocks[block].instructions.last() {
+            Some(IrInstruction::Assign(IrTarget::External(_), value)) => value.clone(),
+            _ => panic!("assignment expected"),
+        }
+    }
+
+    #[test]
+    fn test_fold_and_propagate() {
+        let program = prepare_test("entry { int a = 2; int b = a * 3; OUT1.Data = IntToStr(b + 1) + \"!\"; }");
+
+        assert_eq!(assigned_value(&program, 0), IrExpression::String("7!".to_string()));
+    }
+
+    #[test]
+    fn test_constant_globals() {
+        let program = prepare_test("const int MAX = 4; string name = \"x\"; entry { OUT1.Data = name + IntToStr(MAX); }");
+
+        assert_eq!(assigned_value(&program, 0), IrExpression::String("x4".to_string()));
+    }
+
+    #[test]
+    fn test_constant_condition() {
+        let program = prepare_test("entry { if (1 == 1) { OUT1.Data = \"a\"; } else { OUT1.Data = \"b\"; } }");
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(entry.blocks.len(), 3);
+        assert_eq!(assigned_value(&program, 1), IrExpression::String("a".to_string()));
+    }
+
+    #[test]
+    fn test_no_propagation_of_reassigned_variables() {
+        let program = prepare_test("entry { int i = 0; while (i < 3) { i = i + 1; } OUT1.Data = IntToStr(i); }");
+        let entry = program.function(FunctionId(0));
+
+        assert!(matches!(entry.blocks[1].terminator, Terminator::Branch { .. }));
+    }
+}
@@ -0,0 +1,346 @@
+//! hyperULE Dataflow Analysis
+//!
+//! Reports reads of possibly uninitialized locals, unused locals and
+//! parameters and assignments whose value is never read.
+//!
+//! Every function body is first resolved into a small tree of `FlowOp`s in
+//! which local variables are identified by index instead of by name. The
+//! forward (definite assignment) and backward (liveness) passes then only
+//! have to deal with reads, writes and branches.
+
+use crate::analyzer::AnalyzerWarning;
+use crate::ast::{HuleExpression, HuleFuncDef, HuleStatement};
+
+enum FlowOp {
+    Use(usize),
+    Def(usize),
+    /// Exactly one of both operation lists is executed
+    Branch(Vec<FlowOp>, Vec<FlowOp>),
+    /// Operations executed zero or more times
+    Loop(Vec<FlowOp>),
+    Return,
+    Break,
+}
+
+struct Local {
+    name: String,
+    is_parameter: bool,
+    is_read: bool,
+}
+
+struct FunctionFlow {
+    locals: Vec<Local>,
+    scopes: Vec<Vec<usize>>,
+}
+
+impl FunctionFlow {
+    fn resolve(&self, name: &str) -> Option<usize> {
+        self.scopes.iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|index| self.locals[**index].name == name)
+            .copied()
+    }
+
+    fn declare(&mut self, name: &str, is_parameter: bool) -> usize {
+        self.locals.push(Local {
+            name: name.to_string(),
+            is_parameter,
+            is_read: false,
+        });
+
+        let index = self.locals.len() - 1;
+        self.scopes.last_mut().unwrap().push(index);
+        index
+    }
+
+    fn resolve_expression(&mut self, expression: &HuleExpression, ops: &mut Vec<FlowOp>) {
+        match expression {
+            HuleExpression::Identifier(name) => {
+                if let Some(index) = self.resolve(name) {
+                    self.locals[index].is_read = true;
+                    ops.push(FlowOp::Use(index));
+                }
+            }
+            HuleExpression::Bracketed(inner) => self.resolve_expression(inner, ops),
+            HuleExpression::Call(_, parameters) => {
+                for parameter in parameters {
+                    self.resolve_expression(parameter, ops);
+                }
+            }
+            HuleExpression::Binary { left, right, .. } => {
+                self.resolve_expression(left, ops);
+                self.resolve_expression(right, ops);
+            }
+            _ => {}
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &HuleStatement, ops: &mut Vec<FlowOp>) {
+        match statement {
+            HuleStatement::VariableDecl(decl) => {
+                if let Some(value) = &decl.value {
+                    self.resolve_expression(value, ops);
+                }
+
+                let index = self.declare(&decl.name, false);
+                if decl.value.is_some() {
+                    ops.push(FlowOp::Def(index));
+                }
+            }
+            HuleStatement::VariableDef(def) => {
+                self.resolve_expression(&def.value, ops);
+                if let Some(index) = self.resolve(&def.name) {
+                    ops.push(FlowOp::Def(index));
+                }
+            }
+            HuleStatement::IfStatement(if_statement) => {
+                self.resolve_expression(&if_statement.condition, ops);
+
+                let body_ops = self.resolve_scope(&if_statement.body);
+                let else_ops = match &if_statement.else_body {
+                    Some(else_body) => self.resolve_scope(else_body),
+                    None => vec![],
+                };
+                ops.push(FlowOp::Branch(body_ops, else_ops));
+            }
+            HuleStatement::WhileLoop(while_loop) => {
+                let mut loop_ops = vec![];
+                self.resolve_expression(&while_loop.condition, &mut loop_ops);
+
+                let mut body_ops = self.resolve_scope(&while_loop.body);
+                // the condition is evaluated again after every iteration
+                self.resolve_expression(&while_loop.condition, &mut body_ops);
+
+                ops.extend(loop_ops);
+                ops.push(FlowOp::Loop(body_ops));
+            }
+            HuleStatement::FunctionCall(call) => {
+                for parameter in &call.parameters {
+                    self.resolve_expression(parameter, ops);
+                }
+            }
+            HuleStatement::Body(body) => {
+                for item in &body.items {
+                    self.resolve_statement(item, ops);
+                }
+            }
+            HuleStatement::Return(value) => {
+                self.resolve_expression(value, ops);
+                ops.push(FlowOp::Return);
+            }
+            HuleStatement::Break => ops.push(FlowOp::Break),
+            _ => {}
+        }
+    }
+
+    fn resolve_scope(&mut self, statement: &HuleStatement) -> Vec<FlowOp> {
+        let mut ops = vec![];
+        self.scopes.push(vec![]);
+        self.resolve_statement(statement, &mut ops);
+        self.scopes.pop();
+        ops
+    }
+}
+
+/// Forward pass, reports reads of every local which is not assigned on all
+/// paths leading to it. Returns false if the operations never complete
+/// normally because they end in a `return` or `break`.
+fn check_definite_assignment(ops: &[FlowOp], assigned: &mut [bool], reported: &mut [bool]) -> bool {
+    for op in ops {
+        match op {
+            FlowOp::Use(index) => {
+                if !assigned[*index] {
+                    reported[*index] = true;
+                }
+            }
+            FlowOp::Def(index) => assigned[*index] = true,
+            FlowOp::Branch(then_ops, else_ops) => {
+                let mut then_assigned = assigned.to_vec();
+                let then_completes = check_definite_assignment(then_ops, &mut then_assigned, reported);
+                let mut else_assigned = assigned.to_vec();
+                let else_completes = check_definite_assignment(else_ops, &mut else_assigned, reported);
+
+                // a branch which does not complete does not constrain the
+                // state after the if statement
+                for index in 0..assigned.len() {
+                    assigned[index] = match (then_completes, else_completes) {
+                        (true, true) => then_assigned[index] && else_assigned[index],
+                        (true, false) => then_assigned[index],
+                        (false, true) => else_assigned[index],
+                        (false, false) => true,
+                    };
+                }
+
+                if !then_completes && !else_completes {
+                    return false;
+                }
+            }
+            FlowOp::Loop(body_ops) => {
+                let mut body_assigned = assigned.to_vec();
+                check_definite_assignment(body_ops, &mut body_assigned, reported);
+            }
+            FlowOp::Return | FlowOp::Break => return false,
+        }
+    }
+
+    true
+}
+
+/// Backward pass, marks every write whose value is not read on any path
+/// before the next write. `loop_exit` holds the live locals after the
+/// innermost loop, which is where a `break` continues.
+fn check_liveness(ops: &[FlowOp], live: &mut [bool], loop_exit: &[bool], dead_stores: &mut [bool]) {
+    for op in ops.iter().rev() {
+        match op {
+            FlowOp::Use(index) => live[*index] = true,
+            FlowOp::Def(index) => {
+                if !live[*index] {
+                    dead_stores[*index] = true;
+                }
+                live[*index] = false;
+            }
+            FlowOp::Branch(then_ops, else_ops) => {
+                let mut then_live = live.to_vec();
+                check_liveness(then_ops, &mut then_live, loop_exit, dead_stores);
+                let mut else_live = live.to_vec();
+                check_liveness(else_ops, &mut else_live, loop_exit, dead_stores);
+
+                for index in 0..live.len() {
+                    live[index] = then_live[index] || else_live[index];
+                }
+            }
+            FlowOp::Loop(body_ops) => {
+                // iterate until the live locals at the loop head are stable,
+                // only the last iteration reports dead stores
+                let exit_live = live.to_vec();
+                let mut head_live = live.to_vec();
+                loop {
+                    let mut body_live = head_live.clone();
+                    let mut ignored = vec![false; dead_stores.len()];
+                    check_liveness(body_ops, &mut body_live, &exit_live, &mut ignored);
+
+                    let next_head_live: Vec<bool> = (0..live.len())
+                        .map(|index| exit_live[index] || body_live[index])
+                        .collect();
+                    if next_head_live == head_live {
+                        break;
+                    }
+                    head_live = next_head_live;
+                }
+
+                let mut body_live = head_live.clone();
+                check_liveness(body_ops, &mut body_live, &exit_live, dead_stores);
+                live.copy_from_slice(&head_live);
+            }
+            FlowOp::Return => live.fill(false),
+            FlowOp::Break => live.copy_from_slice(loop_exit),
+        }
+    }
+}
+
+pub fn analyze_function(func: &HuleFuncDef) -> Vec<AnalyzerWarning> {
+    let mut flow = FunctionFlow {
+        locals: vec![],
+        scopes: vec![vec![]],
+    };
+
+    for parameter in &func.parameters {
+        flow.declare(&parameter.name, true);
+    }
+
+    let mut ops = vec![];
+    flow.resolve_statement(&func.body, &mut ops);
+
+    let count = flow.locals.len();
+    let mut assigned: Vec<bool> = flow.locals.iter().map(|l| l.is_parameter).collect();
+    let mut uninitialized = vec![false; count];
+    check_definite_assignment(&ops, &mut assigned, &mut uninitialized);
+
+    let mut live = vec![false; count];
+    let mut dead_stores = vec![false; count];
+    check_liveness(&ops, &mut live, &vec![false; count], &mut dead_stores);
+
+    let mut warnings = vec![];
+    for (index, local) in flow.locals.iter().enumerate() {
+        let function = func.name.clone();
+        let variable = local.name.clone();
+
+        if !local.is_read {
+            if local.is_parameter {
+                warnings.push(AnalyzerWarning::UnusedParameter { function, variable });
+            } else {
+                warnings.push(AnalyzerWarning::UnusedVariable { function, variable });
+            }
+        } else if uninitialized[index] {
+            warnings.push(AnalyzerWarning::UninitializedRead { function, variable });
+        } else if dead_stores[index] {
+            warnings.push(AnalyzerWarning::UnreadAssignment { function, variable });
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> Vec<AnalyzerWarning> {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        ast.functions().flat_map(analyze_function).collect()
+    }
+
+    #[test]
+    fn test_uninitialized_read() {
+        let warnings = prepare_test("entry { string s; OUT1.Data = s; }");
+
+        assert_eq!(warnings, vec![AnalyzerWarning::UninitializedRead {
+            function: "_entry".to_string(),
+            variable: "s".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_conditionally_initialized_read() {
+        let warnings = prepare_test("entry { string s; if (1 == 1) { s = \"a\"; } OUT1.Data = s; }");
+
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(warnings[0], AnalyzerWarning::UninitializedRead { .. }));
+    }
+
+    #[test]
+    fn test_unused_variable_and_parameter() {
+        let warnings = prepare_test("int f(int a) { int b = 1; return 2; } entry { f(1); }");
+
+        assert_eq!(warnings.len(), 2);
+        assert!(matches!(warnings[0], AnalyzerWarning::UnusedParameter { .. }));
+        assert!(matches!(warnings[1], AnalyzerWarning::UnusedVariable { .. }));
+    }
+
+    #[test]
+    fn test_assignment_read_in_next_iteration() {
+        let warnings = prepare_test("entry { int i = 0; while (i < 3) { i = i + 1; } }");
+
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_initialized_in_both_branches() {
+        let warnings = prepare_test("entry { string s; if (1 == 1) { s = \"a\"; } else { s = \"b\"; } OUT1.Data = s; }");
+
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_unread_assignment() {
+        let warnings = prepare_test("entry { string s = \"a\"; s = \"b\"; OUT1.Data = s; }");
+
+        assert_eq!(warnings, vec![AnalyzerWarning::UnreadAssignment {
+            function: "_entry".to_string(),
+            variable: "s".to_string(),
+        }]);
+    }
+}
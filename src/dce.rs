@@ -0,0 +1,195 @@
+//! hyperULE Dead Code Elimination
+//!
+//! Removes everything which can not influence the output of the program:
+//! - functions which are not reachable from `_entry` over the call graph,
+//! - globals which are never read,
+//! - blocks which can not be reached from the entry block of a function,
+//! - stores to locals whose value is never read and
+//! - locals which are no longer referenced.
+//!
+//! IR expressions never have side effects, calls of hyperULE functions are
+//! terminators, so removing an assignment never changes the behaviour.
+
+use std::collections::HashSet;
+use crate::callgraph::CallGraph;
+use crate::liveness::{tracked_variables, transfer_instruction, transfer_terminator, Liveness};
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct DeadCodeEliminationPass;
+
+impl OptimizerPass for DeadCodeEliminationPass {
+    fn name(&self) -> &'static str {
+        "dce"
+    }
+
+    fn run(&self, program: &mut HuleProgram, _notes: &mut Vec<String>) {
+        remove_unreachable_functions(program);
+        remove_unused_globals(program);
+
+        for id in program.function_ids().collect::<Vec<_>>() {
+            let function = program.function_mut(id);
+            function.remove_unreachable_blocks();
+            while remove_dead_stores(function) {}
+            remove_unused_locals(function);
+        }
+    }
+}
+
+fn remove_unreachable_functions(program: &mut HuleProgram) {
+    let reachable = CallGraph::from_program(program).reachable_from(FunctionId(0).0);
+    if reachable.contains(&false) {
+        program.retain_functions(&reachable);
+    }
+}
+
+fn remove_unused_globals(program: &mut HuleProgram) {
+    let mut read = HashSet::new();
+    let mut collect = |expression: &IrExpression| expression.visit_variables(&mut |variable| {
+        read.insert(variable);
+    });
+
+    for global in program.globals() {
+        if let Some(initializer) = &program.variable(*global).initializer {
+            collect(initializer);
+        }
+    }
+    for function in program.functions() {
+        for block in &function.blocks {
+            for expression in block.expressions() {
+                collect(expression);
+            }
+        }
+    }
+
+    let unused: HashSet<VarId> = program.globals().iter()
+        .copied()
+        .filter(|global| !read.contains(global))
+        .collect();
+    if unused.is_empty() {
+        return;
+    }
+
+    program.retain_globals(|global| !unused.contains(&global));
+    for id in program.function_ids().collect::<Vec<_>>() {
+        for block in &mut program.function_mut(id).blocks {
+            block.instructions.retain(|instruction| {
+                !matches!(instruction, IrInstruction::Assign(IrTarget::Variable(variable), _) if unused.contains(variable))
+            });
+            if let Terminator::Call { result, .. } = &mut block.terminator {
+                if matches!(result, Some(IrTarget::Variable(variable)) if unused.contains(variable)) {
+                    *result = None;
+                }
+            }
+        }
+    }
+}
+
+/// Removes assignments to locals which are not live afterwards. Returns
+/// whether anything was removed, which can make further stores dead.
+fn remove_dead_stores(function: &mut HuleFunction) -> bool {
+    let liveness = Liveness::compute(function);
+    let tracked = tracked_variables(function);
+    let is_dead = |target: &IrTarget, live: &HashSet<VarId>| {
+        matches!(target, IrTarget::Variable(variable) if tracked.contains(variable) && !live.contains(variable))
+    };
+
+    let mut changed = false;
+    for (index, block) in function.blocks.iter_mut().enumerate() {
+        let mut live = liveness.live_out[index].clone();
+
+        if let Terminator::Call { result, .. } = &mut block.terminator {
+            if result.as_ref().is_some_and(|target| is_dead(target, &live)) {
+                *result = None;
+                changed = true;
+            }
+        }
+        transfer_terminator(&block.terminator, &tracked, &mut live);
+
+        let mut kept = vec![];
+        for instruction in block.instructions.drain(..).rev() {
+            match &instruction {
+                IrInstruction::Assign(target, _) if is_dead(target, &live) => changed = true,
+                // evaluating a side effect free expression is pointless
+                IrInstruction::Eval(_) => changed = true,
+                _ => {
+                    transfer_instruction(&instruction, &tracked, &mut live);
+                    kept.push(instruction);
+                }
+            }
+        }
+        kept.reverse();
+        block.instructions = kept;
+    }
+
+    changed
+}
+
+fn remove_unused_locals(function: &mut HuleFunction) {
+    let mut referenced = HashSet::new();
+    for block in &function.blocks {
+        referenced.extend(block.defined_variables());
+        for expression in block.expressions() {
+            expression.visit_variables(&mut |variable| {
+                referenced.insert(variable);
+            });
+        }
+    }
+
+    function.locals.retain(|local| referenced.contains(local));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> HuleProgram {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        DeadCodeEliminationPass.run(&mut program, &mut vec![]);
+        program
+    }
+
+    #[test]
+    fn test_unreachable_functions() {
+        let program = prepare_test("void unused() { } string used() { return \"x\"; } entry { OUT1.Data = used(); }");
+
+        assert_eq!(program.functions().len(), 2);
+        assert_eq!(program.function(FunctionId(1)).name, "used");
+        assert!(matches!(program.function(FunctionId(0)).blocks[0].terminator,
+            Terminator::Call { function, .. } if function == FunctionId(1)));
+        assert!(program.to_ir_text().starts_with("function 0 _entry"));
+    }
+
+    #[test]
+    fn test_unused_globals() {
+        let program = prepare_test("int unused = 1; int counter = 0; entry { unused = 2; OUT1.Data = IntToStr(counter); }");
+
+        assert_eq!(program.globals().len(), 1);
+        assert_eq!(program.variable(program.globals()[0]).name, "counter");
+        assert!(program.function(FunctionId(0)).blocks[0].instructions.len() == 1);
+    }
+
+    #[test]
+    fn test_dead_stores() {
+        let program = prepare_test("entry { string s = \"a\"; s = \"b\"; string t = s; OUT1.Data = s; }");
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(entry.blocks[0].instructions.len(), 2);
+        assert_eq!(entry.locals.len(), 1);
+        assert_eq!(entry.blocks[0].instructions[0],
+            IrInstruction::Assign(IrTarget::Variable(entry.locals[0]), IrExpression::String("b".to_string())));
+    }
+
+    #[test]
+    fn test_unused_call_result() {
+        let program = prepare_test("int f() { return 1; } entry { int a = f(); }");
+        let entry = program.function(FunctionId(0));
+
+        assert!(matches!(entry.blocks[0].terminator, Terminator::Call { result: None, .. }));
+        assert!(entry.locals.is_empty());
+    }
+}
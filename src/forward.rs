@@ -0,0 +1,217 @@
+//! hyperULE Single-Use Variable Forwarding
+//!
+//! Every local of a function costs a ULE variable or a slot of its frame on
+//! the call stack. A local which is assigned once and read once in the same
+//! block is replaced by its value at the place it is read:
+//!
+//! ```text
+//! %a = (%x + 1)
+//! OUT1.Data = IntToStr(%a)    ->    OUT1.Data = IntToStr((%x + 1))
+//! ```
+//!
+//! Expressions never have side effects, so the value only has to read the
+//! same variables and device fields at both places. Expressions are folded
+//! and string concatenations simplified again after forwarding, so forwarded
+//! constants are computed and forwarded literals are merged.
+
+use std::collections::HashMap;
+use crate::concat::simplify;
+use crate::constfold::fold_expression;
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct ForwardingPass;
+
+impl OptimizerPass for ForwardingPass {
+    fn name(&self) -> &'static str {
+        "forward"
+    }
+
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) {
+        let mut saved = 0;
+        for id in program.function_ids().collect::<Vec<_>>() {
+            let mut function = program.function(id).clone();
+            let mut forwarded = 0;
+            while forward_variable(program, &mut function) {
+                forwarded += 1;
+            }
+
+            if forwarded > 0 {
+                notes.push(format!("forwarded {} single-use variables in '{}'", forwarded, function.name));
+            }
+            *program.function_mut(id) = function;
+            saved += forwarded;
+        }
+
+        if saved > 0 {
+            notes.push(format!("{} ULE variables saved by forwarding", saved));
+        }
+    }
+}
+
+/// Number of reads of every variable within the function.
+pub fn use_counts(function: &HuleFunction) -> HashMap<VarId, usize> {
+    let mut uses = HashMap::new();
+    for block in &function.blocks {
+        for expression in block.expressions() {
+            expression.visit_variables(&mut |variable| *uses.entry(variable).or_insert(0) += 1);
+        }
+    }
+
+    uses
+}
+
+/// Number of writes of every variable within the function, including call
+/// results.
+pub fn definition_counts(function: &HuleFunction) -> HashMap<VarId, usize> {
+    let mut definitions = HashMap::new();
+    for block in &function.blocks {
+        for variable in block.defined_variables() {
+            *definitions.entry(variable).or_insert(0) += 1;
+        }
+    }
+
+    definitions
+}
+
+/// Forwards the first single-use local found. Returns whether a local was
+/// forwarded.
+fn forward_variable(program: &HuleProgram, function: &mut HuleFunction) -> bool {
+    let uses = use_counts(function);
+    let definitions = definition_counts(function);
+    let is_candidate = |variable: &VarId| function.locals.contains(variable)
+        && uses.get(variable) == Some(&1)
+        && definitions.get(variable) == Some(&1);
+
+    for block in &mut function.blocks {
+        for position in 0..block.instructions.len() {
+            let (variable, value) = match &block.instructions[position] {
+                IrInstruction::Assign(IrTarget::Variable(variable), value) if is_candidate(variable) => (*variable, value.clone()),
+                _ => continue,
+            };
+
+            if !forward_in_block(program, block, position, variable, &value) {
+                continue;
+            }
+
+            block.instructions.remove(position);
+            function.locals.retain(|local| *local != variable);
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Replaces the read of `variable` after the instruction at `position` by
+/// `value`, unless an instruction in between writes anything `value` reads.
+fn forward_in_block(program: &HuleProgram, block: &mut BasicBlock, position: usize, variable: VarId, value: &IrExpression) -> bool {
+    let target = IrTarget::Variable(variable);
+    for index in position + 1..block.instructions.len() {
+        let instruction = &mut block.instructions[index];
+        let read = match instruction.value_mut() {
+            Some(expression) => expression,
+            None => continue,
+        };
+        if read.reads_target(&target) {
+            replace_variable(read, variable, value);
+            *read = simplify(program, &fold_expression(read));
+            return true;
+        }
+
+        if let IrInstruction::Assign(written, _) = instruction {
+            if value.reads_target(written) {
+                return false;
+            }
+        }
+    }
+
+    for expression in block.terminator.expressions_mut() {
+        if expression.reads_target(&target) {
+            replace_variable(expression, variable, value);
+            *expression = simplify(program, &fold_expression(expression));
+            return true;
+        }
+    }
+
+    // read in another block
+    false
+}
+
+fn replace_variable(expression: &mut IrExpression, variable: VarId, value: &IrExpression) {
+    match expression {
+        IrExpression::Variable(read) if *read == variable => *expression = value.clone(),
+        IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments) => {
+            for argument in arguments {
+                replace_variable(argument, variable, value);
+            }
+        }
+        IrExpression::Binary { left, right, .. } => {
+            replace_variable(left, variable, value);
+            replace_variable(right, variable, value);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> (HuleProgram, Vec<String>) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        let mut notes = vec![];
+        ForwardingPass.run(&mut program, &mut notes);
+        (program, notes)
+    }
+
+    #[test]
+    fn test_forward_single_use() {
+        let (program, notes) = prepare_test("entry { int a = StrLen(OUT1.Data) + 1; string b = IntToStr(a); OUT1.Data = b; }");
+        let entry = program.function(FunctionId(0));
+
+        assert!(entry.locals.is_empty());
+        assert_eq!(entry.blocks[0].instructions, vec![IrInstruction::Assign(
+            IrTarget::External("OUT1.Data".to_string()),
+            IrExpression::Builtin("IntToStr".to_string(), vec![IrExpression::Binary {
+                left: Box::new(IrExpression::Builtin("StrLen".to_string(), vec![IrExpression::External("OUT1.Data".to_string())])),
+                operator: crate::ast::Operator::Plus,
+                right: Box::new(IrExpression::Integer(1)),
+            }]),
+        )]);
+        assert_eq!(notes, vec!["forwarded 2 single-use variables in '_entry'", "2 ULE variables saved by forwarding"]);
+    }
+
+    #[test]
+    fn test_fold_forwarded_constants() {
+        let (program, _) = prepare_test("entry { int a = 1 + 2; int b = a + 6; OUT1.Data = IntToStr(b); }");
+
+        assert_eq!(program.function(FunctionId(0)).blocks[0].instructions, vec![IrInstruction::Assign(
+            IrTarget::External("OUT1.Data".to_string()),
+            IrExpression::String("9".to_string()),
+        )]);
+    }
+
+    #[test]
+    fn test_no_forwarding_over_writes() {
+        let (program, notes) = prepare_test("entry { string a = OUT1.Data; OUT1.Data = \"x\"; OUT2.Data = a; }");
+
+        assert_eq!(program.function(FunctionId(0)).locals.len(), 1);
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn test_use_counts() {
+        let (program, _) = prepare_test("entry { int a = 1; int b = a + a; while (b < 3) { b = b + 1; } }");
+        let entry = program.function(FunctionId(0));
+        let uses = use_counts(entry);
+        let definitions = definition_counts(entry);
+
+        assert_eq!(uses[&entry.locals[0]], 2);
+        assert_eq!(definitions[&entry.locals[1]], 2);
+    }
+}
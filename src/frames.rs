@@ -0,0 +1,296 @@
+//! hyperULE Static Frames
+//!
+//! The ULE runtime keeps the frames of called functions on the string
+//! encoded `callStack`, which costs code and time on every call. If no
+//! function can call itself, at most one frame of every function exists at
+//! any time, so the frames can be allocated statically instead.
+//!
+//! Every called function is copied once into `_entry`, its parameters and
+//! locals become locals of `_entry`. A call assigns the parameters and jumps
+//! to the copied body:
+//! - if the function is called from a single place, `return` jumps straight
+//!   back to the continuation of the call,
+//! - otherwise the call stores its number in `_ret_<function>` and `return`
+//!   branches on it to the matching continuation.
+//!
+//! The program is left without calls, so the backend does not need the call
+//! stack runtime at all. Programs with recursion keep the call stack.
+
+use std::collections::HashMap;
+use crate::ast::Operator;
+use crate::callgraph::CallGraph;
+use crate::liveness::Liveness;
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct StaticFramesPass;
+
+impl OptimizerPass for StaticFramesPass {
+    fn name(&self) -> &'static str {
+        "static-frames"
+    }
+
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) {
+        let graph = CallGraph::from_program(program);
+        if let Some(reason) = fallback_reason(program, &graph) {
+            notes.push(format!("call stack kept because {}", reason));
+            return;
+        }
+
+        let reachable = graph.reachable_from(FunctionId(0).0);
+        let callees: Vec<FunctionId> = program.function_ids()
+            .filter(|id| *id != FunctionId(0) && reachable[id.0])
+            .collect();
+        if callees.is_empty() {
+            return;
+        }
+
+        let copies: HashMap<FunctionId, FunctionCopy> = callees.iter()
+            .map(|callee| (*callee, copy_function(program, *callee)))
+            .collect();
+
+        for callee in &callees {
+            link_calls(program, *callee, &copies[callee]);
+        }
+
+        let keep: Vec<bool> = program.function_ids().map(|id| id == FunctionId(0)).collect();
+        program.retain_functions(&keep);
+        notes.push(format!("call stack eliminated, {} functions use static frames", callees.len()));
+    }
+}
+
+/// Why the call stack is needed, if it is.
+fn fallback_reason(program: &HuleProgram, graph: &CallGraph) -> Option<String> {
+    if let Some(function) = program.functions().iter().find(|function| graph.is_recursive(function.id.0)) {
+        return Some(format!("'{}' is recursive", function.name));
+    }
+
+    for function in program.functions() {
+        for variable in function.parameters.iter().chain(&function.locals) {
+            if let HuleType::Array(_, _) = program.variable(*variable).data_type {
+                return Some(format!("'{}' has array variables", function.name));
+            }
+        }
+    }
+
+    None
+}
+
+/// Body of a called function copied into `_entry`.
+struct FunctionCopy {
+    entry: BlockId,
+    parameters: Vec<VarId>,
+    /// Blocks of the copy ending with a `return`
+    returns: Vec<BlockId>,
+}
+
+/// Value of a variable in a new frame.
+pub fn default_value(data_type: &HuleType) -> Option<IrExpression> {
+    match data_type {
+        HuleType::Int => Some(IrExpression::Integer(0)),
+        HuleType::Bool => Some(IrExpression::Boolean(false)),
+        HuleType::String => Some(IrExpression::String(String::new())),
+        _ => None,
+    }
+}
+
+fn copy_function(program: &mut HuleProgram, callee: FunctionId) -> FunctionCopy {
+    let function = program.function(callee).clone();
+    let entry = FunctionId(0);
+
+    let mut variables = HashMap::new();
+    for variable in function.parameters.iter().chain(&function.locals) {
+        let original = program.variable(*variable);
+        let copy = HuleVariable::new(&format!("{}_{}", function.name, original.name), original.data_type.clone());
+        variables.insert(*variable, program.add_local(entry, copy));
+    }
+
+    // a frame on the call stack starts with default values, a static frame
+    // still holds the values of the previous call
+    let live = &Liveness::compute(&function).live_in[0];
+    let mut defaults = vec![];
+    for local in function.locals.iter().filter(|local| live.contains(local)) {
+        if let Some(value) = default_value(&program.variable(*local).data_type) {
+            defaults.push(IrInstruction::Assign(IrTarget::Variable(*local), value));
+        }
+    }
+
+    let map_variable = |variable: &mut VarId| {
+        if let Some(copy) = variables.get(variable) {
+            *variable = *copy;
+        }
+    };
+
+    let target = program.function_mut(entry);
+    let offset = target.blocks.len();
+    let mut returns = vec![];
+
+    for (index, mut block) in function.blocks.into_iter().enumerate() {
+        if index == 0 {
+            block.instructions.splice(0..0, defaults.drain(..));
+        }
+        for instruction in &mut block.instructions {
+            if let IrInstruction::Assign(IrTarget::Variable(variable), _) = instruction {
+                map_variable(variable);
+            }
+        }
+        if let Terminator::Call { result: Some(IrTarget::Variable(variable)), .. } = &mut block.terminator {
+            map_variable(variable);
+        }
+        for expression in block.expressions_mut() {
+            expression.visit_variables_mut(&mut |variable| map_variable(variable));
+        }
+        block.terminator.map_successors(|successor| BlockId(successor.0 + offset));
+
+        if let Terminator::Return(_) = block.terminator {
+            returns.push(BlockId(offset + index));
+        }
+        target.blocks.push(block);
+    }
+
+    FunctionCopy {
+        entry: BlockId(offset),
+        parameters: function.parameters.iter().map(|parameter| variables[parameter]).collect(),
+        returns,
+    }
+}
+
+/// Replaces every call of `callee` by a jump to its copy and every return
+/// of the copy by a jump back to the continuation.
+fn link_calls(program: &mut HuleProgram, callee: FunctionId, copy: &FunctionCopy) {
+    let entry = FunctionId(0);
+    let sites: Vec<BlockId> = program.function(entry).blocks.iter()
+        .enumerate()
+        .filter(|(_, block)| matches!(block.terminator, Terminator::Call { function, .. } if function == callee))
+        .map(|(index, _)| BlockId(index))
+        .collect();
+
+    let return_state = match sites.len() {
+        0 | 1 => None,
+        _ => {
+            let name = format!("_ret_{}", program.function(callee).name);
+            Some(program.add_local(entry, HuleVariable::new(&name, HuleType::Int)))
+        }
+    };
+
+    let function = program.function_mut(entry);
+    let mut continuations = vec![];
+    for (number, site) in sites.iter().enumerate() {
+        let block = function.block_mut(*site);
+        let terminator = std::mem::replace(&mut block.terminator, Terminator::Goto(copy.entry));
+        if let Terminator::Call { arguments, result, continuation, .. } = terminator {
+            for (parameter, argument) in copy.parameters.iter().zip(arguments) {
+                block.instructions.push(IrInstruction::Assign(IrTarget::Variable(*parameter), argument));
+            }
+            if let Some(state) = return_state {
+                block.instructions.push(IrInstruction::Assign(IrTarget::Variable(state), IrExpression::Integer(number as i32)));
+            }
+            continuations.push((result, continuation));
+        }
+    }
+
+    for block in &copy.returns {
+        let value = match std::mem::replace(&mut function.block_mut(*block).terminator, Terminator::Return(None)) {
+            Terminator::Return(value) => value,
+            _ => continue,
+        };
+
+        // one block per call site assigning the result
+        let landings: Vec<BlockId> = continuations.iter()
+            .map(|(result, continuation)| {
+                let mut landing = BasicBlock { instructions: vec![], terminator: Terminator::Goto(*continuation) };
+                if let (Some(result), Some(value)) = (result, &value) {
+                    landing.instructions.push(IrInstruction::Assign(result.clone(), value.clone()));
+                }
+                function.blocks.push(landing);
+                BlockId(function.blocks.len() - 1)
+            })
+            .collect();
+
+        let terminator = match return_state {
+            Some(state) => dispatch(function, state, &landings),
+            None => Terminator::Goto(landings[0]),
+        };
+        function.block_mut(*block).terminator = terminator;
+    }
+}
+
+/// Branches on the return state to the landing block of the call site.
+fn dispatch(function: &mut HuleFunction, state: VarId, landings: &[BlockId]) -> Terminator {
+    let mut terminator = Terminator::Goto(*landings.last().unwrap());
+    for (number, landing) in landings.iter().enumerate().rev().skip(1) {
+        let condition = IrExpression::Binary {
+            left: Box::new(IrExpression::Variable(state)),
+            operator: Operator::Equal,
+            right: Box::new(IrExpression::Integer(number as i32)),
+        };
+
+        let else_block = match terminator {
+            Terminator::Goto(target) => target,
+            terminator => {
+                function.blocks.push(BasicBlock { instructions: vec![], terminator });
+                BlockId(function.blocks.len() - 1)
+            }
+        };
+        terminator = Terminator::Branch { condition, then_block: *landing, else_block };
+    }
+
+    terminator
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simplifycfg::simplify_function;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> (HuleProgram, Vec<String>) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        let mut notes = vec![];
+        StaticFramesPass.run(&mut program, &mut notes);
+        (program, notes)
+    }
+
+    fn has_calls(function: &HuleFunction) -> bool {
+        function.blocks.iter().any(|block| matches!(block.terminator, Terminator::Call { .. }))
+    }
+
+    #[test]
+    fn test_single_call_site() {
+        let (mut program, notes) = prepare_test("string greet(string name) { return \"Hi \" + name; } entry { OUT1.Data = greet(OUT1.Data); }");
+        simplify_function(program.function_mut(FunctionId(0)));
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(program.functions().len(), 1);
+        assert!(!has_calls(entry));
+        assert_eq!(entry.blocks.len(), 1);
+        assert_eq!(notes, vec!["call stack eliminated, 1 functions use static frames"]);
+    }
+
+    #[test]
+    fn test_return_dispatch() {
+        let (program, _) = prepare_test("int twice(int a) { int b; b = b + a; return b + a; } \
+            entry { int x = twice(1); int y = twice(x); int z = twice(y); OUT1.Data = IntToStr(z); }");
+        let entry = program.function(FunctionId(0));
+        let names: Vec<&str> = entry.locals.iter().map(|local| program.variable(*local).name.as_str()).collect();
+
+        assert!(!has_calls(entry));
+        assert!(names.contains(&"twice_a") && names.contains(&"_ret_twice"));
+        // the uninitialized local is reset on every call
+        let body = &entry.blocks[entry.blocks.len() - 5];
+        assert!(matches!(&body.instructions[0], IrInstruction::Assign(_, IrExpression::Integer(0))));
+        let branches = entry.blocks.iter().filter(|block| matches!(block.terminator, Terminator::Branch { .. })).count();
+        assert_eq!(branches, 2);
+    }
+
+    #[test]
+    fn test_recursion_keeps_call_stack() {
+        let (program, notes) = prepare_test("int f(int a) { if (a == 0) { return 0; } return f(a - 1); } entry { f(3); }");
+
+        assert_eq!(program.functions().len(), 2);
+        assert_eq!(notes, vec!["call stack kept because 'f' is recursive"]);
+    }
+}
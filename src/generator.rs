@@ -3,7 +3,7 @@
 ///
 
 pub trait HyperBackend<I, O> {
-    fn generate(source : I) -> O;
+    fn generate(&self, source: I) -> O;
 }
 
 pub trait HyperNode<O> {
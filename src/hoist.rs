@@ -0,0 +1,160 @@
+//! hyperULE Literal Hoisting
+//!
+//! A literal used many times, like a rule table string, is written into the
+//! ULE output at every use. If it is smaller to define the literal once in a
+//! global variable and read the variable instead, the literal is hoisted
+//! into a new global `_c<n>`.
+//!
+//! The decision is based on the bytes of ULE output:
+//! `uses * literal > name + DEFINITION_OVERHEAD + literal + uses * name`
+
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+use crate::literals::escape_string;
+
+/// Bytes of a global definition besides its name and value, ` = ` and the
+/// line break.
+const DEFINITION_OVERHEAD: usize = 4;
+
+pub struct LiteralHoistingPass;
+
+impl OptimizerPass for LiteralHoistingPass {
+    fn name(&self) -> &'static str {
+        "hoist"
+    }
+
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) {
+        let mut literals: Vec<(IrExpression, usize)> = vec![];
+        for function in program.functions() {
+            for block in &function.blocks {
+                for expression in block.expressions() {
+                    count_literals(expression, &mut literals);
+                }
+            }
+        }
+
+        let mut hoisted = 0;
+        for (literal, uses) in literals {
+            let name = format!("_c{}", hoisted);
+            let saved = match saved_bytes(&literal, uses, name.len()) {
+                Some(saved) => saved,
+                None => continue,
+            };
+
+            let mut variable = HuleVariable::new(&name, program.expression_type(&literal));
+            variable.initializer = Some(literal.clone());
+            let global = program.add_global(variable);
+
+            for id in program.function_ids().collect::<Vec<_>>() {
+                for block in &mut program.function_mut(id).blocks {
+                    for expression in block.expressions_mut() {
+                        replace_literal(expression, &literal, global);
+                    }
+                }
+            }
+
+            notes.push(format!("hoisted literal used {} times into '{}', saving {} bytes", uses, name, saved));
+            hoisted += 1;
+        }
+    }
+}
+
+/// Bytes of the literal in the ULE output, booleans are never hoisted.
+pub fn literal_size(literal: &IrExpression) -> Option<usize> {
+    match literal {
+        IrExpression::Integer(value) => Some(value.to_string().len()),
+        IrExpression::String(value) => Some(escape_string(value).len() + 2),
+        _ => None,
+    }
+}
+
+/// Bytes saved by hoisting the literal into a global with a name of
+/// `name_size` bytes, if any.
+fn saved_bytes(literal: &IrExpression, uses: usize, name_size: usize) -> Option<usize> {
+    let size = literal_size(literal)?;
+    let inline_cost = uses * size;
+    let hoisted_cost = name_size + DEFINITION_OVERHEAD + size + uses * name_size;
+    inline_cost.checked_sub(hoisted_cost).filter(|saved| *saved > 0)
+}
+
+/// Counts the uses of every integer and string literal, in order of their
+/// first use.
+fn count_literals(expression: &IrExpression, literals: &mut Vec<(IrExpression, usize)>) {
+    match expression {
+        IrExpression::Integer(_) | IrExpression::String(_) => {
+            match literals.iter_mut().find(|(literal, _)| literal == expression) {
+                Some((_, uses)) => *uses += 1,
+                None => literals.push((expression.clone(), 1)),
+            }
+        }
+        IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments) => {
+            for argument in arguments {
+                count_literals(argument, literals);
+            }
+        }
+        IrExpression::Binary { left, right, .. } => {
+            count_literals(left, literals);
+            count_literals(right, literals);
+        }
+        _ => {}
+    }
+}
+
+fn replace_literal(expression: &mut IrExpression, literal: &IrExpression, global: VarId) {
+    match expression {
+        _ if expression == literal => *expression = IrExpression::Variable(global),
+        IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments) => {
+            for argument in arguments {
+                replace_literal(argument, literal, global);
+            }
+        }
+        IrExpression::Binary { left, right, .. } => {
+            replace_literal(left, literal, global);
+            replace_literal(right, literal, global);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> (HuleProgram, Vec<String>) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        let mut notes = vec![];
+        LiteralHoistingPass.run(&mut program, &mut notes);
+        (program, notes)
+    }
+
+    #[test]
+    fn test_hoist_repeated_literal() {
+        let (program, notes) = prepare_test("entry { OUT1.Data = \"ABCDEFGHIJKLMNOP\"; OUT2.Data = \"ABCDEFGHIJKLMNOP\"; OUT3.Data = \"ABCDEFGHIJKLMNOP\"; }");
+        let global = *program.globals().last().unwrap();
+
+        assert_eq!(program.variable(global).name, "_c0");
+        assert_eq!(program.variable(global).initializer, Some(IrExpression::String("ABCDEFGHIJKLMNOP".to_string())));
+        assert_eq!(notes, vec!["hoisted literal used 3 times into '_c0', saving 20 bytes"]);
+        assert!(program.function(FunctionId(0)).blocks[0].instructions.iter().all(|instruction|
+            matches!(instruction, IrInstruction::Assign(_, IrExpression::Variable(variable)) if *variable == global)));
+    }
+
+    #[test]
+    fn test_keep_literals_when_hoisting_is_larger() {
+        let (program, notes) = prepare_test("entry { OUT1.Data = \"ab\"; OUT2.Data = \"ab\"; OUT3.Data = \"ABCDEFGHIJKLMNOP\"; }");
+
+        assert!(program.globals().is_empty());
+        assert!(notes.is_empty());
+    }
+
+    #[test]
+    fn test_literal_size() {
+        assert_eq!(literal_size(&IrExpression::String("a\"\u{1}".to_string())), Some(9));
+        assert_eq!(literal_size(&IrExpression::Integer(-12)), Some(3));
+        assert_eq!(literal_size(&IrExpression::Boolean(true)), None);
+    }
+}
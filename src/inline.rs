@@ -0,0 +1,206 @@
+//! hyperULE Inliner
+//!
+//! Replaces calls of non-recursive functions by a copy of the callee body.
+//! Every call costs a push and pop of a frame in the ULE runtime, so small
+//! functions are always cheaper when inlined. Functions declared `inline`
+//! are inlined regardless of their size.
+//!
+//! The parameters and locals of the callee become locals of the caller, the
+//! arguments are assigned to the copied parameters and every `return` of
+//! the copied body assigns the call result and jumps to the continuation.
+//! Copied locals read before their first assignment are reset to their
+//! default value, like a new frame of the callee.
+
+use std::collections::HashMap;
+use crate::callgraph::CallGraph;
+use crate::frames::default_value;
+use crate::liveness::Liveness;
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+/// Functions with at most this many instructions and terminators are
+/// inlined automatically.
+const INLINE_THRESHOLD: usize = 6;
+
+pub struct InlinePass;
+
+impl OptimizerPass for InlinePass {
+    fn name(&self) -> &'static str {
+        "inline"
+    }
+
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) {
+        let graph = CallGraph::from_program(program);
+
+        for function in program.functions() {
+            if function.is_inline && graph.is_recursive(function.id.0) {
+                notes.push(format!("'{}' is declared inline but not inlined because it is recursive", function.name));
+            }
+        }
+
+        for caller in program.function_ids().collect::<Vec<_>>() {
+            // inlined bodies are appended, so their calls are visited as well
+            let mut index = 0;
+            while index < program.function(caller).blocks.len() {
+                let callee = match program.function(caller).blocks[index].terminator {
+                    Terminator::Call { function, .. } => function,
+                    _ => {
+                        index += 1;
+                        continue;
+                    }
+                };
+
+                if should_inline(program.function(callee), &graph) {
+                    inline_call(program, caller, BlockId(index));
+                    notes.push(format!("inlined call of '{}' into '{}'",
+                        program.function(callee).name, program.function(caller).name));
+                }
+                index += 1;
+            }
+        }
+    }
+}
+
+fn should_inline(callee: &HuleFunction, graph: &CallGraph) -> bool {
+    if graph.is_recursive(callee.id.0) || callee.id == FunctionId(0) {
+        return false;
+    }
+
+    let size: usize = callee.blocks.iter()
+        .map(|block| block.code_len() + 1)
+        .sum();
+    callee.is_inline || size <= INLINE_THRESHOLD
+}
+
+/// Replaces the call terminating `block` by a copy of the callee body.
+fn inline_call(program: &mut HuleProgram, caller: FunctionId, block: BlockId) {
+    let (callee, arguments, result, continuation) = match program.function(caller).block(block).terminator.clone() {
+        Terminator::Call { function, arguments, result, continuation } => (function, arguments, result, continuation),
+        _ => return,
+    };
+
+    let callee = program.function(callee).clone();
+    let mut variables = HashMap::new();
+    for variable in callee.parameters.iter().chain(&callee.locals) {
+        let original = program.variable(*variable);
+        let copy = HuleVariable::new(&format!("{}_{}", callee.name, original.name), original.data_type.clone());
+        variables.insert(*variable, program.add_local(caller, copy));
+    }
+
+    // the copied locals keep their value between two calls in a loop
+    let live = &Liveness::compute(&callee).live_in[0];
+    let defaults: Vec<IrInstruction> = callee.locals.iter()
+        .filter(|local| live.contains(local))
+        .filter_map(|local| default_value(&program.variable(*local).data_type)
+            .map(|value| IrInstruction::Assign(IrTarget::Variable(variables[local]), value)))
+        .collect();
+
+    let map_variable = |variable: &mut VarId| {
+        if let Some(copy) = variables.get(variable) {
+            *variable = *copy;
+        }
+    };
+    let map_target = |target: &mut IrTarget| {
+        if let IrTarget::Variable(variable) = target {
+            map_variable(variable);
+        }
+    };
+
+    let function = program.function_mut(caller);
+    let offset = function.blocks.len();
+
+    for mut copy in callee.blocks {
+        for instruction in &mut copy.instructions {
+            if let IrInstruction::Assign(target, _) = instruction {
+                map_target(target);
+            }
+        }
+        for expression in copy.expressions_mut() {
+            expression.visit_variables_mut(&mut |variable| map_variable(variable));
+        }
+        copy.terminator.map_successors(|target| BlockId(target.0 + offset));
+
+        match &mut copy.terminator {
+            Terminator::Return(value) => {
+                if let (Some(result), Some(value)) = (&result, value.take()) {
+                    copy.instructions.push(IrInstruction::Assign(result.clone(), value));
+                }
+                copy.terminator = Terminator::Goto(continuation);
+            }
+            Terminator::Call { result: Some(target), .. } => map_target(target),
+            _ => {}
+        }
+
+        function.blocks.push(copy);
+    }
+
+    let call_block = function.block_mut(block);
+    let call_location = call_block.instructions.iter().rev().find(|instruction| instruction.is_location()).cloned();
+    for (parameter, argument) in callee.parameters.iter().zip(arguments) {
+        call_block.instructions.push(IrInstruction::Assign(IrTarget::Variable(variables[parameter]), argument));
+    }
+    call_block.instructions.extend(defaults);
+    call_block.terminator = Terminator::Goto(BlockId(offset));
+
+    // the code after the inlined body belongs to the statement of the call
+    let continuation = function.block_mut(continuation);
+    if let (Some(location), false) = (call_location, continuation.instructions.first().is_some_and(IrInstruction::is_location)) {
+        continuation.instructions.insert(0, location);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> (HuleProgram, Vec<String>) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        let mut notes = vec![];
+        InlinePass.run(&mut program, &mut notes);
+        (program, notes)
+    }
+
+    fn has_calls(function: &HuleFunction) -> bool {
+        function.blocks.iter().any(|block| matches!(block.terminator, Terminator::Call { .. }))
+    }
+
+    #[test]
+    fn test_inline_small_function() {
+        let (program, notes) = prepare_test("string greet(string name) { return \"Hi \" + name; } entry { OUT1.Data = greet(OUT1.Data); }");
+        let entry = program.function(FunctionId(0));
+
+        assert!(!has_calls(entry));
+        assert_eq!(notes, vec!["inlined call of 'greet' into '_entry'"]);
+        assert_eq!(program.variable(entry.locals[0]).name, "greet_name");
+        assert!(entry.blocks.iter().any(|block| block.instructions.contains(&IrInstruction::Assign(
+            IrTarget::External("OUT1.Data".to_string()),
+            IrExpression::Binary {
+                left: Box::new(IrExpression::String("Hi ".to_string())),
+                operator: crate::ast::Operator::Plus,
+                right: Box::new(IrExpression::Variable(entry.locals[0])),
+            },
+        ))));
+    }
+
+    #[test]
+    fn test_inline_modifier() {
+        let body = "int a = 1; a = a + 1; a = a + 1; a = a + 1; a = a + 1; a = a + 1; return a;";
+        let (program, _) = prepare_test(&format!("int big() {{ {} }} entry {{ OUT1.Data = IntToStr(big()); }}", body));
+        assert!(has_calls(program.function(FunctionId(0))));
+
+        let (program, _) = prepare_test(&format!("inline int big() {{ {} }} entry {{ OUT1.Data = IntToStr(big()); }}", body));
+        assert!(!has_calls(program.function(FunctionId(0))));
+    }
+
+    #[test]
+    fn test_no_inlining_of_recursive_functions() {
+        let (program, notes) = prepare_test("inline int f(int a) { if (a == 0) { return 0; } return f(a - 1); } entry { f(3); }");
+
+        assert!(has_calls(program.function(FunctionId(0))));
+        assert_eq!(notes.len(), 1);
+    }
+}
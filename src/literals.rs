@@ -0,0 +1,71 @@
+//! hyperULE String Literals
+//!
+//! hyperULE and ULE write string literals the same way, the parser reads
+//! them with `unescape_string`, the IR printer and the ULE writer write them
+//! with `escape_string`.
+
+/// Value of a string literal without the quotes. Supports `\"`, `\\`, `\n`,
+/// `\r`, `\t` and `\xNN`, other escapes are kept as written.
+pub fn unescape_string(literal: &str) -> String {
+    let mut result = String::new();
+    let mut characters = literal.chars();
+    while let Some(character) = characters.next() {
+        if character != '\\' {
+            result.push(character);
+            continue;
+        }
+
+        match characters.next() {
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('x') => {
+                let digits: String = characters.by_ref().take(2).collect();
+                match u8::from_str_radix(&digits, 16) {
+                    Ok(value) => result.push(value as char),
+                    Err(_) => result.push_str(&format!("\\x{}", digits)),
+                }
+            }
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+
+    result
+}
+
+/// Escapes a value for a ULE string literal, without the quotes. Characters
+/// which are not printable ASCII are written as `\xNN`.
+pub fn escape_string(value: &str) -> String {
+    let mut result = String::new();
+    for character in value.chars() {
+        match character {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            ' '..='~' => result.push(character),
+            _ if (character as u32) < 0x100 => result.push_str(&format!("\\x{:02X}", character as u32)),
+            _ => result.push(character),
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let value = "say \"hi\"\\\n\u{7f}";
+
+        assert_eq!(escape_string(value), "say \\\"hi\\\"\\\\\\x0A\\x7F");
+        assert_eq!(unescape_string(&escape_string(value)), value);
+        assert_eq!(unescape_string("a\\tb\\q\\xZZ"), "a\tb\\q\\xZZ");
+    }
+}
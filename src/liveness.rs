@@ -0,0 +1,122 @@
+//! hyperULE Liveness Analysis
+//!
+//! Backward dataflow analysis on the basic blocks of a `HuleFunction`. A
+//! variable is live at a point if its current value may be read later.
+//! Only the parameters and locals of the function are tracked, globals can
+//! be read by every other function.
+
+use std::collections::HashSet;
+use crate::program::*;
+
+pub struct Liveness {
+    /// Variables live when entering each block
+    pub live_in: Vec<HashSet<VarId>>,
+    /// Variables live when leaving each block
+    pub live_out: Vec<HashSet<VarId>>,
+}
+
+impl Liveness {
+    pub fn compute(function: &HuleFunction) -> Liveness {
+        let count = function.blocks.len();
+        let mut liveness = Liveness {
+            live_in: vec![HashSet::new(); count],
+            live_out: vec![HashSet::new(); count],
+        };
+
+        let tracked = tracked_variables(function);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in (0..count).rev() {
+                let block = &function.blocks[index];
+                let mut live_out = HashSet::new();
+                for successor in block.terminator.successors() {
+                    live_out.extend(liveness.live_in[successor.0].iter().copied());
+                }
+
+                let mut live = live_out.clone();
+                transfer_terminator(&block.terminator, &tracked, &mut live);
+                for instruction in block.instructions.iter().rev() {
+                    transfer_instruction(instruction, &tracked, &mut live);
+                }
+
+                if live != liveness.live_in[index] || live_out != liveness.live_out[index] {
+                    liveness.live_in[index] = live;
+                    liveness.live_out[index] = live_out;
+                    changed = true;
+                }
+            }
+        }
+
+        liveness
+    }
+}
+
+/// Parameters and locals of the function.
+pub fn tracked_variables(function: &HuleFunction) -> HashSet<VarId> {
+    function.parameters.iter()
+        .chain(&function.locals)
+        .copied()
+        .collect()
+}
+
+fn add_uses(expression: &IrExpression, tracked: &HashSet<VarId>, live: &mut HashSet<VarId>) {
+    expression.visit_variables(&mut |variable| {
+        if tracked.contains(&variable) {
+            live.insert(variable);
+        }
+    });
+}
+
+fn remove_definition(target: &IrTarget, live: &mut HashSet<VarId>) {
+    if let IrTarget::Variable(variable) = target {
+        live.remove(variable);
+    }
+}
+
+/// Updates the live variables before the terminator from those after it.
+pub fn transfer_terminator(terminator: &Terminator, tracked: &HashSet<VarId>, live: &mut HashSet<VarId>) {
+    if let Terminator::Call { result: Some(result), .. } = terminator {
+        remove_definition(result, live);
+    }
+
+    for expression in terminator.expressions() {
+        add_uses(expression, tracked, live);
+    }
+}
+
+/// Updates the live variables before the instruction from those after it.
+pub fn transfer_instruction(instruction: &IrInstruction, tracked: &HashSet<VarId>, live: &mut HashSet<VarId>) {
+    match instruction {
+        IrInstruction::Assign(target, value) => {
+            remove_definition(target, live);
+            add_uses(value, tracked, live);
+        }
+        IrInstruction::Eval(value) => add_uses(value, tracked, live),
+        IrInstruction::Location(_) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    #[test]
+    fn test_loop_variable_is_live() {
+        let input = "entry { int i = 0; while (i < 3) { i = i + 1; } }";
+        let mut parser = AstParser::new(input);
+        let program = lower(&parser.parse(input).unwrap()).unwrap();
+        let entry = program.function(FunctionId(0));
+        let liveness = Liveness::compute(entry);
+        let i = entry.locals[0];
+
+        // entry, loop condition, loop body and exit
+        assert!(!liveness.live_in[0].contains(&i));
+        assert!(liveness.live_out[0].contains(&i));
+        assert!(liveness.live_in[1].contains(&i));
+        assert!(liveness.live_out[2].contains(&i));
+        assert!(!liveness.live_in[3].contains(&i));
+    }
+}
@@ -0,0 +1,800 @@
+//! hyperULE Lowering
+//!
+//! Translates the `HuleProgramAst` into the `HuleProgram` IR. Functions get
+//! stable function indices (`_entry` is always 0, followed by the other
+//! functions in source order), names are resolved to variable ids and
+//! every expression is type checked.
+//!
+//! Function bodies are lowered into structured `IrStatement`s first, which
+//! the `BlockBuilder` then splits into basic blocks. Calls of hyperULE
+//! functions end a basic block, their result is stored in a temporary
+//! variable which the continuation block reads.
+
+use crate::ast::*;
+use crate::consteval::ConstEvaluator;
+use crate::program::*;
+
+#[derive(Debug, PartialEq)]
+pub enum LoweringError {
+    UnknownType(String),
+    UndefinedVariable { function: String, name: String },
+    UndefinedFunction { function: String, name: String },
+    ArgumentCount { function: String, name: String, expected: usize, found: usize },
+    TypeMismatch { function: String, context: String, expected: HuleType, found: HuleType },
+    InvalidOperands { function: String, left: HuleType, operator: Operator, right: HuleType },
+    CallInInitializer(String),
+}
+
+impl LoweringError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            LoweringError::UnknownType(name) => format!("Unknown type '{}'.", name),
+            LoweringError::UndefinedVariable { function, name }
+            => format!("Variable '{}' used in '{}' is not defined.", name, function),
+            LoweringError::UndefinedFunction { function, name }
+            => format!("Function '{}' called in '{}' is not defined.", name, function),
+            LoweringError::ArgumentCount { function, name, expected, found }
+            => format!("Function '{}' called in '{}' expects {} parameter(s) but {} given.", name, function, expected, found),
+            LoweringError::TypeMismatch { function, context, expected, found }
+            => format!("Type '{}' expected for {} in '{}' but '{}' given.", expected.name(), context, function, found.name()),
+            LoweringError::InvalidOperands { function, left, operator, right }
+            => format!("Operator '{}' can not be applied to '{}' and '{}' in '{}'.", operator.symbol(), left.name(), right.name(), function),
+            LoweringError::CallInInitializer(name)
+            => format!("Global '{}' is initialized by a function call, only built-in functions can be used there.", name),
+        }
+    }
+}
+
+/// Structured form of a function body before it is split into basic blocks.
+#[derive(Clone, PartialEq, Debug)]
+enum IrStatement {
+    Assign(IrTarget, IrExpression),
+    Eval(IrExpression),
+    If {
+        condition: IrExpression,
+        body: Vec<IrStatement>,
+        else_body: Vec<IrStatement>,
+    },
+    While {
+        condition: IrExpression,
+        body: Vec<IrStatement>,
+    },
+    Return(Option<IrExpression>),
+    Break,
+    Location(SourcePosition),
+}
+
+struct Lowering<'a> {
+    ast: &'a HuleProgramAst,
+    constants: ConstEvaluator,
+    /// Add the position of every statement to the code
+    locations: bool,
+}
+
+/// State while lowering a single function body.
+struct FunctionScope<'p> {
+    program: &'p mut HuleProgram,
+    function: FunctionId,
+    /// Name used in error messages
+    name: String,
+    return_type: HuleType,
+    scopes: Vec<Vec<VarId>>,
+    constants: ConstEvaluator,
+}
+
+pub fn lower(ast: &HuleProgramAst) -> Result<HuleProgram, LoweringError> {
+    let mut lowering = Lowering {
+        ast,
+        constants: ConstEvaluator::new(),
+        locations: false,
+    };
+
+    lowering.lower_program()
+}
+
+/// Lowers the program with an `IrInstruction::Location` before the code of
+/// every statement.
+pub fn lower_with_locations(ast: &HuleProgramAst) -> Result<HuleProgram, LoweringError> {
+    let mut lowering = Lowering {
+        ast,
+        constants: ConstEvaluator::new(),
+        locations: true,
+    };
+
+    lowering.lower_program()
+}
+
+impl<'a> Lowering<'a> {
+    fn lower_program(&mut self) -> Result<HuleProgram, LoweringError> {
+        let mut program = HuleProgram::new();
+
+        // the function ids are the function indices of the runtime, so
+        // `_entry` has to be added first
+        let mut functions: Vec<&HuleFuncDef> = self.ast.functions().collect();
+        functions.sort_by_key(|func| func.name != "_entry");
+
+        // signatures come first, so calls in global initializers are known
+        for func in &functions {
+            let id = program.add_function(&func.name, resolve_type(&func.return_type)?);
+            program.function_mut(id).is_inline = func.is_inline;
+            for parameter in &func.parameters {
+                let data_type = resolve_type(&parameter.data_type)?;
+                program.add_parameter(id, HuleVariable::new(&parameter.name, data_type));
+            }
+        }
+
+        for statement in &self.ast.body.items {
+            if let HuleStatement::VariableDecl(decl) = statement {
+                self.lower_global(decl, &mut program)?;
+            }
+        }
+
+        for (func, id) in functions.iter().zip(0..) {
+            self.lower_function(func, FunctionId(id), &mut program)?;
+        }
+
+        Ok(program)
+    }
+
+    fn lower_global(&mut self, decl: &HuleVariableDecl, program: &mut HuleProgram) -> Result<(), LoweringError> {
+        let data_type = self.resolve_decl_type(decl, &self.constants)?;
+        let mut variable = HuleVariable::new(&decl.name, data_type.clone());
+
+        if let Some(value) = &decl.value {
+            let mut scope = FunctionScope {
+                program,
+                function: FunctionId(0),
+                name: "<global>".to_string(),
+                return_type: HuleType::Void,
+                scopes: vec![vec![]],
+                constants: self.constants.clone(),
+            };
+
+            let (value, value_type) = self.lower_expression(value, &mut scope)?;
+            check_type(&scope, &format!("initial value of '{}'", decl.name), &data_type, &value_type)?;
+            // globals are set before the runtime starts, which runs calls
+            if value.contains_call() {
+                return Err(LoweringError::CallInInitializer(decl.name.clone()));
+            }
+            variable.initializer = Some(value);
+        }
+
+        if decl.is_const {
+            variable.constant = decl.value.as_ref()
+                .and_then(|value| self.constants.evaluate(value).ok());
+            if let Some(constant) = &variable.constant {
+                self.constants.define(&decl.name, constant.clone());
+            }
+        }
+
+        program.add_global(variable);
+        Ok(())
+    }
+
+    fn resolve_decl_type(&self, decl: &HuleVariableDecl, constants: &ConstEvaluator) -> Result<HuleType, LoweringError> {
+        let data_type = resolve_type(&decl.data_type)?;
+
+        // array sizes are validated by the analyzer
+        match &decl.array_size {
+            Some(size) => match constants.evaluate(size) {
+                Ok(crate::consteval::ConstValue::Integer(size)) if size > 0 => {
+                    Ok(HuleType::Array(Box::new(data_type), size as usize))
+                }
+                _ => Err(LoweringError::UnknownType(format!("{}[]", decl.data_type))),
+            },
+            None => Ok(data_type),
+        }
+    }
+
+    fn lower_function(&self, func: &HuleFuncDef, id: FunctionId, program: &mut HuleProgram) -> Result<(), LoweringError> {
+        let parameters = program.function(id).parameters.clone();
+        let mut scope = FunctionScope {
+            function: id,
+            name: func.name.clone(),
+            return_type: program.function(id).return_type.clone(),
+            program,
+            scopes: vec![parameters],
+            constants: self.constants.clone(),
+        };
+
+        let body = self.lower_statement(&func.body, &mut scope)?;
+        let FunctionScope { program, .. } = scope;
+
+        let mut builder = BlockBuilder {
+            program,
+            function: id,
+            blocks: vec![],
+            current: BlockId(0),
+            loop_exits: vec![],
+        };
+        builder.build(&body);
+
+        Ok(())
+    }
+
+    fn lower_block(&self, statement: &HuleStatement, scope: &mut FunctionScope) -> Result<Vec<IrStatement>, LoweringError> {
+        scope.scopes.push(vec![]);
+        let result = self.lower_statement(statement, scope);
+        scope.scopes.pop();
+        result
+    }
+
+    fn lower_statement(&self, statement: &HuleStatement, scope: &mut FunctionScope) -> Result<Vec<IrStatement>, LoweringError> {
+        let mut result = vec![];
+
+        match statement {
+            HuleStatement::Body(body) => {
+                for (index, item) in body.items.iter().enumerate() {
+                    if let (true, Some(position)) = (self.locations, body.position(index)) {
+                        result.push(IrStatement::Location(position));
+                    }
+                    result.extend(self.lower_statement(item, scope)?);
+                }
+            }
+            HuleStatement::VariableDecl(decl) => {
+                let data_type = self.resolve_decl_type(decl, &scope.constants)?;
+                let mut variable = HuleVariable::new(&decl.name, data_type.clone());
+
+                let value = match &decl.value {
+                    Some(value) => {
+                        let (value, value_type) = self.lower_expression(value, scope)?;
+                        check_type(scope, &format!("initial value of '{}'", decl.name), &data_type, &value_type)?;
+                        Some(value)
+                    }
+                    None => None,
+                };
+
+                if decl.is_const {
+                    variable.constant = decl.value.as_ref()
+                        .and_then(|value| scope.constants.evaluate(value).ok());
+                }
+
+                match &variable.constant {
+                    Some(constant) => scope.constants.define(&decl.name, constant.clone()),
+                    None => scope.constants.undefine(&decl.name),
+                }
+
+                let id = scope.declare(variable);
+                if let Some(value) = value {
+                    result.push(IrStatement::Assign(IrTarget::Variable(id), value));
+                }
+            }
+            HuleStatement::VariableDef(def) => {
+                let (target, target_type) = self.resolve_target(&def.name, scope)?;
+                let (value, value_type) = self.lower_expression(&def.value, scope)?;
+                check_type(scope, &format!("assignment to '{}'", def.name), &target_type, &value_type)?;
+                result.push(IrStatement::Assign(target, value));
+            }
+            HuleStatement::IfStatement(if_statement) => {
+                let condition = self.lower_condition(&if_statement.condition, scope)?;
+                let body = self.lower_block(&if_statement.body, scope)?;
+                let else_body = match &if_statement.else_body {
+                    Some(else_body) => self.lower_block(else_body, scope)?,
+                    None => vec![],
+                };
+                result.push(IrStatement::If { condition, body, else_body });
+            }
+            HuleStatement::WhileLoop(while_loop) => {
+                let condition = self.lower_condition(&while_loop.condition, scope)?;
+                let body = self.lower_block(&while_loop.body, scope)?;
+                result.push(IrStatement::While { condition, body });
+            }
+            HuleStatement::FunctionCall(call) => {
+                let (call, _) = self.lower_call(&call.name, &call.parameters, scope)?;
+                result.push(IrStatement::Eval(call));
+            }
+            HuleStatement::Return(value) => {
+                let return_type = scope.return_type.clone();
+                let value = match value {
+                    HuleExpression::Undefined => {
+                        check_type(scope, "return value", &return_type, &HuleType::Void)?;
+                        None
+                    }
+                    value => {
+                        let (value, value_type) = self.lower_expression(value, scope)?;
+                        check_type(scope, "return value", &return_type, &value_type)?;
+                        Some(value)
+                    }
+                };
+                result.push(IrStatement::Return(value));
+            }
+            HuleStatement::Break => result.push(IrStatement::Break),
+            _ => {}
+        }
+
+        Ok(result)
+    }
+
+    fn lower_condition(&self, condition: &HuleExpression, scope: &mut FunctionScope) -> Result<IrExpression, LoweringError> {
+        let (condition, condition_type) = self.lower_expression(condition, scope)?;
+        check_type(scope, "condition", &HuleType::Bool, &condition_type)?;
+        Ok(condition)
+    }
+
+    fn resolve_target(&self, name: &str, scope: &FunctionScope) -> Result<(IrTarget, HuleType), LoweringError> {
+        match self.resolve_variable(name, scope)? {
+            (IrExpression::Variable(variable), data_type) => Ok((IrTarget::Variable(variable), data_type)),
+            (_, data_type) => Ok((IrTarget::External(name.to_string()), data_type)),
+        }
+    }
+
+    fn resolve_variable(&self, name: &str, scope: &FunctionScope) -> Result<(IrExpression, HuleType), LoweringError> {
+        let global = scope.program.globals().iter()
+            .copied()
+            .find(|id| scope.program.variable(*id).name == name);
+
+        if let Some(id) = scope.resolve(name).or(global) {
+            let data_type = scope.program.variable(id).data_type.clone();
+            return Ok((IrExpression::Variable(id), data_type));
+        }
+
+        // fields of the device like OUT1.Data are always strings
+        if name.contains('.') {
+            return Ok((IrExpression::External(name.to_string()), HuleType::String));
+        }
+
+        Err(LoweringError::UndefinedVariable {
+            function: scope.name.clone(),
+            name: name.to_string(),
+        })
+    }
+
+    fn lower_call(&self, name: &str, parameters: &[HuleExpression], scope: &mut FunctionScope) -> Result<(IrExpression, HuleType), LoweringError> {
+        let (id, parameter_types, return_type) = match scope.program.function_by_name(name) {
+            Some(id) => {
+                let function = scope.program.function(id);
+                let parameter_types = function.parameters.iter()
+                    .map(|p| scope.program.variable(*p).data_type.clone())
+                    .collect();
+                (Some(id), parameter_types, function.return_type.clone())
+            }
+            None => match builtin_signature(name) {
+                Some((parameter_types, return_type)) => (None, parameter_types, return_type),
+                None => return Err(LoweringError::UndefinedFunction {
+                    function: scope.name.clone(),
+                    name: name.to_string(),
+                }),
+            },
+        };
+
+        if parameters.len() != parameter_types.len() {
+            return Err(LoweringError::ArgumentCount {
+                function: scope.name.clone(),
+                name: name.to_string(),
+                expected: parameter_types.len(),
+                found: parameters.len(),
+            });
+        }
+
+        let mut arguments = vec![];
+        for (position, (parameter, expected)) in parameters.iter().zip(&parameter_types).enumerate() {
+            let (argument, argument_type) = self.lower_expression(parameter, scope)?;
+            check_type(scope, &format!("parameter {} of '{}'", position + 1, name), expected, &argument_type)?;
+            arguments.push(argument);
+        }
+
+        match id {
+            Some(id) => Ok((IrExpression::Call(id, arguments), return_type)),
+            None => Ok((IrExpression::Builtin(name.to_string(), arguments), return_type)),
+        }
+    }
+
+    fn lower_expression(&self, expression: &HuleExpression, scope: &mut FunctionScope) -> Result<(IrExpression, HuleType), LoweringError> {
+        match expression {
+            HuleExpression::Integer(value) => Ok((IrExpression::Integer(*value), HuleType::Int)),
+            HuleExpression::String(value) => Ok((IrExpression::String(value.clone()), HuleType::String)),
+            HuleExpression::Boolean(value) => Ok((IrExpression::Boolean(*value), HuleType::Bool)),
+            HuleExpression::Bracketed(inner) => self.lower_expression(inner, scope),
+            HuleExpression::Identifier(name) => self.resolve_variable(name, scope),
+            HuleExpression::Call(name, parameters) => self.lower_call(name, parameters, scope),
+            HuleExpression::Undefined => Ok((IrExpression::Integer(0), HuleType::Void)),
+            HuleExpression::Binary { left, operator, right } => {
+                let (left, left_type) = self.lower_expression(left, scope)?;
+                let (right, right_type) = self.lower_expression(right, scope)?;
+
+                let result_type = match (operator, &left_type, &right_type) {
+                    (Operator::Plus, HuleType::Int, HuleType::Int) => Some(HuleType::Int),
+                    (Operator::Plus, HuleType::String, HuleType::String) => Some(HuleType::String),
+                    (Operator::Minus | Operator::Multiply | Operator::Divide, HuleType::Int, HuleType::Int)
+                    => Some(HuleType::Int),
+                    (Operator::Equal | Operator::NotEqual, l, r)
+                    if l == r && matches!(l, HuleType::Int | HuleType::String | HuleType::Bool)
+                    => Some(HuleType::Bool),
+                    (Operator::GreaterThan | Operator::GreaterEqualThan
+                    | Operator::LowerThan | Operator::LowerEqualThan, HuleType::Int, HuleType::Int)
+                    => Some(HuleType::Bool),
+                    (Operator::And | Operator::Or, HuleType::Bool, HuleType::Bool) => Some(HuleType::Bool),
+                    _ => None,
+                };
+
+                match result_type {
+                    Some(result_type) => Ok((IrExpression::Binary {
+                        left: Box::new(left),
+                        operator: operator.clone(),
+                        right: Box::new(right),
+                    }, result_type)),
+                    None => Err(LoweringError::InvalidOperands {
+                        function: scope.name.clone(),
+                        left: left_type,
+                        operator: operator.clone(),
+                        right: right_type,
+                    }),
+                }
+            }
+        }
+    }
+}
+
+struct BlockBuilder<'a> {
+    program: &'a mut HuleProgram,
+    function: FunctionId,
+    blocks: Vec<(Vec<IrInstruction>, Option<Terminator>)>,
+    current: BlockId,
+    loop_exits: Vec<BlockId>,
+}
+
+impl<'a> BlockBuilder<'a> {
+    fn build(&mut self, body: &[IrStatement]) {
+        self.current = self.new_block();
+        for statement in body {
+            self.build_statement(statement);
+        }
+
+        // falling off the end of the function returns, the analyzer ensures
+        // this can only happen in void functions
+        let blocks = std::mem::take(&mut self.blocks);
+        let function = self.program.function_mut(self.function);
+        function.blocks = blocks.into_iter()
+            .map(|(instructions, terminator)| BasicBlock {
+                instructions,
+                terminator: terminator.unwrap_or(Terminator::Return(None)),
+            })
+            .collect();
+        function.remove_unreachable_blocks();
+    }
+
+    fn new_block(&mut self) -> BlockId {
+        self.blocks.push((vec![], None));
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn instructions(&mut self) -> &mut Vec<IrInstruction> {
+        &mut self.blocks[self.current.0].0
+    }
+
+    fn terminator(&mut self) -> &mut Option<Terminator> {
+        &mut self.blocks[self.current.0].1
+    }
+
+    /// Ends the current block, following instructions start a new block.
+    fn terminate(&mut self, terminator: Terminator) {
+        *self.terminator() = Some(terminator);
+        self.current = self.new_block();
+    }
+
+    fn build_statement(&mut self, statement: &IrStatement) {
+        match statement {
+            IrStatement::Assign(target, IrExpression::Call(function, arguments)) => {
+                self.build_call(*function, arguments, Some(target.clone()));
+            }
+            IrStatement::Assign(target, value) => {
+                let value = self.hoist_calls(value);
+                self.instructions().push(IrInstruction::Assign(target.clone(), value));
+            }
+            IrStatement::Eval(IrExpression::Call(function, arguments)) => {
+                self.build_call(*function, arguments, None);
+            }
+            IrStatement::Eval(value) => {
+                let value = self.hoist_calls(value);
+                self.instructions().push(IrInstruction::Eval(value));
+            }
+            IrStatement::If { condition, body, else_body } => {
+                let condition = self.hoist_calls(condition);
+                let then_block = self.new_block();
+                let else_block = self.new_block();
+                let join_block = self.new_block();
+                *self.terminator() = Some(Terminator::Branch { condition, then_block, else_block });
+
+                self.current = then_block;
+                for statement in body {
+                    self.build_statement(statement);
+                }
+                self.terminator().get_or_insert(Terminator::Goto(join_block));
+
+                self.current = else_block;
+                for statement in else_body {
+                    self.build_statement(statement);
+                }
+                self.terminator().get_or_insert(Terminator::Goto(join_block));
+
+                self.current = join_block;
+            }
+            IrStatement::While { condition, body } => {
+                let condition_block = self.new_block();
+                *self.terminator() = Some(Terminator::Goto(condition_block));
+
+                self.current = condition_block;
+                let condition = self.hoist_calls(condition);
+                let body_block = self.new_block();
+                let exit_block = self.new_block();
+                *self.terminator() = Some(Terminator::Branch {
+                    condition,
+                    then_block: body_block,
+                    else_block: exit_block,
+                });
+
+                self.loop_exits.push(exit_block);
+                self.current = body_block;
+                for statement in body {
+                    self.build_statement(statement);
+                }
+                self.terminator().get_or_insert(Terminator::Goto(condition_block));
+                self.loop_exits.pop();
+
+                self.current = exit_block;
+            }
+            IrStatement::Return(value) => {
+                let value = value.as_ref().map(|value| self.hoist_calls(value));
+                self.terminate(Terminator::Return(value));
+            }
+            IrStatement::Location(position) => self.instructions().push(IrInstruction::Location(*position)),
+            IrStatement::Break => {
+                if let Some(exit_block) = self.loop_exits.last() {
+                    self.terminate(Terminator::Goto(*exit_block));
+                }
+            }
+        }
+    }
+
+    fn build_call(&mut self, function: FunctionId, arguments: &[IrExpression], result: Option<IrTarget>) {
+        let arguments = self.hoist_operands(arguments);
+        let continuation = self.new_block();
+        *self.terminator() = Some(Terminator::Call { function, arguments, result, continuation });
+        self.current = continuation;
+    }
+
+    /// Moves calls of hyperULE functions out of the expression into their
+    /// own blocks and replaces them by the temporary holding their result.
+    fn hoist_calls(&mut self, expression: &IrExpression) -> IrExpression {
+        match expression {
+            IrExpression::Binary { left, operator: operator @ (Operator::And | Operator::Or), right } if right.contains_call() => {
+                self.short_circuit(left, operator, right)
+            }
+            IrExpression::Call(function, arguments) => {
+                let return_type = self.program.function(*function).return_type.clone();
+                let temporary = self.program.add_temporary(self.function, return_type);
+
+                self.build_call(*function, arguments, Some(IrTarget::Variable(temporary)));
+                IrExpression::Variable(temporary)
+            }
+            IrExpression::Builtin(name, arguments) => IrExpression::Builtin(name.clone(), self.hoist_operands(arguments)),
+            IrExpression::Binary { left, operator, right } => {
+                let mut operands = self.hoist_operands(&[(**left).clone(), (**right).clone()]).into_iter();
+                IrExpression::Binary {
+                    left: Box::new(operands.next().unwrap()),
+                    operator: operator.clone(),
+                    right: Box::new(operands.next().unwrap()),
+                }
+            }
+            _ => expression.clone(),
+        }
+    }
+
+    /// Hoists the calls of operands evaluated from left to right. A call may
+    /// write globals and device fields, so operands reading them before a
+    /// later call are stored in a temporary ahead of that call.
+    fn hoist_operands(&mut self, operands: &[IrExpression]) -> Vec<IrExpression> {
+        let mut result = vec![];
+        for (index, operand) in operands.iter().enumerate() {
+            let mut value = self.hoist_calls(operand);
+            if operands[index + 1..].iter().any(IrExpression::contains_call) && self.reads_shared(&value) {
+                let temporary = self.program.add_temporary(self.function, self.program.expression_type(&value));
+                self.instructions().push(IrInstruction::Assign(IrTarget::Variable(temporary), value));
+                value = IrExpression::Variable(temporary);
+            }
+            result.push(value);
+        }
+
+        result
+    }
+
+    /// Whether the expression reads a global or a device field.
+    fn reads_shared(&self, expression: &IrExpression) -> bool {
+        match expression {
+            IrExpression::External(_) => true,
+            IrExpression::Variable(variable) => self.program.globals().contains(variable),
+            IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments)
+            => arguments.iter().any(|argument| self.reads_shared(argument)),
+            IrExpression::Binary { left, right, .. } => self.reads_shared(left) || self.reads_shared(right),
+            _ => false,
+        }
+    }
+
+    /// Lowers `left && right` or `left || right` into a branch, so the calls
+    /// of `right` only run if `left` does not decide the result.
+    fn short_circuit(&mut self, left: &IrExpression, operator: &Operator, right: &IrExpression) -> IrExpression {
+        let left = self.hoist_calls(left);
+        let temporary = self.program.add_temporary(self.function, HuleType::Bool);
+        self.instructions().push(IrInstruction::Assign(IrTarget::Variable(temporary), left));
+
+        let right_block = self.new_block();
+        let join_block = self.new_block();
+        let (then_block, else_block) = match operator {
+            Operator::And => (right_block, join_block),
+            _ => (join_block, right_block),
+        };
+        *self.terminator() = Some(Terminator::Branch { condition: IrExpression::Variable(temporary), then_block, else_block });
+
+        self.current = right_block;
+        let right = self.hoist_calls(right);
+        self.instructions().push(IrInstruction::Assign(IrTarget::Variable(temporary), right));
+        *self.terminator() = Some(Terminator::Goto(join_block));
+
+        self.current = join_block;
+        IrExpression::Variable(temporary)
+    }
+}
+
+impl<'p> FunctionScope<'p> {
+    fn declare(&mut self, variable: HuleVariable) -> VarId {
+        let id = self.program.add_local(self.function, variable);
+        self.scopes.last_mut().unwrap().push(id);
+        id
+    }
+
+    fn resolve(&self, name: &str) -> Option<VarId> {
+        self.scopes.iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|id| self.program.variable(**id).name == name)
+            .copied()
+    }
+}
+
+fn resolve_type(name: &str) -> Result<HuleType, LoweringError> {
+    HuleType::from_name(name).ok_or_else(|| LoweringError::UnknownType(name.to_string()))
+}
+
+fn check_type(scope: &FunctionScope, context: &str, expected: &HuleType, found: &HuleType) -> Result<(), LoweringError> {
+    if expected != found {
+        return Err(LoweringError::TypeMismatch {
+            function: scope.name.clone(),
+            context: context.to_string(),
+            expected: expected.clone(),
+            found: found.clone(),
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> Result<HuleProgram, LoweringError> {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        lower(&ast)
+    }
+
+    #[test]
+    fn test_function_indices() {
+        let program = prepare_test("string greet(string name) { return name; } entry { OUT1.Data = greet(OUT1.Data); }").unwrap();
+
+        assert_eq!(program.function(FunctionId(0)).name, "_entry");
+        assert_eq!(program.function(FunctionId(1)).name, "greet");
+        assert_eq!(program.function_by_name("greet"), Some(FunctionId(1)));
+    }
+
+    #[test]
+    fn test_short_circuit_call() {
+        let program = prepare_test("bool f(int n) { return n > 0; } entry { int n = 0; if (n > 0 && f(n - 1)) { OUT1.Data = \"x\"; } }").unwrap();
+        let entry = program.function(FunctionId(0));
+
+        // the call is only reached if `n > 0`
+        let Terminator::Branch { condition, then_block, else_block } = &entry.blocks[0].terminator else {
+            panic!("branch expected");
+        };
+        assert!(matches!(condition, IrExpression::Variable(_)));
+        assert!(matches!(entry.blocks[then_block.0].terminator, Terminator::Call { function: FunctionId(1), .. }));
+        assert!(matches!(entry.blocks[else_block.0].terminator, Terminator::Branch { .. }));
+    }
+
+    #[test]
+    fn test_operand_read_before_call() {
+        let program = prepare_test("int g = 1; int f() { g = 10; return 1; } entry { int n = 2; OUT1.Data = IntToStr(g + f()) + IntToStr(n + f()); }").unwrap();
+        let entry = program.function(FunctionId(0));
+
+        // `g` is read before `f` writes it, the local `n` can not be written by `f`
+        let global = program.globals()[0];
+        assert!(entry.blocks[0].instructions.iter().any(|instruction| matches!(instruction,
+            IrInstruction::Assign(IrTarget::Variable(_), IrExpression::Variable(value)) if *value == global)));
+        assert!(matches!(entry.blocks[0].terminator, Terminator::Call { function: FunctionId(1), .. }));
+        assert!(!entry.blocks[1].instructions.iter().any(|instruction| matches!(instruction,
+            IrInstruction::Assign(IrTarget::Variable(_), IrExpression::Variable(_)))));
+    }
+
+    #[test]
+    fn test_call_in_global_initializer() {
+        assert_eq!(prepare_test("int one() { return 1; } int value = one(); entry { OUT1.Data = IntToStr(value); }").err(),
+            Some(LoweringError::CallInInitializer("value".to_string())));
+        assert!(prepare_test("string value = IntToStr(1); entry { OUT1.Data = value; }").is_ok());
+    }
+
+    #[test]
+    fn test_call_continuation() {
+        let program = prepare_test("string greet(string name) { return name; } entry { OUT1.Data = greet(OUT1.Data); }").unwrap();
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(entry.blocks.len(), 2);
+        assert_eq!(entry.blocks[0].terminator, Terminator::Call {
+            function: FunctionId(1),
+            arguments: vec![IrExpression::External("OUT1.Data".to_string())],
+            result: Some(IrTarget::External("OUT1.Data".to_string())),
+            continuation: BlockId(1),
+        });
+        assert_eq!(entry.blocks[1].terminator, Terminator::Return(None));
+    }
+
+    #[test]
+    fn test_nested_calls_use_temporaries() {
+        let program = prepare_test("int f(int a) { return a; } entry { OUT1.Data = IntToStr(f(1) + f(2)); }").unwrap();
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(entry.blocks.len(), 3);
+        assert_eq!(entry.locals.len(), 2);
+        assert_eq!(entry.blocks[2].instructions[0], IrInstruction::Assign(
+            IrTarget::External("OUT1.Data".to_string()),
+            IrExpression::Builtin("IntToStr".to_string(), vec![IrExpression::Binary {
+                left: Box::new(IrExpression::Variable(entry.locals[0])),
+                operator: Operator::Plus,
+                right: Box::new(IrExpression::Variable(entry.locals[1])),
+            }]),
+        ));
+        assert_eq!(program.variable(entry.locals[0]).owner, Some(FunctionId(0)));
+    }
+
+    #[test]
+    fn test_variable_ids() {
+        let program = prepare_test("int g = 1; int f(int a) { int b = a + g; return b; } entry { f(1); }").unwrap();
+        let function = program.function(FunctionId(1));
+
+        assert_eq!(function.parameters.len(), 1);
+        assert_eq!(program.variable(function.locals[0]).name, "b");
+        assert_eq!(function.blocks[0].instructions[0], IrInstruction::Assign(
+            IrTarget::Variable(function.locals[0]),
+            IrExpression::Binary {
+                left: Box::new(IrExpression::Variable(function.parameters[0])),
+                operator: Operator::Plus,
+                right: Box::new(IrExpression::Variable(program.globals()[0])),
+            },
+        ));
+    }
+
+    #[test]
+    fn test_program_is_mutable() {
+        let mut program = prepare_test("int f(int a) { return a; } entry { f(1); }").unwrap();
+        let temporary = program.add_temporary(FunctionId(1), HuleType::Int);
+        program.function_mut(FunctionId(1)).blocks[0].terminator = Terminator::Return(Some(IrExpression::Variable(temporary)));
+
+        assert_eq!(program.function(FunctionId(1)).locals, vec![temporary]);
+        assert!(program.to_ir_text().contains("return %_t"));
+    }
+
+    #[test]
+    fn test_ir_text_escapes_strings() {
+        let program = prepare_test("entry { OUT1.Data = \"say \\\"hi\\\"\\n\"; }").unwrap();
+
+        assert!(program.to_ir_text().contains("OUT1.Data = \"say \\\"hi\\\"\\x0A\"\n"));
+    }
+
+    #[test]
+    fn test_type_errors() {
+        assert!(matches!(prepare_test("entry { int a = \"x\"; }"), Err(LoweringError::TypeMismatch { .. })));
+        assert!(matches!(prepare_test("entry { OUT1.Data = \"a\" + 1; }"), Err(LoweringError::InvalidOperands { .. })));
+        assert!(matches!(prepare_test("entry { OUT1.Data = IntToStr(1, 2); }"), Err(LoweringError::ArgumentCount { .. })));
+        assert!(matches!(prepare_test("entry { x = 1; }"), Err(LoweringError::UndefinedVariable { .. })));
+    }
+}
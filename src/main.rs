@@ -2,16 +2,72 @@ mod ast;
 mod tokenizer;
 mod tokens;
 mod analyzer;
+mod callgraph;
+mod cfg;
+mod cli;
+mod concat;
+mod consteval;
+mod constfold;
+mod dataflow;
+mod dce;
 mod optimizer;
+mod forward;
+mod frames;
 mod generator;
+mod hoist;
+mod inline;
+mod literals;
+mod liveness;
+mod lowering;
+mod minify;
 mod parser;
+mod peephole;
 mod program;
+mod regalloc;
+mod simplifycfg;
+mod sourcemap;
+mod sizereport;
+mod tailcall;
 mod ule;
+mod writer;
 mod filebuilder;
 
+use std::fs;
+use std::process::exit;
+use analyzer::Analyzer;
+use cli::{CompilerOptions, EmitKind, MapLookup};
+use generator::HyperBackend;
+use optimizer::{OptimizerLevel, PassManager};
+use sizereport::SizeReport;
+use sourcemap::SourceMap;
 use parser::*;
+use ule::{UleBackend, UleOptions};
 
 fn main() {
+    let options = match CompilerOptions::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err.to_message());
+            exit(1);
+        }
+    };
+
+    if let Some(lookup) = &options.lookup {
+        if let Err(message) = lookup_position(lookup) {
+            eprintln!("{}", message);
+            exit(1);
+        }
+        return;
+    }
+
+    if let Some(input) = &options.input {
+        if let Err(message) = compile(&options, input) {
+            eprintln!("{}", message);
+            exit(1);
+        }
+        return;
+    }
+
     println!("hyperULE Compiler v0.1");
     println!("----------------------");
     println!("{}", concat!(
@@ -20,19 +76,6 @@ fn main() {
     "-i <input file> / -i <input_file> -o <output_file>."));
     println!("");
 
-
-    let input = "entry{string test = \"\";}";
-    let input = "int global = \"some global var\";entry{string test = \"\";}string greet(string name) {string result = \"hello \" + name + \"!!!\";}";
-    let mut parser = AstParser::new(&input);
-    match parser.parse(&input) {
-        Ok(ast) => {
-            println!("{:#?}", ast);
-        }
-        Err(err) => {
-            println!("Compilation failed during parsing: {}", err.to_message())
-        }
-    }
-
     loop {
         let mut input = String::new();
         match std::io::stdin().read_line(&mut input) {
@@ -52,13 +95,152 @@ fn main() {
                 println!("Compilation failed during parsing: {}", err.to_message())
             }
         }
+    }
+}
+
+fn compile(options: &CompilerOptions, input: &str) -> Result<(), String> {
+    let source = fs::read_to_string(input)
+        .map_err(|err| format!("Could not read '{}': {}", input, err))?;
+
+    let mut parser = AstParser::new(&source);
+    let ast = parser.parse(&source)
+        .map_err(|err| format!("Compilation failed during parsing: {}", err.to_message()))?;
+
+    if options.emit == EmitKind::Ast {
+        return write_output(options, &format!("{:#?}\n", ast));
+    }
 
+    let mut analyzer = Analyzer::new(ast);
+    analyzer.analyze()
+        .map_err(|err| format!("Compilation failed during analysis: {}", err.to_message()))?;
 
-        // let source_code = r#"class start comment/* test adasdasas */endcomment Example { int x = 10; }"#;
-        // let tokens = tokenizer.tokenize(&input);
-        // for mut token in tokens {
-        //     let test = token.get_cached_token_type().clone();
-        //     println!("{:?} = {:?}", token.value, test);
-        // }
+    for warning in analyzer.warnings() {
+        eprintln!("[Warning] {}", warning.to_message());
+    }
+
+    if options.warnings_as_errors && !analyzer.warnings().is_empty() {
+        return Err(format!("Compilation failed with {} warning(s) treated as errors.", analyzer.warnings().len()));
+    }
+
+    // the comments of the pretty style and the source map need the source
+    // locations, they never change the generated code
+    let lowered = if options.emit == EmitKind::Ule {
+        lowering::lower_with_locations(analyzer.program())
+    } else {
+        lowering::lower(analyzer.program())
+    };
+    let mut program = lowered
+        .map_err(|err| format!("Compilation failed during lowering: {}", err.to_message()))?;
+
+    let pass_manager = PassManager::new(options.optimizer_level, &options.pass_overrides, &options.print_after)
+        .map_err(|err| err.to_message())?;
+    if options.verbose {
+        eprintln!("[Info] Optimizer passes: {}", pass_manager.pass_names().join(", "));
+    }
+
+    let mut notes = vec![];
+    for (pass, ir) in pass_manager.run(&mut program, &mut notes) {
+        eprintln!("; IR after {}", pass);
+        eprint!("{}", ir);
+    }
+
+    if options.verbose {
+        for note in notes {
+            eprintln!("[Info] {}", note);
+        }
+    }
+
+    match options.emit {
+        EmitKind::CallGraph => {
+            let call_graph = analyzer.call_graph().expect("call graph is built by analyze");
+            write_output(options, &call_graph.to_dot())
+        }
+        EmitKind::Ir => write_output(options, &program.to_ir_text()),
+        EmitKind::Ule => {
+            let backend = UleBackend::new(UleOptions {
+                peephole: options.optimizer_level != OptimizerLevel::O0,
+                minify: options.minify,
+                frame_width: options.frame_width,
+                heap_size: options.heap_size.unwrap_or(ule::DEFAULT_HEAP_SIZE),
+                heap_error: options.heap_error.clone(),
+                style: options.ule_style,
+                file: input.to_string(),
+            });
+            let output = backend.generate(&program)
+                .map_err(|err| format!("Compilation failed during code generation: {}", err.to_message()))?;
+
+            if options.size_report || options.max_size.is_some() {
+                let report = SizeReport::measure(&output.sizes);
+                if options.size_report {
+                    eprint!("{}", report.to_text());
+                }
+                if let Some(budget) = options.max_size {
+                    report.check_budget(budget)
+                        .map_err(|err| format!("Compilation failed: {}", err.to_message()))?;
+                }
+            }
+
+            if options.verbose {
+                if output.frame_width > 0 {
+                    eprintln!("[Info] call stack fields are {} digits wide", output.frame_width);
+                }
+                for (rule, count) in &output.peephole.applied {
+                    eprintln!("[Info] peephole rule '{}' applied {} time(s)", rule, count);
+                }
+            }
+            if let Some(names) = &output.names {
+                match &options.output {
+                    Some(output) => {
+                        let map = format!("{}.names", output);
+                        fs::write(&map, names.to_map_text())
+                            .map_err(|err| format!("Could not write '{}': {}", map, err))?;
+                    }
+                    // stdout is the ULE code, the names go to stderr
+                    None => eprint!("[Info] minified names:\n{}", names.to_map_text()),
+                }
+            }
+
+            if let Some(map) = &options.source_map {
+                fs::write(map, output.source_map.to_map_text())
+                    .map_err(|err| format!("Could not write '{}': {}", map, err))?;
+            }
+
+            write_output(options, &output.source)
+        }
+        EmitKind::Ast => Ok(()),
+    }
+}
+
+/// Prints the hyperULE positions the ULE position was generated from.
+fn lookup_position(lookup: &MapLookup) -> Result<(), String> {
+    let text = fs::read_to_string(&lookup.map)
+        .map_err(|err| format!("Could not read '{}': {}", lookup.map, err))?;
+    let map = SourceMap::parse(&text).map_err(|err| err.to_message())?;
+
+    let position = match lookup.column {
+        Some(column) => format!("position {}:{}", lookup.line, column),
+        None => format!("line {}", lookup.line),
+    };
+    if !map.contains(lookup.line, lookup.column) {
+        return Err(format!("ULE {} is outside of the mapped output, which ends at {}:{}.", position, map.end.line, map.end.column));
+    }
+    let positions = map.lookup(lookup.line, lookup.column);
+    if positions.is_empty() {
+        return Err(format!("ULE {} is runtime code and belongs to no hyperULE statement.", position));
+    }
+    for position in positions {
+        println!("{}:{}:{}", map.file, position.line, position.column);
+    }
+    Ok(())
+}
+
+fn write_output(options: &CompilerOptions, content: &str) -> Result<(), String> {
+    match &options.output {
+        Some(output) => fs::write(output, content)
+            .map_err(|err| format!("Could not write '{}': {}", output, err)),
+        None => {
+            print!("{}", content);
+            Ok(())
+        }
     }
 }
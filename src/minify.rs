@@ -0,0 +1,146 @@
+//! hyperULE Minifier
+//!
+//! Renames the runtime and user variables of the generated ULE code to the
+//! shortest unique identifiers. The most frequently used variables get the
+//! shortest names. Fields of the device like `OUT1.Data` keep their name.
+//!
+//! The returned `MinifyMap` is written next to the output, so a minified
+//! script can still be debugged.
+
+use std::collections::HashMap;
+use crate::ule::UleNode;
+
+/// Keywords and built-in functions of ULE, compared case insensitively.
+const RESERVED_NAMES: &[&str] = &[
+    "if", "else", "while", "for", "do", "break", "continue", "return", "true", "false",
+    "and", "or", "not", "var", "function",
+    "strlen", "left", "right", "mid", "inttostr", "strtoint",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct MinifyMap {
+    /// Pairs of minified and original name, in order of the minified names
+    names: Vec<(String, String)>,
+}
+
+impl MinifyMap {
+    /// Content of the mapping file, one `<minified> = <original>` per line.
+    pub fn to_map_text(&self) -> String {
+        self.names.iter()
+            .map(|(minified, original)| format!("{} = {}\n", minified, original))
+            .collect()
+    }
+}
+
+pub fn is_reserved(name: &str) -> bool {
+    RESERVED_NAMES.contains(&name.to_lowercase().as_str())
+}
+
+/// Identifiers in order of their length: a, b, ..., z, aa, ab, ...
+fn identifier(mut index: usize) -> String {
+    let mut result = vec![];
+    loop {
+        result.push(b'a' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+
+    result.reverse();
+    String::from_utf8(result).unwrap()
+}
+
+pub fn minify(nodes: &mut [UleNode]) -> MinifyMap {
+    // usage count and first occurrence of every variable
+    let mut usages: HashMap<String, (usize, usize)> = HashMap::new();
+    for node in nodes.iter_mut() {
+        node.visit_variables_mut(&mut |name| {
+            if !name.contains('.') {
+                let next = usages.len();
+                usages.entry(name.clone()).or_insert((0, next)).0 += 1;
+            }
+        });
+    }
+
+    let mut variables: Vec<(String, (usize, usize))> = usages.into_iter().collect();
+    variables.sort_by_key(|(_, (count, first))| (std::cmp::Reverse(*count), *first));
+
+    let mut names = (0..).map(identifier).filter(|name| !is_reserved(name));
+    let renames: HashMap<String, String> = variables.into_iter()
+        .map(|(original, _)| (original, names.next().unwrap()))
+        .collect();
+
+    for node in nodes.iter_mut() {
+        node.visit_variables_mut(&mut |name| {
+            if let Some(minified) = renames.get(name) {
+                *name = minified.clone();
+            }
+        });
+    }
+
+    let mut names: Vec<(String, String)> = renames.into_iter()
+        .map(|(original, minified)| (minified, original))
+        .collect();
+    names.sort_by(|(a, _), (b, _)| a.len().cmp(&b.len()).then(a.cmp(b)));
+    MinifyMap { names }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Operator;
+    use crate::ule::{UleExpression, UleVarDef};
+
+    fn assign(name: &str, value: UleExpression) -> UleNode {
+        UleNode::VariableDefinition(UleVarDef { name: name.to_string(), value })
+    }
+
+    fn variable(name: &str) -> UleExpression {
+        UleExpression::Variable(name.to_string())
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(identifier(0), "a");
+        assert_eq!(identifier(25), "z");
+        assert_eq!(identifier(26), "aa");
+        assert_eq!(identifier(27), "ab");
+        assert_eq!(identifier(26 + 26 * 26), "aaa");
+        assert!(is_reserved("IF"));
+    }
+
+    #[test]
+    fn test_most_used_variables_get_shortest_names() {
+        let mut nodes = vec![
+            assign("message", UleExpression::String("x".to_string())),
+            assign("callStack", variable("OUT1.Data")),
+            assign("OUT1.Data", UleExpression::Binary {
+                left: Box::new(variable("callStack")),
+                operator: Operator::Plus,
+                right: Box::new(variable("message")),
+            }),
+            assign("callStack", variable("callStack")),
+        ];
+
+        let map = minify(&mut nodes);
+
+        assert_eq!(nodes[0], assign("b", UleExpression::String("x".to_string())));
+        assert_eq!(nodes[1], assign("a", variable("OUT1.Data")));
+        assert_eq!(map.to_map_text(), "a = callStack\nb = message\n");
+    }
+
+    #[test]
+    fn test_reserved_names_are_skipped() {
+        let mut nodes: Vec<UleNode> = (0..26 * 10)
+            .map(|index| assign(&format!("v{}", index), UleExpression::Integer(0)))
+            .collect();
+
+        let map = minify(&mut nodes);
+
+        let minified: Vec<&str> = map.names.iter().map(|(name, _)| name.as_str()).collect();
+        assert!(!minified.contains(&"if"));
+        assert!(!minified.contains(&"do"));
+        assert!(minified.contains(&"ie"));
+    }
+}
@@ -1,23 +1,37 @@
-/// hyperULE Compiler
-/// Code Optimizer
-///
-/// Program Size Optimizer
-/// - Minify / obfuscate variables
-/// - Move duplicate constant values in to variables
-/// - Remove duplicates from single-pass code generation like:
-///   var + "" + var2 + ""    ->    var + "" + var2
-/// - Remove dead code
-///
-/// RAM Optimizer:
-/// - Move constant, single use variables in to constant expressions
-///
-/// Flash Memory Optimizer
-/// -
-///
-
-use std::rc::{Rc, Weak};
+//! hyperULE Compiler
+//! Code Optimizer
+//!
+//! Every optimization is a named `OptimizerPass` working on the
+//! `HuleProgram` IR. The `PassManager` runs the passes of an
+//! `OptimizerLevel` in a fixed order, single passes can be enabled or
+//! disabled with `-Cpass=+name,-name`.
+//!
+//! Program Size Optimizer
+//! - Minify / obfuscate variables
+//! - Move duplicate constant values in to variables
+//! - Remove duplicates from single-pass code generation like:
+//!   var + "" + var2 + ""    ->    var + "" + var2
+//! - Remove dead code
+//!
+//! RAM Optimizer:
+//! - Move constant, single use variables in to constant expressions
+//!
+//! Flash Memory Optimizer
+//! -
+
+use crate::concat::ConcatSimplificationPass;
+use crate::constfold::ConstantFoldingPass;
+use crate::dce::DeadCodeEliminationPass;
+use crate::forward::ForwardingPass;
+use crate::frames::StaticFramesPass;
+use crate::hoist::LiteralHoistingPass;
+use crate::inline::InlinePass;
 use crate::program::HuleProgram;
+use crate::regalloc::RegisterAllocationPass;
+use crate::simplifycfg::SimplifyCfgPass;
+use crate::tailcall::TailCallPass;
 
+#[derive(Clone, Copy, PartialEq, Debug)]
 pub enum OptimizerLevel {
     O0,
     O1,
@@ -25,40 +39,186 @@ pub enum OptimizerLevel {
     O3
 }
 
+impl OptimizerLevel {
+    pub fn from_name(name: &str) -> Option<OptimizerLevel> {
+        match name {
+            "0" => Some(OptimizerLevel::O0),
+            "1" => Some(OptimizerLevel::O1),
+            "2" => Some(OptimizerLevel::O2),
+            "3" => Some(OptimizerLevel::O3),
+            _ => None,
+        }
+    }
+
+    /// Names of the passes run at this level, in execution order.
+    pub fn passes(&self) -> Vec<&'static str> {
+        match self {
+            OptimizerLevel::O0 => vec![],
+            OptimizerLevel::O1 => vec!["inline", "tail-calls", "const-fold", "dce", "simplify-cfg", "concat"],
+            OptimizerLevel::O2 => vec!["inline", "tail-calls", "const-fold", "dce", "simplify-cfg", "concat", "forward", "hoist", "regalloc"],
+            OptimizerLevel::O3 => vec!["inline", "tail-calls", "static-frames", "const-fold", "dce", "simplify-cfg", "concat", "forward", "hoist", "regalloc"],
+        }
+    }
+}
+
+pub trait OptimizerPass {
+    fn name(&self) -> &'static str;
+    /// Optimizes the program. `notes` collects what the pass did, they are
+    /// reported with `--verbose`.
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>);
+}
 
-pub trait HuleOptimizer<T> {
-    fn new(program: &Rc<HuleProgram>) -> T;
-    fn optimize(&self, program: HuleProgram);
+/// Every known pass. Passes always run in this order, no matter if they are
+/// enabled by the optimizer level or by `-Cpass`.
+fn all_passes() -> Vec<Box<dyn OptimizerPass>> {
+    vec![
+        Box::new(InlinePass),
+        Box::new(TailCallPass),
+        Box::new(StaticFramesPass),
+        Box::new(ConstantFoldingPass),
+        Box::new(DeadCodeEliminationPass),
+        Box::new(SimplifyCfgPass),
+        Box::new(ConcatSimplificationPass),
+        Box::new(ForwardingPass),
+        Box::new(LiteralHoistingPass),
+        Box::new(RegisterAllocationPass),
+    ]
 }
 
-struct FirstLevelOptimizer {
-    program: Weak<HuleProgram>
+#[derive(Clone, PartialEq, Debug)]
+pub enum PassOverride {
+    Enable(String),
+    Disable(String),
 }
 
-impl HuleOptimizer<FirstLevelOptimizer> for FirstLevelOptimizer {
-    fn new(program: &Rc<HuleProgram>) -> FirstLevelOptimizer {
-        FirstLevelOptimizer {
-            program: Rc::downgrade(program)
+impl PassOverride {
+    fn name(&self) -> &str {
+        match self {
+            PassOverride::Enable(name) | PassOverride::Disable(name) => name,
         }
     }
+}
 
-    fn optimize(&self, program: HuleProgram) {
+#[derive(Debug, PartialEq)]
+pub enum OptimizerError {
+    UnknownPass(String),
+    /// `--print-after` names a pass which is not enabled
+    PassNotRun(String),
+}
 
+impl OptimizerError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            OptimizerError::UnknownPass(name) => {
+                let names: Vec<&str> = all_passes().iter().map(|p| p.name()).collect();
+                format!("Unknown optimizer pass '{}', expected one of: {}.", name, names.join(", "))
+            }
+            OptimizerError::PassNotRun(name)
+            => format!("Pass '{}' is not enabled, enable it with -Cpass=+{} to print the IR after it.", name, name),
+        }
     }
 }
 
-struct SecondLevelOptimizer {
-    first_level_optimizer: FirstLevelOptimizer
+pub struct PassManager {
+    passes: Vec<Box<dyn OptimizerPass>>,
+    print_after: Vec<String>,
 }
 
-struct ThirdLevelOptimizer {
-    first_level_optimizer: FirstLevelOptimizer,
-    second_level_optimizer: SecondLevelOptimizer
+impl PassManager {
+    pub fn new(level: OptimizerLevel, overrides: &[PassOverride], print_after: &[String]) -> Result<PassManager, OptimizerError> {
+        let known = all_passes();
+        for name in overrides.iter().map(|o| o.name()).chain(print_after.iter().map(|n| n.as_str())) {
+            if !known.iter().any(|p| p.name() == name) {
+                return Err(OptimizerError::UnknownPass(name.to_string()));
+            }
+        }
+
+        let level_passes = level.passes();
+        let passes = known.into_iter()
+            .filter(|pass| {
+                // the last override of a pass wins
+                match overrides.iter().rev().find(|o| o.name() == pass.name()) {
+                    Some(PassOverride::Enable(_)) => true,
+                    Some(PassOverride::Disable(_)) => false,
+                    None => level_passes.contains(&pass.name()),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(name) = print_after.iter().find(|name| !passes.iter().any(|p| p.name() == name.as_str())) {
+            return Err(OptimizerError::PassNotRun(name.to_string()));
+        }
+
+        Ok(PassManager {
+            passes,
+            print_after: print_after.to_vec(),
+        })
+    }
+
+    pub fn pass_names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|p| p.name()).collect()
+    }
+
+    /// Runs all enabled passes and returns the IR dumps requested by
+    /// `--print-after` together with the name of their pass. The notes of
+    /// every pass are prefixed with its name.
+    pub fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) -> Vec<(&'static str, String)> {
+        let mut dumps = vec![];
+        for pass in &self.passes {
+            let mut pass_notes = vec![];
+            pass.run(program, &mut pass_notes);
+            notes.extend(pass_notes.into_iter().map(|note| format!("{}: {}", pass.name(), note)));
+
+            if self.print_after.iter().any(|name| name == pass.name()) {
+                dumps.push((pass.name(), program.to_ir_text()));
+            }
+        }
+
+        dumps
+    }
 }
 
-impl HuleProgram {
-    // pub fn optimize(self) -> HuleProgram {
-    //     let result = *self.clone();
-    //     result
-    // }
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level_passes() {
+        let manager = PassManager::new(OptimizerLevel::O0, &[], &[]).unwrap();
+        assert!(manager.pass_names().is_empty());
+
+        let manager = PassManager::new(OptimizerLevel::O1, &[], &[]).unwrap();
+        assert_eq!(manager.pass_names(), vec!["inline", "tail-calls", "const-fold", "dce", "simplify-cfg", "concat"]);
+    }
+
+    #[test]
+    fn test_pass_overrides() {
+        let enable = [PassOverride::Enable("simplify-cfg".to_string())];
+        let manager = PassManager::new(OptimizerLevel::O0, &enable, &[]).unwrap();
+        assert_eq!(manager.pass_names(), vec!["simplify-cfg"]);
+
+        let disable = [PassOverride::Disable("simplify-cfg".to_string())];
+        let manager = PassManager::new(OptimizerLevel::O3, &disable, &[]).unwrap();
+        assert_eq!(manager.pass_names(), vec!["inline", "tail-calls", "static-frames", "const-fold", "dce", "concat", "forward", "hoist", "regalloc"]);
+    }
+
+    #[test]
+    fn test_unknown_pass() {
+        let overrides = [PassOverride::Enable("unroll".to_string())];
+        assert!(matches!(PassManager::new(OptimizerLevel::O1, &overrides, &[]),
+            Err(OptimizerError::UnknownPass(name)) if name == "unroll"));
+        assert!(PassManager::new(OptimizerLevel::O1, &[], &["unroll".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_print_after_disabled_pass() {
+        let print_after = ["forward".to_string()];
+        assert_eq!(PassManager::new(OptimizerLevel::O1, &[], &print_after).err(),
+            Some(OptimizerError::PassNotRun("forward".to_string())));
+
+        let disable = [PassOverride::Disable("forward".to_string())];
+        assert_eq!(PassManager::new(OptimizerLevel::O2, &disable, &print_after).err(),
+            Some(OptimizerError::PassNotRun("forward".to_string())));
+        assert!(PassManager::new(OptimizerLevel::O2, &[], &print_after).is_ok());
+    }
+}
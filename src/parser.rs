@@ -4,6 +4,8 @@ use std::string::ParseError;
 use crate::tokenizer::{Tokenized, Tokenizer};
 use crate::ast::*;
 use crate::tokens::{Token, TokenType};
+use crate::literals::unescape_string;
+use crate::consteval::ConstEvalError;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum AstParserError {
@@ -13,6 +15,7 @@ pub enum AstParserError {
     TokenExpected(String, String),
     SomeTokenExpected(Vec<String>, String),
     StatementExpected(String),
+    InvalidInteger(String, ConstEvalError),
 
     IncompatibleStatement
 }
@@ -26,6 +29,8 @@ impl AstParserError {
             => format!("Token '{}' expected but '{}' given.", expected, given),
             AstParserError::SomeTokenExpected(_, _) => "SomeTokenExpected".to_string(),
             AstParserError::StatementExpected(_) => "StatementExpected".to_string(),
+            AstParserError::InvalidInteger(literal, err)
+            => format!("Integer literal '{}' is invalid: {}", literal, err.to_message()),
             AstParserError::IncompatibleStatement => "IncompatibleStatement".to_string(),
         }
     }
@@ -34,6 +39,9 @@ impl AstParserError {
 pub struct AstParser {
     pub(crate) tokens : Tokenized,
     source : String,
+    /// Invalid integer literal, statements are parsed by trying every kind
+    /// of statement, so the error is kept until the end of the parse
+    integer_error: Option<AstParserError>,
 
 }
 
@@ -99,12 +107,63 @@ impl AstParser {
         }
 
         self.expect_token_type(TokenType::CurlyBracketClose)?;
+
+        let mut else_body = None;
+        if self.expect_token_value("else".to_string()).is_ok() {
+            let else_index = self.tokens.get_current_token_index();
+            let else_if = self.try_parse_if_statement()
+                .or_reset(self, else_index);
+
+            else_body = match else_if {
+                Ok(statement) => Some(Box::new(statement)),
+                Err(AstParserError::IncompatibleStatement) => {
+                    self.expect_token_type(TokenType::CurlyBracketOpen)?;
+                    let parsed_else_body = self.try_parse_local_body()?;
+                    self.expect_token_type(TokenType::CurlyBracketClose)?;
+                    Some(Box::new(parsed_else_body))
+                }
+                Err(err) => return Err(err),
+            };
+        }
+
         Ok(HuleStatement::IfStatement(HuleIfStatement {
             condition,
-            body: Box::new(parsed_body.unwrap())
+            body: Box::new(parsed_body.unwrap()),
+            else_body,
         }))
     }
 
+    /// While-Loop
+    /// while <bracket_open_token> <expression> <bracket_close_token> <body>
+    fn try_parse_while_loop(&mut self) -> Result<HuleStatement, AstParserError> {
+        self.expect_token_value("while".to_string())
+            .map_err(|_| AstParserError::IncompatibleStatement)?;
+
+        self.expect_token_type(TokenType::BracketOpen)?;
+        let condition = self.try_parse_expression()?;
+        self.expect_token_type(TokenType::BracketClose)?;
+
+        self.expect_token_type(TokenType::CurlyBracketOpen)?;
+        let body = self.try_parse_local_body()?;
+        self.expect_token_type(TokenType::CurlyBracketClose)?;
+
+        Ok(HuleStatement::WhileLoop(HuleWhileLoop {
+            condition,
+            body: Box::new(body),
+        }))
+    }
+
+    /// Break-Statement
+    /// break <semicolon>
+    fn try_parse_break(&mut self) -> Result<HuleStatement, AstParserError> {
+        self.expect_token_value("break".to_string())
+            .map_err(|_| AstParserError::IncompatibleStatement)?;
+
+        self.expect_token_type(TokenType::Semicolon)?;
+
+        Ok(HuleStatement::Break)
+    }
+
     /// Bracket-Expression
     /// <bracket_open_token> <expression> <bracket_close_token>
     fn try_parse_bracket_expression(&mut self) -> Result<HuleExpression, AstParserError> {
@@ -114,12 +173,47 @@ impl AstParser {
             .or_reset(self, start_index)
             .or_else(|_| Err(AstParserError::IncompatibleStatement))?;
 
-        let result = self.try_parse_expression()
-            .or_else(|_| Err(AstParserError::IncompatibleStatement))?;
+        let result = self.try_parse_expression()?;
 
         self.expect_token_type(TokenType::BracketClose)?;
 
-        Ok(result)
+        Ok(HuleExpression::Bracketed(Box::new(result)))
+    }
+
+    /// Primary-Expression
+    /// <bracket_expression> | <minus> <primary_expression> | <simple_expression>
+    fn try_parse_primary_expression(&mut self) -> Result<HuleExpression, AstParserError> {
+        let start_index = self.tokens.get_current_token_index();
+
+        if self.expect_token_type(TokenType::BracketOpen).is_ok() {
+            self.tokens.set_current_token_index(start_index);
+            return self.try_parse_bracket_expression();
+        }
+        self.tokens.set_current_token_index(start_index);
+
+        if self.expect_token_type(TokenType::Minus).is_ok() {
+            // the sign belongs to the literal, otherwise i32::MIN overflows
+            let literal_index = self.tokens.get_current_token_index();
+            if let Some(token) = self.tokens.next() {
+                if token.get_calculated_token_type() == TokenType::ConstIntegerExpression {
+                    let literal = format!("-{}", token.value);
+                    return self.parse_integer_literal(literal);
+                }
+            }
+            self.tokens.set_current_token_index(literal_index);
+
+            return match self.try_parse_primary_expression()? {
+                HuleExpression::Integer(value) if value != i32::MIN => Ok(HuleExpression::Integer(-value)),
+                expression => Ok(HuleExpression::Binary {
+                    left: Box::new(HuleExpression::Integer(0)),
+                    operator: Operator::Minus,
+                    right: Box::new(expression),
+                }),
+            };
+        }
+        self.tokens.set_current_token_index(start_index);
+
+        self.try_parse_simple_expression()
     }
 
     fn try_parse_simple_expression(&mut self) -> Result<HuleExpression, AstParserError> {
@@ -127,9 +221,28 @@ impl AstParser {
 
         if let Some(current_token) = self.tokens.next() {
             match current_token.get_calculated_token_type() {
-                TokenType::Identifier => Ok(HuleExpression::Identifier(current_token.value.clone())),
-                TokenType::ConstStringExpression => Ok(HuleExpression::String(current_token.value.clone())),
-                TokenType::ConstIntegerExpression => Ok(HuleExpression::Integer(current_token.value.parse().unwrap())),
+                TokenType::Identifier => {
+                    let name = current_token.value.clone();
+                    if self.expect_token_type(TokenType::BracketOpen).is_ok() {
+                        let params = self.try_parse_func_call_params()?;
+                        self.expect_token_type(TokenType::BracketClose)?;
+                        return Ok(HuleExpression::Call(name, params));
+                    }
+
+                    match name.as_str() {
+                        "true" => Ok(HuleExpression::Boolean(true)),
+                        "false" => Ok(HuleExpression::Boolean(false)),
+                        _ => Ok(HuleExpression::Identifier(name)),
+                    }
+                },
+                TokenType::ConstStringExpression => {
+                    let value = &current_token.value;
+                    Ok(HuleExpression::String(unescape_string(&value[1..value.len() - 1])))
+                },
+                TokenType::ConstIntegerExpression => {
+                    let literal = current_token.value.clone();
+                    self.parse_integer_literal(literal)
+                },
                 _ => {
                     self.tokens.set_current_token_index(current_index);
                     Err(AstParserError::IncompatibleStatement)
@@ -141,6 +254,17 @@ impl AstParser {
         }
     }
 
+    fn parse_integer_literal(&mut self, literal: String) -> Result<HuleExpression, AstParserError> {
+        match literal.parse::<i32>() {
+            Ok(value) => Ok(HuleExpression::Integer(value)),
+            Err(_) => {
+                let err = AstParserError::InvalidInteger(literal, ConstEvalError::Overflow);
+                self.integer_error = Some(err.clone());
+                Err(err)
+            }
+        }
+    }
+
     fn try_parse_binary_operator(&mut self) -> Result<Operator, AstParserError> {
         let current_index = self.tokens.get_current_token_index();
 
@@ -166,53 +290,39 @@ impl AstParser {
         }
     }
 
-    fn try_parse_binary_expression(&mut self, left_side: HuleExpression) -> Result<HuleExpression, AstParserError>  {
-        let current_index = self.tokens.get_current_token_index();
-
-        let operator = self.try_parse_binary_operator()
-            .or_reset(self, current_index)?;
-
-        let right_side = self.try_parse_expression()
-            .or_reset(self, current_index)?;
+    /// Binary-Expression
+    /// <primary_expression> { <operator> <primary_expression> }
+    ///
+    /// Operators bind according to `Operator::precedence`, operators of the
+    /// same precedence are left associative.
+    fn try_parse_binary_expression(&mut self, min_precedence: u8) -> Result<HuleExpression, AstParserError>  {
+        let mut left_side = self.try_parse_primary_expression()?;
 
-        Ok(HuleExpression::Binary {
-            left: Box::new(left_side),
-            right: Box::new(right_side),
-            operator: operator.clone(),
-        })
-    }
-    fn try_parse_expression(&mut self) -> Result<HuleExpression, AstParserError> {
-        let mut current_index = self.tokens.get_current_token_index();
-
-        let mut result = HuleExpression::Undefined;
+        loop {
+            let current_index = self.tokens.get_current_token_index();
 
-        let bracket = self.expect_token_type(TokenType::BracketOpen);
+            let operator = match self.try_parse_binary_operator() {
+                Ok(operator) if operator.precedence() >= min_precedence => operator,
+                _ => {
+                    self.tokens.set_current_token_index(current_index);
+                    break;
+                }
+            };
 
-        if let Ok(expr) = self.try_parse_simple_expression().or_reset(self, current_index) {
-            current_index = self.tokens.get_current_token_index();
-            let binary_expression = self.try_parse_binary_expression(expr.clone())
-                .or_reset(self, current_index)
-                .unwrap_or(HuleExpression::Undefined);
+            let right_side = self.try_parse_binary_expression(operator.precedence() + 1)?;
 
-            if binary_expression == HuleExpression::Undefined {
-                result = expr;
-            } else {
-                result = binary_expression;
-            }
+            left_side = HuleExpression::Binary {
+                left: Box::new(left_side),
+                right: Box::new(right_side),
+                operator,
+            };
         }
 
-        if let(parsed_result) = result.clone() { // clone right??
-            if bracket.is_ok() {
-                //bracket
-                result = HuleExpression::Bracketed(Box::new(result));
-
-                self.expect_token_type(TokenType::BracketClose)?;
-            }
-
-            return Ok(result);
-        }
+        Ok(left_side)
+    }
 
-        Err(AstParserError::IncompatibleStatement)
+    fn try_parse_expression(&mut self) -> Result<HuleExpression, AstParserError> {
+        self.try_parse_binary_expression(0)
     }
 
 
@@ -224,40 +334,99 @@ impl AstParser {
     //         //.or_reset(self, current_index).or_else(|_| self.try_parse_simple_expression())
     // }
 
+    /// Variable-Declaration
+    /// [const] <type> <identifier> [<square_bracket_open> <expression> <square_bracket_close>]
+    ///     [<assign> <expression>] <semicolon>
     fn try_parse_var_decl(&mut self) -> Result<HuleStatement, AstParserError> {
+        let is_const = self.expect_token_value("const".to_string()).is_ok();
+
         let var_type = self.expect_token_type(TokenType::Identifier)
             .map_err(|_| AstParserError::IncompatibleStatement)?;
 
         let var_name = self.expect_token_type(TokenType::Identifier)?;
 
+        let mut array_size = None;
+        if self.expect_token_type(TokenType::SquareBracketOpen).is_ok() {
+            array_size = Some(self.try_parse_expression()?);
+            self.expect_token_type(TokenType::SquareBracketClose)?;
+        }
+
         let mut statement_type_token = self.expect_token_type(TokenType::Semicolon)
             .or_else(|_| self.expect_token_type(TokenType::Assign))?;
 
         let mut var_value = None;
         if statement_type_token.get_token_type() == TokenType::Assign {
             var_value = Some(self.try_parse_expression()?);
+            self.expect_token_type(TokenType::Semicolon)?;
         }
 
-        self.expect_token_type(TokenType::Semicolon)?;
-
         Ok(HuleStatement::VariableDecl(HuleVariableDecl {
             data_type: var_type.value,
             name: var_name.value,
-            value: var_value
+            value: var_value,
+            is_const,
+            array_size,
+        }))
+    }
+
+    /// Return-Statement
+    /// return <expression> <semicolon>
+    fn try_parse_return(&mut self) -> Result<HuleStatement, AstParserError> {
+        self.expect_token_value("return".to_string())
+            .map_err(|_| AstParserError::IncompatibleStatement)?;
+
+        let value = if self.expect_token_type(TokenType::Semicolon).is_ok() {
+            self.tokens.prev();
+            HuleExpression::Undefined
+        } else {
+            self.try_parse_expression()?
+        };
+
+        self.expect_token_type(TokenType::Semicolon)?;
+
+        Ok(HuleStatement::Return(value))
+    }
+
+    /// Assignment
+    /// <identifier> <assign> <expression> <semicolon>
+    fn try_parse_var_def(&mut self) -> Result<HuleStatement, AstParserError> {
+        let var_name = self.expect_token_type(TokenType::Identifier)
+            .map_err(|_| AstParserError::IncompatibleStatement)?;
+
+        self.expect_token_type(TokenType::Assign)
+            .map_err(|_| AstParserError::IncompatibleStatement)?;
+
+        let value = self.try_parse_expression()?;
+
+        self.expect_token_type(TokenType::Semicolon)?;
+
+        Ok(HuleStatement::VariableDef(HuleVariableDef {
+            name: var_name.value,
+            value,
         }))
     }
 
     fn try_parse_local_body(&mut self) -> Result<HuleStatement, AstParserError> {
         let mut result : Vec<HuleStatement> = vec![];
+        let mut positions = vec![];
         let mut last_error = AstParserError::IncompatibleStatement;
 
         loop {
+            let current_index = self.tokens.get_current_token_index();
+            let position = self.tokens.next_position();
+
             let mut parsed = self.try_parse_if_statement()
-                .or_else(|_| self.try_parse_var_decl())
-                .or_else(|_| self.try_parse_func_call());
+                .or_reset(self, current_index).or_else(|_| self.try_parse_while_loop())
+                .or_reset(self, current_index).or_else(|_| self.try_parse_return())
+                .or_reset(self, current_index).or_else(|_| self.try_parse_break())
+                .or_reset(self, current_index).or_else(|_| self.try_parse_var_decl())
+                .or_reset(self, current_index).or_else(|_| self.try_parse_var_def())
+                .or_reset(self, current_index).or_else(|_| self.try_parse_func_call())
+                .or_reset(self, current_index);
 
             if let Ok(statement) = parsed {
                 result.push(statement);
+                positions.push(position.expect("a parsed statement starts with a token"));
             } else {
                 last_error = parsed.unwrap_err();
                 break;
@@ -268,7 +437,7 @@ impl AstParser {
             return Err(last_error);
         }
 
-        Ok(HuleStatement::Body(HuleBody::new(result)))
+        Ok(HuleStatement::Body(HuleBody::with_positions(result, positions)))
     }
 
     fn try_parse_func_call(&mut self) -> Result<HuleStatement, AstParserError> {
@@ -282,7 +451,8 @@ impl AstParser {
         let params = self.try_parse_func_call_params()
             .or_reset(self, start_index)?;
 
-        self.expect_token_type(TokenType::BracketOpen)?;
+        self.expect_token_type(TokenType::BracketClose)?;
+        self.expect_token_type(TokenType::Semicolon)?;
 
         Ok(HuleStatement::FunctionCall(HuleFuncCall {
             name: func_name.value,
@@ -295,6 +465,12 @@ impl AstParser {
     pub fn try_parse_func_call_params(&mut self) -> Result<Vec<HuleExpression>, AstParserError> {
         let mut expressions = vec![];
 
+        // empty parameter list
+        if self.expect_token_type(TokenType::BracketClose).is_ok() {
+            self.tokens.prev();
+            return Ok(expressions);
+        }
+
         loop {
             let current_index = self.tokens.get_current_token_index();
             let expression = self.try_parse_expression()
@@ -303,9 +479,7 @@ impl AstParser {
 
             expressions.push(expression);
 
-            if let Ok(token) = self.expect_token_type(TokenType::Comma) {
-                self.tokens.set_current_token_index(current_index + 1);
-            } else {
+            if self.expect_token_type(TokenType::Comma).is_err() {
                 break;
             }
         }
@@ -366,10 +540,15 @@ impl AstParser {
             parameters: vec![],
             return_type: "void".to_string(),
             body: Box::new(body),
+            is_inline: false,
         }))
     }
 
+    /// Function-Declaration
+    /// [inline] <type> <identifier> <bracket_open> [<parameters>] <bracket_close> <body>
     fn try_parse_function_decl(&mut self) -> Result<HuleStatement, AstParserError> {
+        let is_inline = self.expect_token_value("inline".to_string()).is_ok();
+
         let mut func_ret_type = self.expect_token_type(TokenType::Identifier)
             .map_err(|_| AstParserError::IncompatibleStatement)?;
 
@@ -383,8 +562,6 @@ impl AstParser {
         let mut parameters = self.try_parse_func_decl_params()
             .unwrap_or(vec![]);
 
-        println!("found {} params", parameters.len());
-
 
         // bracket close
         let mut general_token = self.tokens.next().ok_or_else(|| AstParserError::IncompatibleStatement)?.clone();
@@ -413,6 +590,7 @@ impl AstParser {
             return_type: func_ret_type.value.clone(),
             parameters,
             body: Box::new(body),
+            is_inline,
         }))
     }
 
@@ -421,20 +599,24 @@ impl AstParser {
         AstParser {
             tokens: Tokenized::new(),
             source: source.to_string(),
+            integer_error: None,
         }
     }
 
     pub fn parse(&mut self, source : &str) -> Result<HuleProgramAst, AstParserError> {
         let mut statements = vec![];
+        let mut positions = vec![];
         self.source = source.to_owned();
 
         let mut tokenizer = Tokenizer::new();
         self.tokens = tokenizer.tokenize(&self.source);
+        self.integer_error = None;
 
         let _last_error : Option<ParseError> = None;
 
         loop {
             let current_index = self.tokens.get_current_token_index();
+            let position = self.tokens.next_position();
 
             let res = self.try_parse_var_decl()
                 .or_reset(self, current_index).or_else(|_| self.try_parse_entry_func())
@@ -443,9 +625,12 @@ impl AstParser {
             match res {
                 Ok(statement) => {
                     statements.push(statement.clone());
+                    positions.push(position.expect("a parsed statement starts with a token"));
                 }
                 Err(err) => {
-                    if let AstParserError::IncompatibleStatement = err {
+                    if let Some(err) = self.integer_error.take() {
+                        return Err(err);
+                    } else if let AstParserError::IncompatibleStatement = err {
                         break;
                     } else if let AstParserError::EndOfFile = err {
                         break;
@@ -461,8 +646,12 @@ impl AstParser {
             }
         }
 
+        if let Some(err) = self.integer_error.take() {
+            return Err(err);
+        }
+
         Ok(HuleProgramAst {
-            body: HuleBody::new(statements)
+            body: HuleBody::with_positions(statements, positions)
         })
     }
-}
\ No newline at end of file
+}
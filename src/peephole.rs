@@ -0,0 +1,310 @@
+//! hyperULE Peephole Optimizer
+//!
+//! Cleans up the generated ULE code. The IR passes do not know the runtime
+//! the backend wraps around every function, so patterns like
+//!
+//! ```text
+//! csAppend = csAppend + Right("000" + IntToStr(hp), 3) + "002"
+//! callStack = Left(callStack, csSize - 3) + csAppend
+//! csAppend = ""
+//! ```
+//!
+//! only show up in the `UleNode` tree. Every rule of `RULES` looks at a
+//! window of consecutive nodes and returns their replacement. The rules are
+//! applied to every node list until none of them matches anymore.
+//!
+//! Fields of the device like `OUT1.Data` may react to being written, so
+//! rules never remove or move writes of names containing a `.`.
+
+use crate::ast::Operator;
+use crate::ule::{UleExpression, UleIfStatement, UleNode, UleVarDef};
+
+pub struct PeepholeRule {
+    pub name: &'static str,
+    /// Number of consecutive nodes the rule looks at
+    pub window: usize,
+    /// Replacement of the nodes, if the rule matches
+    pub apply: fn(&[UleNode]) -> Option<Vec<UleNode>>,
+}
+
+pub const RULES: &[PeepholeRule] = &[
+    PeepholeRule { name: "self-assignment", window: 1, apply: remove_self_assignment },
+    PeepholeRule { name: "empty-if", window: 1, apply: remove_empty_if },
+    PeepholeRule { name: "literal-concat", window: 1, apply: merge_literals },
+    PeepholeRule { name: "dead-store", window: 2, apply: remove_dead_store },
+    PeepholeRule { name: "forward-temporary", window: 3, apply: forward_temporary },
+];
+
+/// Number of times each rule was applied.
+#[derive(Debug, Default, PartialEq)]
+pub struct PeepholeStats {
+    pub applied: Vec<(&'static str, usize)>,
+}
+
+impl PeepholeStats {
+    fn record(&mut self, rule: &'static str) {
+        match self.applied.iter_mut().find(|(name, _)| *name == rule) {
+            Some((_, count)) => *count += 1,
+            None => self.applied.push((rule, 1)),
+        }
+    }
+}
+
+pub fn optimize(nodes: &mut Vec<UleNode>) -> PeepholeStats {
+    let mut stats = PeepholeStats::default();
+    optimize_with(RULES, nodes, &mut stats);
+    stats
+}
+
+pub fn optimize_with(rules: &[PeepholeRule], nodes: &mut Vec<UleNode>, stats: &mut PeepholeStats) {
+    for node in nodes.iter_mut() {
+        match node {
+            UleNode::IfStatement(if_statement) => {
+                optimize_with(rules, &mut if_statement.body, stats);
+                optimize_with(rules, &mut if_statement.else_body, stats);
+            }
+            UleNode::WhileLoop(while_loop) => optimize_with(rules, &mut while_loop.body, stats),
+            UleNode::Scope(scope) => optimize_with(rules, &mut scope.body, stats),
+            UleNode::VariableDefinition(_) | UleNode::Location(_) | UleNode::Owner(_) => {}
+        }
+    }
+
+    let mut position = 0;
+    while position < nodes.len() {
+        if nodes[position].is_marker() {
+            position += 1;
+            continue;
+        }
+
+        let matched = rules.iter().find_map(|rule| {
+            let (window, end) = code_window(nodes, position, rule.window)?;
+            (rule.apply)(&window).map(|replacement| (rule, end, replacement))
+        });
+
+        match matched {
+            Some((rule, end, replacement)) => {
+                // markers within the window stay in front of the replacement
+                let markers: Vec<UleNode> = nodes[position..end].iter()
+                    .filter(|node| node.is_marker())
+                    .cloned()
+                    .collect();
+                nodes.splice(position..end, markers.into_iter().chain(replacement));
+                stats.record(rule.name);
+                // the replacement may complete a window starting earlier
+                for _ in 0..2 {
+                    position = nodes[..position].iter().rposition(|node| !node.is_marker()).unwrap_or(0);
+                }
+            }
+            None => position += 1,
+        }
+    }
+}
+
+/// The next `size` nodes from `start` on which are not markers and the
+/// index after the last of them. Rules see through markers, so the
+/// comments of the pretty style do not change the generated code.
+fn code_window(nodes: &[UleNode], start: usize, size: usize) -> Option<(Vec<UleNode>, usize)> {
+    let mut window = vec![];
+    let mut end = start;
+    while window.len() < size {
+        let node = nodes.get(end)?;
+        if !node.is_marker() {
+            window.push(node.clone());
+        }
+        end += 1;
+    }
+    Some((window, end))
+}
+
+fn is_device_field(name: &str) -> bool {
+    name.contains('.')
+}
+
+/// `x = x`
+fn remove_self_assignment(nodes: &[UleNode]) -> Option<Vec<UleNode>> {
+    match &nodes[0] {
+        UleNode::VariableDefinition(UleVarDef { name, value: UleExpression::Variable(value) })
+        if name == value && !is_device_field(name) => Some(vec![]),
+        _ => None,
+    }
+}
+
+/// `if (c) { }` and `if (c) { } else { }`, conditions never have side
+/// effects.
+fn remove_empty_if(nodes: &[UleNode]) -> Option<Vec<UleNode>> {
+    match &nodes[0] {
+        UleNode::IfStatement(UleIfStatement { body, else_body, .. })
+        if body.iter().chain(else_body).all(UleNode::is_marker) => Some(vec![]),
+        _ => None,
+    }
+}
+
+/// Merges string literals in concatenations, `"a" + "b"` becomes `"ab"`
+/// and `(x + "a") + "b"` becomes `x + "ab"`.
+fn merge_expression(expression: &UleExpression) -> Option<UleExpression> {
+    let merged = merge_children(expression);
+    if let UleExpression::Binary { left, operator: Operator::Plus, right } = merged.as_ref().unwrap_or(expression) {
+        if let UleExpression::String(b) = &**right {
+            match &**left {
+                UleExpression::String(a) => return Some(UleExpression::String(format!("{}{}", a, b))),
+                UleExpression::Binary { left: inner, operator: Operator::Plus, right: last } => {
+                    if let UleExpression::String(a) = &**last {
+                        return Some(UleExpression::Binary {
+                            left: inner.clone(),
+                            operator: Operator::Plus,
+                            right: Box::new(UleExpression::String(format!("{}{}", a, b))),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    merged
+}
+
+/// The expression with merged operands or arguments, if any were merged.
+fn merge_children(expression: &UleExpression) -> Option<UleExpression> {
+    match expression {
+        UleExpression::Binary { left, operator, right } => {
+            let merged_left = merge_expression(left);
+            let merged_right = merge_expression(right);
+            if merged_left.is_none() && merged_right.is_none() {
+                return None;
+            }
+            Some(UleExpression::Binary {
+                left: Box::new(merged_left.unwrap_or_else(|| *left.clone())),
+                operator: operator.clone(),
+                right: Box::new(merged_right.unwrap_or_else(|| *right.clone())),
+            })
+        }
+        UleExpression::Call(name, arguments) => {
+            let merged: Vec<Option<UleExpression>> = arguments.iter().map(merge_expression).collect();
+            if merged.iter().all(Option::is_none) {
+                return None;
+            }
+            Some(UleExpression::Call(name.clone(), merged.into_iter()
+                .zip(arguments)
+                .map(|(merged, argument)| merged.unwrap_or_else(|| argument.clone()))
+                .collect()))
+        }
+        _ => None,
+    }
+}
+
+fn merge_literals(nodes: &[UleNode]) -> Option<Vec<UleNode>> {
+    match &nodes[0] {
+        UleNode::VariableDefinition(definition) => merge_expression(&definition.value)
+            .map(|value| vec![UleNode::VariableDefinition(UleVarDef { name: definition.name.clone(), value })]),
+        UleNode::IfStatement(if_statement) => merge_expression(&if_statement.condition)
+            .map(|condition| vec![UleNode::IfStatement(UleIfStatement { condition, ..if_statement.clone() })]),
+        _ => None,
+    }
+}
+
+/// `x = a; x = b` becomes `x = b` if `b` does not read `x`.
+fn remove_dead_store(nodes: &[UleNode]) -> Option<Vec<UleNode>> {
+    match (&nodes[0], &nodes[1]) {
+        (UleNode::VariableDefinition(first), UleNode::VariableDefinition(second))
+        if first.name == second.name && second.value.reads(&first.name) == 0 && !is_device_field(&first.name)
+        => Some(vec![nodes[1].clone()]),
+        _ => None,
+    }
+}
+
+/// `t = a; x = f(t); t = b` becomes `x = f(a); t = b` if `t` is read once.
+fn forward_temporary(nodes: &[UleNode]) -> Option<Vec<UleNode>> {
+    match (&nodes[0], &nodes[1], &nodes[2]) {
+        (UleNode::VariableDefinition(temporary), UleNode::VariableDefinition(user), UleNode::VariableDefinition(reset))
+        if temporary.name == reset.name
+            && user.name != temporary.name
+            && !is_device_field(&temporary.name)
+            && user.value.reads(&temporary.name) == 1
+            && reset.value.reads(&temporary.name) == 0
+            && reset.value.reads(&user.name) == 0 => {
+            let mut value = user.value.clone();
+            value.substitute(&temporary.name, &temporary.value);
+            Some(vec![
+                UleNode::VariableDefinition(UleVarDef { name: user.name.clone(), value }),
+                nodes[2].clone(),
+            ])
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ule::UleWhileLoop;
+
+    fn assign(name: &str, value: UleExpression) -> UleNode {
+        UleNode::VariableDefinition(UleVarDef { name: name.to_string(), value })
+    }
+
+    fn variable(name: &str) -> UleExpression {
+        UleExpression::Variable(name.to_string())
+    }
+
+    fn string(value: &str) -> UleExpression {
+        UleExpression::String(value.to_string())
+    }
+
+    fn plus(left: UleExpression, right: UleExpression) -> UleExpression {
+        UleExpression::Binary { left: Box::new(left), operator: Operator::Plus, right: Box::new(right) }
+    }
+
+    #[test]
+    fn test_push_frame() {
+        let push = plus(UleExpression::Call("Left".to_string(), vec![variable("callStack"), variable("csSize")]), variable("csAppend"));
+        let mut nodes = vec![
+            assign("csAppend", plus(string("\u{2}"), string("1"))),
+            assign("callStack", push),
+            assign("csAppend", string("")),
+        ];
+
+        let stats = optimize(&mut nodes);
+
+        assert_eq!(nodes, vec![
+            assign("callStack", plus(UleExpression::Call("Left".to_string(), vec![variable("callStack"), variable("csSize")]), string("\u{2}1"))),
+            assign("csAppend", string("")),
+        ]);
+        assert_eq!(stats.applied, vec![("literal-concat", 1), ("forward-temporary", 1)]);
+    }
+
+    #[test]
+    fn test_rules_in_nested_bodies() {
+        let mut nodes = vec![UleNode::WhileLoop(UleWhileLoop {
+            condition: variable("run"),
+            body: vec![
+                assign("i1", variable("i1")),
+                assign("i2", UleExpression::Integer(1)),
+                assign("i2", UleExpression::Integer(2)),
+                UleNode::IfStatement(UleIfStatement { condition: variable("i1"), body: vec![], else_body: vec![] }),
+            ],
+        })];
+
+        let stats = optimize(&mut nodes);
+
+        assert_eq!(nodes, vec![UleNode::WhileLoop(UleWhileLoop {
+            condition: variable("run"),
+            body: vec![assign("i2", UleExpression::Integer(2))],
+        })]);
+        assert_eq!(stats.applied, vec![("self-assignment", 1), ("dead-store", 1), ("empty-if", 1)]);
+    }
+
+    #[test]
+    fn test_device_fields_are_kept() {
+        let mut nodes = vec![
+            assign("OUT1.Data", string("a")),
+            assign("OUT1.Data", variable("OUT1.Data")),
+            assign("OUT1.Data", string("b")),
+        ];
+
+        let stats = optimize(&mut nodes);
+
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(stats, PeepholeStats::default());
+    }
+}
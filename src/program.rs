@@ -1,41 +1,718 @@
-use std::ops::Index;
-use std::rc::{Rc, Weak};
+use crate::ast::{Operator, SourcePosition};
+use crate::cfg::FlowGraph;
+use crate::consteval::ConstValue;
+use crate::literals::escape_string;
 
+#[derive(Clone, PartialEq, Debug)]
+pub enum HuleType {
+    Void,
+    Int,
+    String,
+    Bool,
+    Array(Box<HuleType>, usize),
+}
+
+impl HuleType {
+    pub fn from_name(name: &str) -> Option<HuleType> {
+        match name {
+            "void" => Some(HuleType::Void),
+            "int" => Some(HuleType::Int),
+            "string" => Some(HuleType::String),
+            "bool" => Some(HuleType::Bool),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            HuleType::Void => "void".to_string(),
+            HuleType::Int => "int".to_string(),
+            HuleType::String => "string".to_string(),
+            HuleType::Bool => "bool".to_string(),
+            HuleType::Array(element, size) => format!("{}[{}]", element.name(), size),
+        }
+    }
+}
+
+/// Parameter and return types of the ULE built-in functions.
+pub fn builtin_signature(name: &str) -> Option<(Vec<HuleType>, HuleType)> {
+    match name {
+        "StrLen" => Some((vec![HuleType::String], HuleType::Int)),
+        "Left" | "Right" => Some((vec![HuleType::String, HuleType::Int], HuleType::String)),
+        "Mid" => Some((vec![HuleType::String, HuleType::Int, HuleType::Int], HuleType::String)),
+        "IntToStr" => Some((vec![HuleType::Int], HuleType::String)),
+        "StrToInt" => Some((vec![HuleType::String], HuleType::Int)),
+        _ => None,
+    }
+}
+
+/// Index of a function in the program arena. It is also the function index
+/// the ULE runtime uses to dispatch calls, `_entry` is always 0.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct FunctionId(pub usize);
+
+/// Index of a variable in the program arena. Globals, parameters, locals and
+/// temporaries of all functions share one arena.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct VarId(pub usize);
+
+/// Index of a basic block within its function.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct BlockId(pub usize);
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum IrExpression {
+    Integer(i32),
+    String(String),
+    Boolean(bool),
+    Variable(VarId),
+    /// Field of the device, like `OUT1.Data`
+    External(String),
+    /// Call of a hyperULE function
+    Call(FunctionId, Vec<IrExpression>),
+    /// Call of a ULE built-in function
+    Builtin(String, Vec<IrExpression>),
+    Binary {
+        left: Box<IrExpression>,
+        operator: Operator,
+        right: Box<IrExpression>,
+    },
+}
+
+impl IrExpression {
+    pub fn from_constant(value: ConstValue) -> IrExpression {
+        match value {
+            ConstValue::Integer(value) => IrExpression::Integer(value),
+            ConstValue::String(value) => IrExpression::String(value),
+            ConstValue::Boolean(value) => IrExpression::Boolean(value),
+        }
+    }
+
+    /// Value of literal expressions.
+    pub fn as_constant(&self) -> Option<ConstValue> {
+        match self {
+            IrExpression::Integer(value) => Some(ConstValue::Integer(*value)),
+            IrExpression::String(value) => Some(ConstValue::String(value.clone())),
+            IrExpression::Boolean(value) => Some(ConstValue::Boolean(*value)),
+            _ => None,
+        }
+    }
+
+    /// Whether the expression calls a hyperULE function.
+    pub fn contains_call(&self) -> bool {
+        match self {
+            IrExpression::Call(..) => true,
+            IrExpression::Builtin(_, arguments) => arguments.iter().any(IrExpression::contains_call),
+            IrExpression::Binary { left, right, .. } => left.contains_call() || right.contains_call(),
+            _ => false,
+        }
+    }
+
+    /// Calls `visit` for every hyperULE function called by the expression.
+    pub fn visit_calls_mut<F: FnMut(&mut FunctionId)>(&mut self, visit: &mut F) {
+        match self {
+            IrExpression::Call(function, arguments) => {
+                visit(function);
+                for argument in arguments {
+                    argument.visit_calls_mut(visit);
+                }
+            }
+            IrExpression::Builtin(_, arguments) => {
+                for argument in arguments {
+                    argument.visit_calls_mut(visit);
+                }
+            }
+            IrExpression::Binary { left, right, .. } => {
+                left.visit_calls_mut(visit);
+                right.visit_calls_mut(visit);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn visit_variables_mut<F: FnMut(&mut VarId)>(&mut self, visit: &mut F) {
+        match self {
+            IrExpression::Variable(variable) => visit(variable),
+            IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments) => {
+                for argument in arguments {
+                    argument.visit_variables_mut(visit);
+                }
+            }
+            IrExpression::Binary { left, right, .. } => {
+                left.visit_variables_mut(visit);
+                right.visit_variables_mut(visit);
+            }
+            _ => {}
+        }
+    }
+
+    /// Calls `visit` for every variable read by the expression.
+    pub fn visit_variables<F: FnMut(VarId)>(&self, visit: &mut F) {
+        match self {
+            IrExpression::Variable(variable) => visit(*variable),
+            IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments) => {
+                for argument in arguments {
+                    argument.visit_variables(visit);
+                }
+            }
+            IrExpression::Binary { left, right, .. } => {
+                left.visit_variables(visit);
+                right.visit_variables(visit);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether the expression reads the variable or device field.
+    pub fn reads_target(&self, target: &IrTarget) -> bool {
+        match (self, target) {
+            (IrExpression::Variable(variable), IrTarget::Variable(target)) => variable == target,
+            (IrExpression::External(name), IrTarget::External(target)) => name == target,
+            (IrExpression::Call(_, arguments) | IrExpression::Builtin(_, arguments), _)
+            => arguments.iter().any(|argument| argument.reads_target(target)),
+            (IrExpression::Binary { left, right, .. }, _) => left.reads_target(target) || right.reads_target(target),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum IrTarget {
+    Variable(VarId),
+    External(String),
+}
+
+/// Instruction within a basic block. Expressions of instructions never
+/// contain calls of hyperULE functions, those always end a block.
+#[derive(Clone, PartialEq, Debug)]
+pub enum IrInstruction {
+    Assign(IrTarget, IrExpression),
+    /// Expression evaluated for its side effects only
+    Eval(IrExpression),
+    /// Position of the hyperULE statement the following instructions are
+    /// generated from, only present if the program was lowered with
+    /// locations
+    Location(SourcePosition),
+}
+
+impl IrInstruction {
+    pub fn is_location(&self) -> bool {
+        matches!(self, IrInstruction::Location(_))
+    }
+
+    /// Expression evaluated by the instruction.
+    pub fn value(&self) -> Option<&IrExpression> {
+        match self {
+            IrInstruction::Assign(_, value) | IrInstruction::Eval(value) => Some(value),
+            IrInstruction::Location(_) => None,
+        }
+    }
+
+    pub fn value_mut(&mut self) -> Option<&mut IrExpression> {
+        match self {
+            IrInstruction::Assign(_, value) | IrInstruction::Eval(value) => Some(value),
+            IrInstruction::Location(_) => None,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Terminator {
+    Goto(BlockId),
+    Branch {
+        condition: IrExpression,
+        then_block: BlockId,
+        else_block: BlockId,
+    },
+    /// Calls a hyperULE function and continues with the `continuation`
+    /// block once it returned. In the ULE runtime, the continuation is the
+    /// state pushed onto the call stack below the callee.
+    Call {
+        function: FunctionId,
+        arguments: Vec<IrExpression>,
+        result: Option<IrTarget>,
+        continuation: BlockId,
+    },
+    /// Call in tail position. The frame of the caller is replaced by the
+    /// frame of the callee, which returns directly to the caller's caller.
+    TailCall {
+        function: FunctionId,
+        arguments: Vec<IrExpression>,
+    },
+    Return(Option<IrExpression>),
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Goto(target) => vec![*target],
+            Terminator::Branch { then_block, else_block, .. } => vec![*then_block, *else_block],
+            Terminator::Call { continuation, .. } => vec![*continuation],
+            Terminator::TailCall { .. } | Terminator::Return(_) => vec![],
+        }
+    }
+
+    /// Expressions evaluated by the terminator.
+    pub fn expressions(&self) -> Vec<&IrExpression> {
+        match self {
+            Terminator::Goto(_) | Terminator::Return(None) => vec![],
+            Terminator::Branch { condition, .. } => vec![condition],
+            Terminator::Call { arguments, .. } | Terminator::TailCall { arguments, .. } => arguments.iter().collect(),
+            Terminator::Return(Some(value)) => vec![value],
+        }
+    }
+
+    pub fn expressions_mut(&mut self) -> Vec<&mut IrExpression> {
+        match self {
+            Terminator::Goto(_) | Terminator::Return(None) => vec![],
+            Terminator::Branch { condition, .. } => vec![condition],
+            Terminator::Call { arguments, .. } | Terminator::TailCall { arguments, .. } => arguments.iter_mut().collect(),
+            Terminator::Return(Some(value)) => vec![value],
+        }
+    }
+
+    pub fn map_successors<F: Fn(BlockId) -> BlockId>(&mut self, map: F) {
+        match self {
+            Terminator::Goto(target) => *target = map(*target),
+            Terminator::Branch { then_block, else_block, .. } => {
+                *then_block = map(*then_block);
+                *else_block = map(*else_block);
+            }
+            Terminator::Call { continuation, .. } => *continuation = map(*continuation),
+            Terminator::TailCall { .. } | Terminator::Return(_) => {}
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct BasicBlock {
+    pub instructions: Vec<IrInstruction>,
+    pub terminator: Terminator,
+}
+
+impl BasicBlock {
+    /// Number of instructions which generate code, locations are not
+    /// counted.
+    pub fn code_len(&self) -> usize {
+        self.instructions.iter().filter(|instruction| !instruction.is_location()).count()
+    }
+
+    /// Every expression of the block in evaluation order, the terminator
+    /// comes last.
+    pub fn expressions(&self) -> Vec<&IrExpression> {
+        let mut expressions: Vec<&IrExpression> = self.instructions.iter()
+            .filter_map(IrInstruction::value)
+            .collect();
+        expressions.extend(self.terminator.expressions());
+        expressions
+    }
+
+    pub fn expressions_mut(&mut self) -> Vec<&mut IrExpression> {
+        let mut expressions: Vec<&mut IrExpression> = self.instructions.iter_mut()
+            .filter_map(IrInstruction::value_mut)
+            .collect();
+        expressions.extend(self.terminator.expressions_mut());
+        expressions
+    }
+
+    /// Variables written by the block in order, including call results.
+    pub fn defined_variables(&self) -> Vec<VarId> {
+        let targets = self.instructions.iter()
+            .filter_map(|instruction| match instruction {
+                IrInstruction::Assign(target, _) => Some(target),
+                IrInstruction::Eval(_) | IrInstruction::Location(_) => None,
+            })
+            .chain(match &self.terminator {
+                Terminator::Call { result: Some(result), .. } => Some(result),
+                _ => None,
+            });
+
+        targets
+            .filter_map(|target| match target {
+                IrTarget::Variable(variable) => Some(*variable),
+                IrTarget::External(_) => None,
+            })
+            .collect()
+    }
+}
+
+
+/// The whole program in arena form. Functions and variables are owned by
+/// the program and referenced by `FunctionId` and `VarId`, so passes can
+/// rewrite any part of it through a single `&mut HuleProgram`.
 #[derive(Clone)]
 pub struct HuleProgram {
-    functions: Vec<Box<HuleFunction>>,
+    functions: Vec<HuleFunction>,
+    variables: Vec<HuleVariable>,
+    globals: Vec<VarId>,
 }
-    
+
 impl HuleProgram {
-    fn new(program: &Rc<HuleProgram>) -> HuleProgram {
+    pub fn new() -> HuleProgram {
         HuleProgram {
             functions: vec![],
+            variables: vec![],
+            globals: vec![],
+        }
+    }
+
+    /// Adds an empty function, the first function added is `_entry`.
+    pub fn add_function(&mut self, name: &str, return_type: HuleType) -> FunctionId {
+        let id = FunctionId(self.functions.len());
+        self.functions.push(HuleFunction::new(id, name, return_type));
+        id
+    }
+
+    pub fn add_global(&mut self, variable: HuleVariable) -> VarId {
+        let id = self.add_variable(variable);
+        self.globals.push(id);
+        id
+    }
+
+    pub fn add_parameter(&mut self, function: FunctionId, variable: HuleVariable) -> VarId {
+        let id = self.add_variable(HuleVariable { owner: Some(function), ..variable });
+        self.functions[function.0].parameters.push(id);
+        id
+    }
+
+    pub fn add_local(&mut self, function: FunctionId, variable: HuleVariable) -> VarId {
+        let id = self.add_variable(HuleVariable { owner: Some(function), ..variable });
+        self.functions[function.0].locals.push(id);
+        id
+    }
+
+    /// Adds a compiler generated local variable to the function.
+    pub fn add_temporary(&mut self, function: FunctionId, data_type: HuleType) -> VarId {
+        let name = format!("_t{}", self.variables.len());
+        self.add_local(function, HuleVariable::new(&name, data_type))
+    }
+
+    fn add_variable(&mut self, variable: HuleVariable) -> VarId {
+        self.variables.push(variable);
+        VarId(self.variables.len() - 1)
+    }
+
+    pub fn functions(&self) -> &[HuleFunction] {
+        &self.functions
+    }
+
+    pub fn function_ids(&self) -> impl Iterator<Item=FunctionId> {
+        (0..self.functions.len()).map(FunctionId)
+    }
+
+    pub fn function(&self, id: FunctionId) -> &HuleFunction {
+        &self.functions[id.0]
+    }
+
+    pub fn function_mut(&mut self, id: FunctionId) -> &mut HuleFunction {
+        &mut self.functions[id.0]
+    }
+
+    pub fn function_by_name(&self, name: &str) -> Option<FunctionId> {
+        self.functions.iter()
+            .position(|f| f.name == name)
+            .map(FunctionId)
+    }
+
+    pub fn variable(&self, id: VarId) -> &HuleVariable {
+        &self.variables[id.0]
+    }
+
+    pub fn variable_mut(&mut self, id: VarId) -> &mut HuleVariable {
+        &mut self.variables[id.0]
+    }
+
+    pub fn globals(&self) -> &[VarId] {
+        &self.globals
+    }
+
+    pub fn expression_type(&self, expression: &IrExpression) -> HuleType {
+        match expression {
+            IrExpression::Integer(_) => HuleType::Int,
+            IrExpression::String(_) | IrExpression::External(_) => HuleType::String,
+            IrExpression::Boolean(_) => HuleType::Bool,
+            IrExpression::Variable(variable) => self.variable(*variable).data_type.clone(),
+            IrExpression::Call(function, _) => self.function(*function).return_type.clone(),
+            IrExpression::Builtin(name, _) => builtin_signature(name)
+                .map(|(_, return_type)| return_type)
+                .unwrap_or(HuleType::Void),
+            IrExpression::Binary { left, operator, .. } => match operator {
+                Operator::Plus | Operator::Minus | Operator::Multiply | Operator::Divide => self.expression_type(left),
+                _ => HuleType::Bool,
+            },
+        }
+    }
+
+    /// Removes every function whose `keep` flag is not set and renumbers the
+    /// remaining ones. `_entry` must be kept. Variables of removed functions
+    /// stay in the arena but are no longer referenced.
+    pub fn retain_functions(&mut self, keep: &[bool]) {
+        let mut new_ids = vec![None; self.functions.len()];
+        let mut next_id = 0;
+        for (index, is_kept) in keep.iter().enumerate() {
+            if *is_kept {
+                new_ids[index] = Some(FunctionId(next_id));
+                next_id += 1;
+            }
+        }
+
+        let remap = |function: &mut FunctionId| {
+            *function = new_ids[function.0].expect("called functions are kept");
+        };
+
+        let functions = std::mem::take(&mut self.functions);
+        self.functions = functions.into_iter()
+            .zip(keep)
+            .filter(|(_, is_kept)| **is_kept)
+            .map(|(mut function, _)| {
+                remap(&mut function.id);
+                for block in &mut function.blocks {
+                    if let Terminator::Call { function, .. } | Terminator::TailCall { function, .. } = &mut block.terminator {
+                        remap(function);
+                    }
+                    for expression in block.expressions_mut() {
+                        expression.visit_calls_mut(&mut |function| remap(function));
+                    }
+                }
+                function
+            })
+            .collect();
+
+        for variable in &mut self.variables {
+            if let Some(initializer) = &mut variable.initializer {
+                initializer.visit_calls_mut(&mut |function| remap(function));
+            }
+            if let Some(owner) = variable.owner {
+                variable.owner = Some(new_ids[owner.0].unwrap_or(owner));
+            }
+        }
+    }
+
+    pub fn retain_globals<F: FnMut(VarId) -> bool>(&mut self, mut keep: F) {
+        self.globals.retain(|global| keep(*global));
+    }
+
+    pub fn to_ir_text(&self) -> String {
+        let printer = IrPrinter { program: self };
+        let mut result = String::new();
+        for global in &self.globals {
+            let variable = self.variable(*global);
+            match &variable.initializer {
+                Some(value) => result.push_str(&format!("global {} @{} = {}\n",
+                    variable.data_type.name(), variable.name, printer.expression(value))),
+                None => result.push_str(&format!("global {} @{}\n", variable.data_type.name(), variable.name)),
+            }
+        }
+
+        for function in &self.functions {
+            if !result.is_empty() {
+                result.push('\n');
+            }
+            result.push_str(&function.to_ir_text(self));
         }
+
+        result
     }
 }
 
 #[derive(Clone)]
 pub struct HuleFunction {
-    program: Weak<HuleProgram>
+    pub id: FunctionId,
+    pub name: String,
+    pub return_type: HuleType,
+    /// Declared with the `inline` modifier
+    pub is_inline: bool,
+    pub parameters: Vec<VarId>,
+    /// Local variables and temporaries, parameters are not included
+    pub locals: Vec<VarId>,
+    /// Basic blocks of the function body, the first block is the entry
+    pub blocks: Vec<BasicBlock>,
 }
 
 impl HuleFunction {
-    pub fn new(program: &Rc<HuleProgram>) -> HuleFunction {
+    fn new(id: FunctionId, name: &str, return_type: HuleType) -> HuleFunction {
         HuleFunction {
-            program: Rc::downgrade(program),
+            id,
+            name: name.to_string(),
+            return_type,
+            is_inline: false,
+            parameters: vec![],
+            locals: vec![],
+            blocks: vec![],
         }
     }
 
-    pub fn get_func_index(&self) -> i32 {
-        // if let Some(program) = self.program.upgrade() {
-        //     program.functions.iter().position(self).unwrap() as i32
-        // } else {
-        //     -1
-        // }
-        42
+    pub fn block(&self, id: BlockId) -> &BasicBlock {
+        &self.blocks[id.0]
+    }
+
+    pub fn block_mut(&mut self, id: BlockId) -> &mut BasicBlock {
+        &mut self.blocks[id.0]
+    }
+
+    /// Drops blocks which can not be reached from the entry block and
+    /// renumbers the remaining ones.
+    pub fn remove_unreachable_blocks(&mut self) {
+        let reachable = self.reachable_blocks();
+        let mut new_ids = vec![BlockId(0); self.blocks.len()];
+        let mut next_id = 0;
+        for (index, is_reachable) in reachable.iter().enumerate() {
+            if *is_reachable {
+                new_ids[index] = BlockId(next_id);
+                next_id += 1;
+            }
+        }
+
+        let blocks = std::mem::take(&mut self.blocks);
+        self.blocks = blocks.into_iter()
+            .zip(reachable)
+            .filter(|(_, is_reachable)| *is_reachable)
+            .map(|(mut block, _)| {
+                block.terminator.map_successors(|target| new_ids[target.0]);
+                block
+            })
+            .collect();
+    }
+
+    /// Textual form of the function with one section per basic block. Every
+    /// block becomes a state of the ULE runtime.
+    pub fn to_ir_text(&self, program: &HuleProgram) -> String {
+        let printer = IrPrinter { program };
+        let parameters: Vec<String> = self.parameters.iter()
+            .map(|p| format!("{} {}", program.variable(*p).data_type.name(), printer.variable(*p)))
+            .collect();
+
+        let mut result = format!("function {} {}({}) -> {} {{\n",
+            self.id.0, self.name, parameters.join(", "), self.return_type.name());
+
+        for local in &self.locals {
+            result.push_str(&format!("    var {} {}\n", program.variable(*local).data_type.name(), printer.variable(*local)));
+        }
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            result.push_str(&format!("  bb{}:\n", index));
+            for instruction in &block.instructions {
+                result.push_str(&format!("    {}\n", printer.instruction(instruction)));
+            }
+            result.push_str(&format!("    {}\n", printer.terminator(&block.terminator)));
+        }
+
+        result.push_str("}\n");
+        result
+    }
+}
+
+impl FlowGraph for HuleFunction {
+    fn entry_block(&self) -> usize {
+        0
+    }
+
+    fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    fn successors(&self, block: usize) -> Vec<usize> {
+        self.blocks[block].terminator.successors()
+            .into_iter()
+            .map(|b| b.0)
+            .collect()
     }
 }
 
-struct HuleVariable {
-    program: Weak<HuleProgram>
-}
\ No newline at end of file
+struct IrPrinter<'a> {
+    program: &'a HuleProgram,
+}
+
+impl<'a> IrPrinter<'a> {
+    fn variable(&self, id: VarId) -> String {
+        let variable = self.program.variable(id);
+        match variable.owner {
+            Some(_) => format!("%{}", variable.name),
+            None => format!("@{}", variable.name),
+        }
+    }
+
+    fn target(&self, target: &IrTarget) -> String {
+        match target {
+            IrTarget::Variable(variable) => self.variable(*variable),
+            IrTarget::External(name) => name.clone(),
+        }
+    }
+
+    fn expressions(&self, expressions: &[IrExpression]) -> String {
+        expressions.iter()
+            .map(|e| self.expression(e))
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+
+    fn expression(&self, expression: &IrExpression) -> String {
+        match expression {
+            IrExpression::Integer(value) => value.to_string(),
+            IrExpression::String(value) => format!("\"{}\"", escape_string(value)),
+            IrExpression::Boolean(value) => value.to_string(),
+            IrExpression::Variable(variable) => self.variable(*variable),
+            IrExpression::External(name) => name.clone(),
+            IrExpression::Call(function, arguments)
+            => format!("call {}({})", self.program.function(*function).name, self.expressions(arguments)),
+            IrExpression::Builtin(name, arguments) => format!("{}({})", name, self.expressions(arguments)),
+            IrExpression::Binary { left, operator, right } => format!("({} {} {})",
+                self.expression(left), operator.symbol(), self.expression(right)),
+        }
+    }
+
+    fn instruction(&self, instruction: &IrInstruction) -> String {
+        match instruction {
+            IrInstruction::Assign(target, value) => format!("{} = {}", self.target(target), self.expression(value)),
+            IrInstruction::Eval(value) => self.expression(value),
+            IrInstruction::Location(position) => format!("; line {}:{}", position.line, position.column),
+        }
+    }
+
+    fn terminator(&self, terminator: &Terminator) -> String {
+        match terminator {
+            Terminator::Goto(target) => format!("goto bb{}", target.0),
+            Terminator::Branch { condition, then_block, else_block }
+            => format!("branch {} ? bb{} : bb{}", self.expression(condition), then_block.0, else_block.0),
+            Terminator::Call { function, arguments, result, continuation } => {
+                let callee = &self.program.function(*function).name;
+                match result {
+                    Some(result) => format!("{} = call {}({}) then bb{}",
+                        self.target(result), callee, self.expressions(arguments), continuation.0),
+                    None => format!("call {}({}) then bb{}", callee, self.expressions(arguments), continuation.0),
+                }
+            }
+            Terminator::TailCall { function, arguments }
+            => format!("tailcall {}({})", self.program.function(*function).name, self.expressions(arguments)),
+            Terminator::Return(Some(value)) => format!("return {}", self.expression(value)),
+            Terminator::Return(None) => "return".to_string(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HuleVariable {
+    pub name: String,
+    pub data_type: HuleType,
+    /// Function the variable belongs to, globals have none
+    pub owner: Option<FunctionId>,
+    /// Value of `const` declarations
+    pub constant: Option<ConstValue>,
+    /// Initial value of global variables
+    pub initializer: Option<IrExpression>,
+    /// Register of the register allocation, a global no source declares
+    pub register: bool,
+}
+
+impl HuleVariable {
+    pub fn new(name: &str, data_type: HuleType) -> HuleVariable {
+        HuleVariable {
+            name: name.to_string(),
+            data_type,
+            owner: None,
+            constant: None,
+            initializer: None,
+            register: false,
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! hyperULE Register Allocation
+//!
+//! Without allocation every local of a function is its own ULE variable or
+//! frame slot. Most locals only live for a few instructions, so locals whose
+//! lifetimes never overlap can share one ULE variable, a register:
+//! - `i1`, `i2`, ... for int locals,
+//! - `b1`, `b2`, ... for bool locals,
+//! - `s1`, `s2`, ... for string locals.
+//!
+//! The registers are shared by all functions. A call may overwrite every
+//! register, so locals which are live across a call keep their slot in the
+//! frame, as do parameters, arrays and locals read before their first
+//! assignment. `ri` and `rs` stay reserved for return values.
+//!
+//! Registers become globals of the program and replace the allocated locals.
+
+use std::collections::{HashMap, HashSet};
+use crate::liveness::{tracked_variables, transfer_instruction, transfer_terminator, Liveness};
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct RegisterAllocationPass;
+
+impl OptimizerPass for RegisterAllocationPass {
+    fn name(&self) -> &'static str {
+        "regalloc"
+    }
+
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) {
+        let mut registers = RegisterFile::new(program);
+
+        for id in program.function_ids().collect::<Vec<_>>() {
+            let colors = color_function(program, program.function(id));
+            if colors.is_empty() {
+                continue;
+            }
+
+            let mapping: HashMap<VarId, VarId> = colors.into_iter()
+                .map(|(local, (class, index))| (local, registers.get(program, class, index)))
+                .collect();
+
+            let function = program.function_mut(id);
+            rename_variables(function, &mapping);
+            function.locals.retain(|local| !mapping.contains_key(local));
+            notes.push(format!("allocated {} locals of '{}' to registers", mapping.len(), function.name));
+        }
+
+        if !registers.names.is_empty() {
+            let names: Vec<&str> = registers.names.iter().map(|(_, name)| name.as_str()).collect();
+            notes.push(format!("registers used: {}", names.join(", ")));
+        }
+    }
+}
+
+/// Prefix of the registers holding variables of the type, if any.
+fn register_class(data_type: &HuleType) -> Option<&'static str> {
+    match data_type {
+        HuleType::Int => Some("i"),
+        HuleType::Bool => Some("b"),
+        HuleType::String => Some("s"),
+        _ => None,
+    }
+}
+
+/// Registers created so far, as globals of the program.
+struct RegisterFile {
+    registers: HashMap<(&'static str, usize), VarId>,
+    /// Registers in order of creation, for the notes
+    names: Vec<(VarId, String)>,
+    /// Names of the globals of the program, registers must not shadow them
+    taken: HashSet<String>,
+}
+
+impl RegisterFile {
+    fn new(program: &HuleProgram) -> RegisterFile {
+        RegisterFile {
+            registers: HashMap::new(),
+            names: vec![],
+            taken: program.globals().iter().map(|global| program.variable(*global).name.clone()).collect(),
+        }
+    }
+
+    fn get(&mut self, program: &mut HuleProgram, class: &'static str, index: usize) -> VarId {
+        if let Some(register) = self.registers.get(&(class, index)) {
+            return *register;
+        }
+
+        let mut number = index + 1;
+        while self.taken.contains(&format!("{}{}", class, number)) {
+            number += 1;
+        }
+        let name = format!("{}{}", class, number);
+        let data_type = match class {
+            "i" => HuleType::Int,
+            "b" => HuleType::Bool,
+            _ => HuleType::String,
+        };
+
+        let register = program.add_global(HuleVariable { register: true, ..HuleVariable::new(&name, data_type) });
+        self.taken.insert(name.clone());
+        self.registers.insert((class, index), register);
+        self.names.push((register, name));
+        register
+    }
+}
+
+/// Pairs of locals which are live at the same time.
+pub fn interference(function: &HuleFunction, liveness: &Liveness) -> HashSet<(VarId, VarId)> {
+    let tracked = tracked_variables(function);
+    let mut edges = HashSet::new();
+    let mut interfere = |variable: VarId, live: &HashSet<VarId>| {
+        for other in live.iter().filter(|other| **other != variable) {
+            edges.insert((variable, *other));
+            edges.insert((*other, variable));
+        }
+    };
+
+    for (index, block) in function.blocks.iter().enumerate() {
+        let mut live = liveness.live_out[index].clone();
+
+        if let Terminator::Call { result: Some(IrTarget::Variable(result)), .. } = &block.terminator {
+            interfere(*result, &live);
+        }
+        transfer_terminator(&block.terminator, &tracked, &mut live);
+
+        for instruction in block.instructions.iter().rev() {
+            if let IrInstruction::Assign(IrTarget::Variable(variable), _) = instruction {
+                interfere(*variable, &live);
+            }
+            transfer_instruction(instruction, &tracked, &mut live);
+        }
+    }
+
+    edges
+}
+
+/// Register class and index of every local of the function which can be
+/// held in a register.
+fn color_function(program: &HuleProgram, function: &HuleFunction) -> Vec<(VarId, (&'static str, usize))> {
+    let liveness = Liveness::compute(function);
+    let edges = interference(function, &liveness);
+
+    // locals whose value has to survive a call or which are read before
+    // their first assignment stay in the frame
+    let mut in_frame: HashSet<VarId> = liveness.live_in.first().cloned().unwrap_or_default();
+    for (index, block) in function.blocks.iter().enumerate() {
+        if let Terminator::Call { result, .. } = &block.terminator {
+            in_frame.extend(liveness.live_out[index].iter()
+                .filter(|variable| result.as_ref() != Some(&IrTarget::Variable(**variable))));
+        }
+    }
+
+    let mut colors: Vec<(VarId, (&'static str, usize))> = vec![];
+    for local in &function.locals {
+        let class = match register_class(&program.variable(*local).data_type) {
+            Some(class) if !in_frame.contains(local) => class,
+            _ => continue,
+        };
+
+        let used: HashSet<usize> = colors.iter()
+            .filter(|(other, (other_class, _))| *other_class == class && edges.contains(&(*local, *other)))
+            .map(|(_, (_, index))| *index)
+            .collect();
+        let index = (0..).find(|index| !used.contains(index)).unwrap();
+        colors.push((*local, (class, index)));
+    }
+
+    colors
+}
+
+fn rename_variables(function: &mut HuleFunction, mapping: &HashMap<VarId, VarId>) {
+    let rename = |variable: &mut VarId| {
+        if let Some(register) = mapping.get(variable) {
+            *variable = *register;
+        }
+    };
+
+    for block in &mut function.blocks {
+        for instruction in &mut block.instructions {
+            if let IrInstruction::Assign(IrTarget::Variable(variable), _) = instruction {
+                rename(variable);
+            }
+        }
+        if let Terminator::Call { result: Some(IrTarget::Variable(variable)), .. } = &mut block.terminator {
+            rename(variable);
+        }
+        for expression in block.expressions_mut() {
+            expression.visit_variables_mut(&mut |variable| rename(variable));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> (HuleProgram, Vec<String>) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        let mut notes = vec![];
+        RegisterAllocationPass.run(&mut program, &mut notes);
+        (program, notes)
+    }
+
+    fn global_names(program: &HuleProgram) -> Vec<String> {
+        program.globals().iter().map(|global| program.variable(*global).name.clone()).collect()
+    }
+
+    #[test]
+    fn test_disjoint_locals_share_a_register() {
+        let (program, notes) = prepare_test("entry { int a = StrLen(OUT1.Data); OUT1.Data = IntToStr(a); int b = StrLen(OUT2.Data); OUT2.Data = IntToStr(b); }");
+
+        assert!(program.function(FunctionId(0)).locals.is_empty());
+        assert_eq!(global_names(&program), vec!["i1"]);
+        assert_eq!(notes, vec!["allocated 2 locals of '_entry' to registers", "registers used: i1"]);
+    }
+
+    #[test]
+    fn test_overlapping_locals_and_types() {
+        let (program, _) = prepare_test("int i1 = 0; entry { int a = StrLen(OUT1.Data); int b = a + 1; string s = IntToStr(a + b); OUT1.Data = s; }");
+
+        assert_eq!(global_names(&program), vec!["i1", "i2", "i3", "s1"]);
+        assert!(!program.variable(program.globals()[0]).register);
+        assert!(program.variable(program.globals()[1]).register);
+    }
+
+    #[test]
+    fn test_locals_live_across_calls_stay_in_frame() {
+        let (program, _) = prepare_test("int f() { return 1; } entry { int a = StrLen(OUT1.Data); int b = f(); OUT1.Data = IntToStr(a + b); }");
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(entry.locals.len(), 1);
+        assert_eq!(program.variable(entry.locals[0]).name, "a");
+    }
+}
@@ -0,0 +1,132 @@
+//! hyperULE CFG Simplification
+//!
+//! Every basic block becomes a state of the ULE runtime, so the number of
+//! blocks directly affects the size of the generated script. This pass
+//! - redirects jumps to empty blocks which only jump on,
+//! - turns branches with two identical targets into jumps,
+//! - merges a block into its only predecessor if that one jumps to it and
+//! - drops blocks which became unreachable.
+
+use crate::cfg::FlowGraph;
+use crate::optimizer::OptimizerPass;
+use crate::program::{BlockId, HuleFunction, HuleProgram, Terminator};
+
+pub struct SimplifyCfgPass;
+
+impl OptimizerPass for SimplifyCfgPass {
+    fn name(&self) -> &'static str {
+        "simplify-cfg"
+    }
+
+    fn run(&self, program: &mut HuleProgram, _notes: &mut Vec<String>) {
+        for id in program.function_ids().collect::<Vec<_>>() {
+            simplify_function(program.function_mut(id));
+        }
+    }
+}
+
+pub fn simplify_function(function: &mut HuleFunction) {
+    thread_jumps(function);
+    function.remove_unreachable_blocks();
+    merge_blocks(function);
+    function.remove_unreachable_blocks();
+}
+
+/// Follows a chain of empty blocks ending in a `goto` to its final target.
+fn jump_target(function: &HuleFunction, block: BlockId) -> BlockId {
+    let mut target = block;
+    let mut visited = vec![false; function.blocks.len()];
+
+    while !visited[target.0] {
+        visited[target.0] = true;
+        let block = function.block(target);
+        match block.terminator {
+            Terminator::Goto(next) if block.code_len() == 0 => target = next,
+            _ => break,
+        }
+    }
+
+    target
+}
+
+fn thread_jumps(function: &mut HuleFunction) {
+    let targets: Vec<BlockId> = (0..function.blocks.len())
+        .map(|index| jump_target(function, BlockId(index)))
+        .collect();
+
+    for block in &mut function.blocks {
+        block.terminator.map_successors(|target| targets[target.0]);
+
+        if let Terminator::Branch { then_block, else_block, .. } = block.terminator {
+            // conditions never contain calls, so dropping them is safe
+            if then_block == else_block {
+                block.terminator = Terminator::Goto(then_block);
+            }
+        }
+    }
+}
+
+fn merge_blocks(function: &mut HuleFunction) {
+    let mut predecessors = vec![0; function.blocks.len()];
+    let reachable = function.reachable_blocks();
+    for (index, block) in function.blocks.iter().enumerate() {
+        if reachable[index] {
+            for successor in block.terminator.successors() {
+                predecessors[successor.0] += 1;
+            }
+        }
+    }
+
+    for index in 0..function.blocks.len() {
+        // a block may absorb a whole chain of successors
+        while let Terminator::Goto(target) = function.blocks[index].terminator {
+            if target.0 == index || target == BlockId(0) || predecessors[target.0] != 1 {
+                break;
+            }
+
+            // the merged block stays in place until it is dropped as
+            // unreachable, its only predecessor no longer jumps to it
+            let merged = function.block(target).clone();
+            let block = &mut function.blocks[index];
+            block.instructions.extend(merged.instructions);
+            block.terminator = merged.terminator;
+            predecessors[target.0] = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+    use crate::program::FunctionId;
+
+    fn prepare_test(input: &str) -> HuleProgram {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        SimplifyCfgPass.run(&mut program, &mut vec![]);
+        program
+    }
+
+    #[test]
+    fn test_if_without_else() {
+        let program = prepare_test("entry { if (OUT1.Data == \"a\") { OUT1.Data = \"b\"; } OUT1.Data = \"c\"; }");
+        let entry = program.function(FunctionId(0));
+
+        // condition, then branch and join block
+        assert_eq!(entry.blocks.len(), 3);
+        assert!(matches!(entry.blocks[0].terminator, Terminator::Branch { else_block, .. } if else_block == BlockId(2)));
+    }
+
+    #[test]
+    fn test_merge_straight_line_blocks() {
+        let program = prepare_test("entry { if (1 == 1) { } else { } OUT1.Data = \"c\"; }");
+        let entry = program.function(FunctionId(0));
+
+        assert_eq!(entry.blocks.len(), 1);
+        assert_eq!(entry.blocks[0].instructions.len(), 1);
+        assert_eq!(entry.blocks[0].terminator, Terminator::Return(None));
+    }
+}
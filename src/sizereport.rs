@@ -0,0 +1,196 @@
+//! hyperULE Size Report
+//!
+//! Script storage on the device is small. The size report attributes the
+//! bytes of the ULE output to the runtime prelude, every function, every
+//! global and every string literal, so it is visible what costs space.
+//! `--max-size` turns the total into a budget which fails the build.
+//!
+//! The bytes are counted by the writer on the generated ULE code after
+//! peephole optimization, minification and styling. The state dispatch and
+//! the trampoline loop count as runtime, the code of a state counts for its
+//! function.
+
+use crate::literals::escape_string;
+use crate::ule::CodeOwner;
+use crate::writer::CodeSizes;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SizeCategory {
+    Prelude,
+    Function,
+    Global,
+    Literal,
+}
+
+impl SizeCategory {
+    fn name(&self) -> &'static str {
+        match self {
+            SizeCategory::Prelude => "runtime",
+            SizeCategory::Function => "function",
+            SizeCategory::Global => "global",
+            SizeCategory::Literal => "literal",
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SizeEntry {
+    pub category: SizeCategory,
+    pub name: String,
+    pub bytes: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SizeError {
+    BudgetExceeded { size: usize, budget: usize },
+}
+
+impl SizeError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            SizeError::BudgetExceeded { size, budget }
+            => format!("Output size of {} bytes exceeds the budget of {} bytes by {} bytes.", size, budget, size - budget),
+        }
+    }
+}
+
+pub struct SizeReport {
+    pub entries: Vec<SizeEntry>,
+}
+
+impl SizeReport {
+    /// Attributes the bytes counted while writing the ULE code. The
+    /// runtime comes first, followed by the globals, the functions and the
+    /// string literals in order of appearance.
+    pub fn measure(sizes: &CodeSizes) -> SizeReport {
+        let mut entries = vec![SizeEntry { category: SizeCategory::Prelude, name: "prelude".to_string(), bytes: 0 }];
+        let mut functions = vec![];
+        for (owner, bytes) in &sizes.owners {
+            match owner {
+                CodeOwner::Runtime => entries[0].bytes += bytes,
+                CodeOwner::Global(name)
+                => entries.push(SizeEntry { category: SizeCategory::Global, name: name.clone(), bytes: *bytes }),
+                CodeOwner::Function(name)
+                => functions.push(SizeEntry { category: SizeCategory::Function, name: name.clone(), bytes: *bytes }),
+            }
+        }
+        entries.append(&mut functions);
+
+        for (value, bytes) in &sizes.literals {
+            entries.push(SizeEntry { category: SizeCategory::Literal, name: format!("\"{}\"", escape_string(value)), bytes: *bytes });
+        }
+
+        SizeReport { entries }
+    }
+
+    pub fn total(&self) -> usize {
+        self.entries.iter().map(|entry| entry.bytes).sum()
+    }
+
+    pub fn check_budget(&self, budget: usize) -> Result<(), SizeError> {
+        match self.total() {
+            size if size > budget => Err(SizeError::BudgetExceeded { size, budget }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut result = String::from("ULE output size (bytes)\n");
+        for entry in &self.entries {
+            result.push_str(&format!("  {:<10}{:<30}{:>8}\n", entry.category.name(), entry.name, entry.bytes));
+        }
+        result.push_str(&format!("  {:<40}{:>8}\n", "total", self.total()));
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::HyperBackend;
+    use crate::lowering::lower;
+    use crate::optimizer::OptimizerPass;
+    use crate::parser::AstParser;
+    use crate::program::HuleProgram;
+    use crate::regalloc::RegisterAllocationPass;
+    use crate::ule::{UleBackend, UleOptions, DEFAULT_HEAP_ERROR_FIELD, DEFAULT_HEAP_SIZE};
+    use crate::writer::UleStyle;
+
+    fn lower_test(input: &str) -> HuleProgram {
+        let mut parser = AstParser::new(input);
+        lower(&parser.parse(input).unwrap()).unwrap()
+    }
+
+    /// The report and the length of the ULE code of the program.
+    fn prepare_test(program: &HuleProgram, style: UleStyle) -> (SizeReport, usize) {
+        let backend = UleBackend::new(UleOptions {
+            peephole: true,
+            minify: style == UleStyle::Compact,
+            frame_width: None,
+            heap_size: DEFAULT_HEAP_SIZE,
+            heap_error: DEFAULT_HEAP_ERROR_FIELD.to_string(),
+            style,
+            file: "test.hule".to_string(),
+        });
+        let output = backend.generate(program).unwrap();
+        (SizeReport::measure(&output.sizes), output.source.len())
+    }
+
+    fn entry<'r>(report: &'r SizeReport, category: SizeCategory, name: &str) -> &'r SizeEntry {
+        report.entries.iter().find(|entry| entry.category == category && entry.name == name).unwrap()
+    }
+
+    #[test]
+    fn test_attribution() {
+        let program = lower_test("string name = \"x\"; string greet() { return \"Hi\"; } \
+            entry { OUT1.Data = greet(); OUT2.Data = \"Hi\"; }");
+        let (report, size) = prepare_test(&program, UleStyle::Compact);
+
+        assert_eq!(report.total(), size);
+        assert_eq!(entry(&report, SizeCategory::Literal, "\"x\"").bytes, 3);
+        assert_eq!(entry(&report, SizeCategory::Literal, "\"Hi\"").bytes, 8);
+        // `a=` and `;` of the minified global
+        assert_eq!(entry(&report, SizeCategory::Global, "name").bytes, 3);
+        // `rs=;` minified, the string is counted as literal
+        assert_eq!(entry(&report, SizeCategory::Function, "greet").bytes, 3);
+        assert!(report.entries.iter().all(|entry| entry.bytes > 0));
+    }
+
+    #[test]
+    fn test_budget() {
+        let (report, size) = prepare_test(&lower_test("entry { OUT1.Data = \"a\"; }"), UleStyle::Pretty);
+
+        assert_eq!(report.check_budget(size), Ok(()));
+        assert_eq!(report.check_budget(size - 1), Err(SizeError::BudgetExceeded { size, budget: size - 1 }));
+        // the scope around the program
+        assert_eq!(entry(&report, SizeCategory::Prelude, "prelude").bytes, "{\n}\n".len());
+        assert_eq!(entry(&report, SizeCategory::Function, "_entry").bytes, "    OUT1.Data = ;\n".len());
+    }
+
+    #[test]
+    fn test_measured_total() {
+        let program = lower_test("string greet(string name) { return \"Hi \" + name; } \
+            entry { OUT1.Data = greet(\"a\"); OUT2.Data = greet(\"b\"); }");
+
+        for style in [UleStyle::Pretty, UleStyle::Compact] {
+            let (report, size) = prepare_test(&program, style);
+
+            assert_eq!(report.total(), size);
+            assert!(report.entries.iter().all(|entry| entry.bytes > 0));
+        }
+    }
+
+    #[test]
+    fn test_registers_are_runtime() {
+        let mut program = lower_test("int counter = 1; entry { int a = StrLen(OUT1.Data); OUT1.Data = IntToStr(a + counter); }");
+        RegisterAllocationPass.run(&mut program, &mut vec![]);
+
+        let (report, size) = prepare_test(&program, UleStyle::Pretty);
+        assert_eq!(report.total(), size);
+        let globals: Vec<&str> = report.entries.iter()
+            .filter(|entry| entry.category == SizeCategory::Global)
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(globals, vec!["counter"]);
+    }
+}
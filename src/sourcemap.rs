@@ -0,0 +1,220 @@
+//! hyperULE Source Maps
+//!
+//! Maps positions in the generated ULE code back to the hyperULE statement
+//! they were generated from. The printer reports runtime errors by ULE
+//! line, `hyperULECompiler map <file.map> <line>` turns it into a line of
+//! the `.hule` source.
+//!
+//! Each entry starts at a ULE position and lasts until the next entry or the
+//! end of the ULE code. The code of the runtime, like the state dispatch,
+//! belongs to no statement.
+
+use crate::ast::SourcePosition;
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct SourceMapEntry {
+    /// Line and column in the ULE code, both start at 1
+    pub ule: SourcePosition,
+    /// Statement in the hyperULE source, none for runtime code
+    pub hule: Option<SourcePosition>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SourceMap {
+    /// Name of the hyperULE source
+    pub file: String,
+    /// Entries in order of their ULE position
+    pub entries: Vec<SourceMapEntry>,
+    /// Position after the last character of the ULE code
+    pub end: SourcePosition,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SourceMapError {
+    InvalidLine(usize, String),
+    MissingEnd,
+}
+
+impl SourceMapError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            SourceMapError::InvalidLine(number, line)
+            => format!("Line {} of the source map is invalid: '{}'.", number, line),
+            SourceMapError::MissingEnd => "The source map has no 'end' line.".to_string(),
+        }
+    }
+}
+
+impl SourceMap {
+    /// Builds the map from the statement in effect at byte offsets of the
+    /// ULE code. Consecutive offsets of the same statement are merged.
+    pub fn from_offsets(file: &str, source: &str, offsets: &[(usize, Option<SourcePosition>)]) -> SourceMap {
+        let mut entries: Vec<SourceMapEntry> = vec![];
+        let mut offsets = offsets.iter().peekable();
+        let mut position = SourcePosition { line: 1, column: 1 };
+
+        let mut end = position;
+        for (offset, character) in source.char_indices().chain([(source.len(), '\n')]) {
+            end = position;
+            while let Some((_, hule)) = offsets.next_if(|(start, _)| *start == offset) {
+                // of several nodes at the same offset the innermost counts
+                if entries.last().is_some_and(|entry| entry.ule == position) {
+                    entries.pop();
+                }
+                if entries.last().map(|entry| entry.hule) != Some(*hule) {
+                    entries.push(SourceMapEntry { ule: position, hule: *hule });
+                }
+            }
+
+            position = match character {
+                '\n' => SourcePosition { line: position.line + 1, column: 1 },
+                _ => SourcePosition { line: position.line, column: position.column + 1 },
+            };
+        }
+
+        SourceMap { file: file.to_string(), entries, end }
+    }
+
+    /// Content of the map file, the name of the source followed by one
+    /// `<ule line>:<column> = <line>:<column>` line per entry, `-` for
+    /// runtime code, and the end of the ULE code.
+    pub fn to_map_text(&self) -> String {
+        let mut result = format!("file = {}\n", self.file);
+        for entry in &self.entries {
+            let hule = match entry.hule {
+                Some(position) => format!("{}:{}", position.line, position.column),
+                None => "-".to_string(),
+            };
+            result.push_str(&format!("{}:{} = {}\n", entry.ule.line, entry.ule.column, hule));
+        }
+        result.push_str(&format!("end = {}:{}\n", self.end.line, self.end.column));
+        result
+    }
+
+    pub fn parse(text: &str) -> Result<SourceMap, SourceMapError> {
+        let mut map = SourceMap { file: String::new(), entries: vec![], end: SourcePosition { line: 1, column: 1 } };
+        let mut has_end = false;
+        for (index, line) in text.lines().enumerate() {
+            let invalid = || SourceMapError::InvalidLine(index + 1, line.to_string());
+            let (key, value) = line.split_once(" = ").ok_or_else(invalid)?;
+
+            if key == "file" {
+                map.file = value.to_string();
+                continue;
+            }
+            if key == "end" {
+                map.end = parse_position(value).ok_or_else(invalid)?;
+                has_end = true;
+                continue;
+            }
+            let ule = parse_position(key).ok_or_else(invalid)?;
+            let hule = match value {
+                "-" => None,
+                _ => Some(parse_position(value).ok_or_else(invalid)?),
+            };
+            map.entries.push(SourceMapEntry { ule, hule });
+        }
+
+        if !has_end {
+            return Err(SourceMapError::MissingEnd);
+        }
+        Ok(map)
+    }
+
+    /// Whether the ULE position, or the start of the line if no column is
+    /// given, is part of the ULE code.
+    pub fn contains(&self, line: usize, column: Option<usize>) -> bool {
+        SourcePosition { line, column: column.unwrap_or(1) } < self.end
+    }
+
+    /// Statements of the ULE position, or of every entry starting on the
+    /// ULE line if no column is given. A line without an entry of its own
+    /// belongs to the entry in effect at its start.
+    pub fn lookup(&self, line: usize, column: Option<usize>) -> Vec<SourcePosition> {
+        let start = SourcePosition { line, column: column.unwrap_or(1) };
+        let on_line: Vec<&SourceMapEntry> = self.entries.iter()
+            .filter(|entry| entry.ule.line == line)
+            .collect();
+
+        let entries = match (column, on_line.is_empty()) {
+            (None, false) => on_line,
+            _ => self.entries.iter().rev().find(|entry| entry.ule <= start).into_iter().collect(),
+        };
+        let mut result = vec![];
+        for hule in entries.into_iter().filter_map(|entry| entry.hule) {
+            if !result.contains(&hule) {
+                result.push(hule);
+            }
+        }
+        result
+    }
+}
+
+/// `<line>:<column>`
+fn parse_position(text: &str) -> Option<SourcePosition> {
+    let (line, column) = text.split_once(':')?;
+    Some(SourcePosition { line: line.parse().ok()?, column: column.parse().ok()? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: usize, column: usize) -> SourcePosition {
+        SourcePosition { line, column }
+    }
+
+    fn prepare_test() -> SourceMap {
+        let source = "fc = 0;\nif (fc == 0) {\n    x = 1; y = 2;\n}\n";
+        SourceMap::from_offsets("main.hule", source, &[
+            (0, None),
+            (27, Some(position(4, 5))),
+            (34, Some(position(5, 5))),
+            (41, None),
+        ])
+    }
+
+    #[test]
+    fn test_entries() {
+        let map = prepare_test();
+
+        assert_eq!(map.entries, vec![
+            SourceMapEntry { ule: position(1, 1), hule: None },
+            SourceMapEntry { ule: position(3, 5), hule: Some(position(4, 5)) },
+            SourceMapEntry { ule: position(3, 12), hule: Some(position(5, 5)) },
+            SourceMapEntry { ule: position(4, 1), hule: None },
+        ]);
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let map = prepare_test();
+        let text = map.to_map_text();
+
+        assert_eq!(text, "file = main.hule\n1:1 = -\n3:5 = 4:5\n3:12 = 5:5\n4:1 = -\nend = 5:1\n");
+        assert_eq!(SourceMap::parse(&text), Ok(map));
+        assert_eq!(SourceMap::parse("file = a\n3 = 4:5").err(), Some(SourceMapError::InvalidLine(2, "3 = 4:5".to_string())));
+        assert_eq!(SourceMap::parse("file = a\n1:1 = -").err(), Some(SourceMapError::MissingEnd));
+    }
+
+    #[test]
+    fn test_lookup() {
+        let map = prepare_test();
+
+        assert_eq!(map.lookup(3, None), vec![position(4, 5), position(5, 5)]);
+        assert_eq!(map.lookup(3, Some(14)), vec![position(5, 5)]);
+        assert_eq!(map.lookup(2, None), vec![]);
+        assert_eq!(map.lookup(4, None), vec![]);
+        assert_eq!(map.lookup(9, None), vec![]);
+    }
+
+    #[test]
+    fn test_contains() {
+        let map = prepare_test();
+
+        assert!(map.contains(4, None));
+        assert!(map.contains(4, Some(1)));
+        assert!(!map.contains(5, None));
+        assert!(!map.contains(9999, None));
+    }
+}
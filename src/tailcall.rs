@@ -0,0 +1,201 @@
+//! hyperULE Tail Calls
+//!
+//! Every call of a recursive function pushes a frame onto the string encoded
+//! `callStack`, so deep recursion grows it without bounds. A call whose
+//! result is returned right away is a tail call, the frame of the caller is
+//! not needed anymore once it starts:
+//! - a function calling itself in tail position overwrites its parameters
+//!   and jumps back to its start, the recursion becomes a loop,
+//! - tail calls between mutually recursive functions become a `TailCall`,
+//!   which the runtime lowers to replacing the caller frame by the callee
+//!   frame.
+//!
+//! Both run in constant stack size. Tail calls of functions which can not
+//! call the caller again are kept, they never grow the stack without bounds.
+
+use crate::callgraph::CallGraph;
+use crate::frames::default_value;
+use crate::liveness::Liveness;
+use crate::optimizer::OptimizerPass;
+use crate::program::*;
+
+pub struct TailCallPass;
+
+impl OptimizerPass for TailCallPass {
+    fn name(&self) -> &'static str {
+        "tail-calls"
+    }
+
+    fn run(&self, program: &mut HuleProgram, notes: &mut Vec<String>) {
+        let graph = CallGraph::from_program(program);
+
+        for id in program.function_ids().collect::<Vec<_>>() {
+            let mut loop_header = None;
+
+            for index in 0..program.function(id).blocks.len() {
+                let callee = match tail_call(program.function(id), BlockId(index)) {
+                    Some(callee) if graph.reaches(callee.0, id.0) => callee,
+                    _ => continue,
+                };
+
+                let name = program.function(callee).name.clone();
+                if callee == id {
+                    let header = *loop_header.get_or_insert_with(|| split_entry(program.function_mut(id)));
+                    replace_frame(program, id, BlockId(index), header);
+                    notes.push(format!("recursive tail call of '{}' turned into a loop", name));
+                } else {
+                    let block = program.function_mut(id).block_mut(BlockId(index));
+                    if let Terminator::Call { function, arguments, .. } = block.terminator.clone() {
+                        block.terminator = Terminator::TailCall { function, arguments };
+                    }
+                    notes.push(format!("tail call of '{}' in '{}' replaces the frame", name, program.function(id).name));
+                }
+            }
+        }
+    }
+}
+
+/// Callee of the call ending `block`, if the result of the call is returned
+/// right away.
+fn tail_call(function: &HuleFunction, block: BlockId) -> Option<FunctionId> {
+    let (callee, result, mut next) = match &function.block(block).terminator {
+        Terminator::Call { function, result, continuation, .. } => (*function, result, *continuation),
+        _ => return None,
+    };
+
+    // empty blocks between the call and the return
+    let mut visited = vec![false; function.blocks.len()];
+    loop {
+        let continuation = function.block(next);
+        if continuation.code_len() > 0 || visited[next.0] {
+            return None;
+        }
+        visited[next.0] = true;
+
+        match (&continuation.terminator, result) {
+            (Terminator::Goto(target), _) => next = *target,
+            (Terminator::Return(None), None) => return Some(callee),
+            (Terminator::Return(Some(IrExpression::Variable(value))), Some(IrTarget::Variable(result)))
+            if value == result => return Some(callee),
+            _ => return None,
+        }
+    }
+}
+
+/// Moves the body of the entry block into a new block, so the start of the
+/// function can be jumped to. Returns the new block.
+fn split_entry(function: &mut HuleFunction) -> BlockId {
+    let header = BlockId(function.blocks.len());
+    let entry = std::mem::replace(&mut function.blocks[0], BasicBlock {
+        instructions: vec![],
+        terminator: Terminator::Goto(header),
+    });
+
+    function.blocks.push(entry);
+    for block in &mut function.blocks {
+        block.terminator.map_successors(|target| if target == BlockId(0) { header } else { target });
+    }
+    function.blocks[0].terminator = Terminator::Goto(header);
+    header
+}
+
+/// Replaces the recursive call ending `block` by assigning the arguments to
+/// the parameters and jumping to `header`.
+fn replace_frame(program: &mut HuleProgram, id: FunctionId, block: BlockId, header: BlockId) {
+    let arguments = match program.function(id).block(block).terminator.clone() {
+        Terminator::Call { arguments, .. } => arguments,
+        _ => return,
+    };
+    let parameters = program.function(id).parameters.clone();
+
+    // arguments reading a parameter which is assigned before them are
+    // evaluated into a temporary first
+    let mut temporaries = vec![];
+    let mut assignments = vec![];
+    for (index, (parameter, argument)) in parameters.iter().zip(arguments).enumerate() {
+        let reads_assigned = parameters[..index].iter()
+            .any(|assigned| argument.reads_target(&IrTarget::Variable(*assigned)));
+
+        let value = if reads_assigned {
+            let temporary = program.add_temporary(id, program.variable(*parameter).data_type.clone());
+            temporaries.push(IrInstruction::Assign(IrTarget::Variable(temporary), argument));
+            IrExpression::Variable(temporary)
+        } else {
+            argument
+        };
+        assignments.push(IrInstruction::Assign(IrTarget::Variable(*parameter), value));
+    }
+
+    // a new frame starts with default values for locals read before their
+    // first assignment
+    let function = program.function(id);
+    let live = &Liveness::compute(function).live_in[header.0];
+    let defaults: Vec<IrInstruction> = function.locals.iter()
+        .filter(|local| live.contains(local))
+        .filter_map(|local| default_value(&program.variable(*local).data_type)
+            .map(|value| IrInstruction::Assign(IrTarget::Variable(*local), value)))
+        .collect();
+
+    let block = program.function_mut(id).block_mut(block);
+    block.instructions.extend(temporaries);
+    block.instructions.extend(assignments);
+    block.instructions.extend(defaults);
+    block.terminator = Terminator::Goto(header);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::lower;
+    use crate::parser::AstParser;
+
+    fn prepare_test(input: &str) -> (HuleProgram, Vec<String>) {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        let mut notes = vec![];
+        TailCallPass.run(&mut program, &mut notes);
+        (program, notes)
+    }
+
+    fn has_calls(function: &HuleFunction) -> bool {
+        function.blocks.iter().any(|block| matches!(block.terminator, Terminator::Call { .. }))
+    }
+
+    #[test]
+    fn test_self_tail_call_becomes_loop() {
+        let (program, notes) = prepare_test("int sum(int n, int total) { if (n == 0) { return total; } return sum(n - 1, total + n); } \
+            entry { OUT1.Data = IntToStr(sum(3, 0)); }");
+        let sum = program.function(FunctionId(1));
+
+        assert!(!has_calls(sum));
+        assert_eq!(sum.blocks[0].terminator, Terminator::Goto(BlockId(sum.blocks.len() - 1)));
+        assert_eq!(notes, vec!["recursive tail call of 'sum' turned into a loop"]);
+        assert!(!CallGraph::from_program(&program).is_recursive(1));
+    }
+
+    #[test]
+    fn test_swapped_parameters_use_temporaries() {
+        let (program, _) = prepare_test("int f(int a, int b) { if (a > b) { return a; } return f(b, a); } entry { f(1, 2); }");
+        let f = program.function(FunctionId(1));
+        let jump = f.blocks.iter().find(|block| block.instructions.len() == 3).unwrap();
+        let temporary = *f.locals.last().unwrap();
+
+        assert_eq!(jump.instructions[0], IrInstruction::Assign(IrTarget::Variable(temporary), IrExpression::Variable(f.parameters[0])));
+        assert_eq!(jump.instructions[1], IrInstruction::Assign(IrTarget::Variable(f.parameters[0]), IrExpression::Variable(f.parameters[1])));
+        assert_eq!(jump.instructions[2], IrInstruction::Assign(IrTarget::Variable(f.parameters[1]), IrExpression::Variable(temporary)));
+    }
+
+    #[test]
+    fn test_mutual_tail_calls() {
+        let (program, notes) = prepare_test("bool even(int n) { if (n == 0) { return true; } return odd(n - 1); } \
+            bool odd(int n) { if (n == 0) { return false; } return even(n - 1); } \
+            int twice(int n) { return n * 2; } \
+            entry { if (even(twice(3))) { OUT1.Data = \"even\"; } }");
+
+        assert_eq!(notes.len(), 2);
+        assert!(program.functions().iter().filter(|function| function.name != "_entry")
+            .all(|function| !has_calls(function)));
+        assert!(has_calls(program.function(FunctionId(0))));
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use crate::ast::SourcePosition;
 use crate::tokens::*;
 
 pub struct Tokenized {
@@ -37,6 +38,11 @@ impl Tokenized {
         Some(self.tokens[index].clone())
     }
 
+    /// Position of the token `next` returns, if there is one.
+    pub fn next_position(&self) -> Option<SourcePosition> {
+        self.tokens.get((self.current_token_index + 1) as usize).map(Token::position)
+    }
+
     pub fn get_current_token(&self) -> Option<Token> {
         if !self.is_currently_in_range() {
             return None;
@@ -160,6 +166,11 @@ pub struct Tokenizer {
     state: TokenizerState,
     current_line_pos: usize,
     current_char_index: usize,
+    current_line: usize,
+    /// Index of the first character of the current line
+    line_start_index: usize,
+    /// Position of the first character in `buffer`
+    buffer_position: SourcePosition,
 }
 
 impl Tokenizer {
@@ -191,6 +202,9 @@ impl Tokenizer {
             state: TokenizerState::Unknown,
             current_line_pos: 0,
             current_char_index: 0,
+            current_line: 1,
+            line_start_index: 0,
+            buffer_position: SourcePosition { line: 1, column: 1 },
         }
     }
 
@@ -202,6 +216,8 @@ impl Tokenizer {
         self.state = TokenizerState::Unknown;
         self.current_char_index = 0;
         self.current_line_pos = 0;
+        self.current_line = 1;
+        self.line_start_index = 0;
 
         let chars: Vec<char> = self.source.chars().collect();
         while self.current_char_index < chars.len() {
@@ -221,6 +237,10 @@ impl Tokenizer {
 
             self.current_char_index += 1;
             self.current_line_pos += 1;
+            if current_char == '\n' {
+                self.current_line += 1;
+                self.line_start_index = self.current_char_index;
+            }
         }
 
         // Add any remaining buffer as a token
@@ -236,7 +256,7 @@ impl Tokenizer {
             if self.string_chars.contains(&current_char) {
                 self.set_state_and_add_token(TokenizerState::String, tokenized);
                 self.current_string_char = current_char;
-                self.buffer.push(current_char);
+                self.push_to_buffer(current_char);
                 return;
             } else if current_char == '/' && next_char == Some('*') {
                 self.set_state_and_add_token(TokenizerState::StartStopComment, tokenized);
@@ -253,7 +273,7 @@ impl Tokenizer {
 
         match self.state {
             TokenizerState::Unknown => {
-                self.buffer.push(current_char);
+                self.push_to_buffer(current_char);
             }
 
             TokenizerState::String => {
@@ -261,7 +281,7 @@ impl Tokenizer {
                     self.string_escaped = true;
                 }
 
-                self.buffer.push(current_char);
+                self.push_to_buffer(current_char);
 
                 if !self.string_escaped && current_char == self.current_string_char {
                     self.state = TokenizerState::Unknown;
@@ -285,18 +305,18 @@ impl Tokenizer {
             }
 
             TokenizerState::StopChar => {
-                let mut found_together = false;
-                for keep_together in &self.keep_together {
-                    if self.is_next_sequence(keep_together) {
-                        self.buffer.push_str(keep_together);
-                        self.advance_chars(keep_together.len() - 1);
-                        found_together = true;
-                        break;
+                let together = self.keep_together.iter()
+                    .find(|keep_together| self.is_next_sequence(keep_together))
+                    .cloned();
+                if let Some(keep_together) = &together {
+                    for character in keep_together.chars() {
+                        self.push_to_buffer(character);
                     }
+                    self.advance_chars(keep_together.len() - 1);
                 }
 
-                if !found_together && !self.ignore_chars.contains(&current_char) {
-                    self.buffer.push(current_char);
+                if together.is_none() && !self.ignore_chars.contains(&current_char) {
+                    self.push_to_buffer(current_char);
                 }
 
                 self.set_state_and_add_token(TokenizerState::Unknown, tokenized);
@@ -324,13 +344,25 @@ impl Tokenizer {
         self.current_char_index += count;
     }
 
+    /// Adds the character to the token in `buffer`, remembering where the
+    /// token starts.
+    fn push_to_buffer(&mut self, character: char) {
+        if self.buffer.is_empty() {
+            self.buffer_position = SourcePosition {
+                line: self.current_line,
+                column: self.current_char_index - self.line_start_index + 1,
+            };
+        }
+        self.buffer.push(character);
+    }
+
     fn add_token(&mut self, tokenized: &mut Tokenized) {
         if self.buffer.is_empty() {
             return;
         }
 
         let token_str = self.buffer.clone();
-        tokenized.tokens.push(Token::new(token_str.to_string(), 0, 0));
+        tokenized.tokens.push(Token::new(token_str.to_string(), self.buffer_position));
         self.buffer.clear();
     }
 
@@ -378,4 +410,24 @@ mod tests {
 
         assert_eq!(tokenized.get_token_count(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_token_positions() {
+        let tokenized = self::prepare_test("int a;\n  a = \"x y\" >= 2;");
+        let positions: Vec<(String, usize, usize)> = (0..tokenized.get_token_count())
+            .map(|index| tokenized.get_token(index).unwrap())
+            .map(|token| (token.value.clone(), token.position().line, token.position().column))
+            .collect();
+
+        assert_eq!(positions[3], ("a".to_string(), 2, 3));
+        assert_eq!(positions[5], ("\"x y\"".to_string(), 2, 7));
+        assert_eq!(positions[6], (">=".to_string(), 2, 13));
+
+        let mut tokenized = tokenized;
+        assert_eq!(tokenized.next_position(), Some(SourcePosition { line: 1, column: 1 }));
+        tokenized.set_current_token_index(2);
+        assert_eq!(tokenized.next_position(), Some(SourcePosition { line: 2, column: 3 }));
+        tokenized.set_current_token_index(tokenized.get_token_count() - 1);
+        assert_eq!(tokenized.next_position(), None);
+    }
+}
@@ -1,5 +1,5 @@
 use std::cmp::PartialEq;
-use crate::ast::{HuleExpressionResultExt, Operator};
+use crate::ast::{HuleExpressionResultExt, Operator, SourcePosition};
 use crate::parser::{AstParser, AstParserError};
 
 #[derive(PartialEq, Copy, Clone, Debug,)]
@@ -69,8 +69,7 @@ impl TokenType {
 pub struct Token {
     token_type: TokenType,
     pub value: String,
-    token_start : u32,
-    token_end : u32
+    position: SourcePosition,
 }
 
 impl HuleExpressionResultExt for Result<Operator, AstParserError> {
@@ -89,15 +88,19 @@ impl HuleExpressionResultExt for Result<Operator, AstParserError> {
 }
 
 impl Token {
-    pub fn new(value : String, token_start : u32, token_end : u32) -> Token {
+    pub fn new(value : String, position: SourcePosition) -> Token {
         Token {
             token_type: TokenType::Unknown,
             value,
-            token_start,
-            token_end,
+            position,
         }
     }
 
+    /// Line and column of the first character of the token.
+    pub fn position(&self) -> SourcePosition {
+        self.position
+    }
+
     pub fn get_calculated_token_type(&self) -> TokenType {
         let token: &str = &self.value;
         match token {
@@ -125,9 +128,9 @@ impl Token {
             _ => {
                 if token.starts_with('"') && token.ends_with('"') {
                     TokenType::ConstStringExpression
-                } else if let Ok(_) = &self.value.parse::<i32>() {
-                    TokenType::ConstIntegerExpression
-                } else if let Ok(_) = &self.value.parse::<i32>() {
+                } else if token.strip_prefix('-').unwrap_or(token).bytes().all(|byte| byte.is_ascii_digit())
+                    && token.bytes().any(|byte| byte.is_ascii_digit()) {
+                    // out of range values are reported by the parser
                     TokenType::ConstIntegerExpression
                 } else {
                     TokenType::Identifier
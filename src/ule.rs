@@ -1,34 +1,1135 @@
-use crate::ast::HuleExpression;
-use crate::generator::{HyperBackend, HyperNode};
-use crate::program::HuleProgram;
+//! hyperULE ULE Backend
+//!
+//! ULE has no functions, so the program is wrapped into the runtime of
+//! `docs/3_CodeGen.md`. Every basic block becomes a state of a loop which
+//! dispatches on the state on top of `callStack`:
+//!
+//! ```text
+//! while (StrLen(callStack) > 0) {
+//!     fc = StrToInt(Right(callStack, 3));
+//!     csSize = StrLen(callStack);
+//!     if (fc == 0) { ... } else if (fc == 1) { ... }
+//!     callStack = Left(callStack, csSize - 3) + csAppend;
+//!     csAppend = "";
+//! }
+//! ```
+//!
+//! Each state replaces itself by what it appends to `csAppend`: the next
+//! state on a jump, nothing on a `return`. A call appends the continuation
+//! state of the caller and the frame of the callee, which is a pointer to
+//! every argument followed by the first state of the callee. Arguments are
+//! length prefixed records on the heap `dynStack`. Variables of a function
+//! live in ULE variables while it runs, the ones still needed after a call
+//! are stored on the heap and referenced below the continuation state if
+//! the callee may call the function again.
+//!
+//! Programs without calls only need the `fc` state loop, programs with a
+//! single block no runtime at all.
 
-struct UleBackend {
+use std::collections::HashMap;
+use crate::ast::{Operator, SourcePosition};
+use crate::callgraph::CallGraph;
+use crate::generator::HyperBackend;
+use crate::liveness::Liveness;
+use crate::minify::{is_reserved, minify, MinifyMap};
+use crate::peephole::{self, PeepholeStats};
+use crate::program::*;
+use crate::sourcemap::SourceMap;
+use crate::writer::{CodeSizes, UleStyle, UleWriter};
 
+/// Heap size in bytes assumed for the width of the frame fields if none is
+/// configured.
+pub const DEFAULT_HEAP_SIZE: usize = 999;
+
+/// Largest heap size, records are addressed by ULE integers.
+pub const MAX_HEAP_SIZE: usize = i32::MAX as usize;
+
+/// Largest width of the frame fields, enough for any heap address.
+pub const MAX_FRAME_WIDTH: usize = MAX_HEAP_SIZE.ilog10() as usize + 1;
+
+/// Device field the compiler reports a heap overflow in if none is
+/// configured.
+pub const DEFAULT_HEAP_ERROR_FIELD: &str = "OUT1.Data";
+
+/// Value written to the configured device field on a heap overflow.
+pub const HEAP_OVERFLOW_VALUE: &str = "HEAP OVERFLOW";
+
+/// Variables of the runtime, user variables never use these names.
+const RUNTIME_NAMES: &[&str] = &["callStack", "dynStack", "csSize", "csAppend", "fc", "hp", "hv", "hx", "ri", "rs"];
+
+pub struct UleOptions {
+    /// Run the peephole optimizer on the generated code
+    pub peephole: bool,
+    /// Rename every variable to the shortest possible name
+    pub minify: bool,
+    /// Digits of the fields on the call stack and of the length prefixes of
+    /// heap records, chosen from the number of states and the heap size if
+    /// not set, at most `MAX_FRAME_WIDTH`
+    pub frame_width: Option<usize>,
+    /// Largest size of `dynStack` in bytes, at most `MAX_HEAP_SIZE`
+    pub heap_size: usize,
+    /// Device field set to `HEAP_OVERFLOW_VALUE` if the heap overflows,
+    /// `DEFAULT_HEAP_ERROR_FIELD` unless configured
+    pub heap_error: String,
+    pub style: UleStyle,
+    /// Name of the hyperULE source in the comments of the pretty style and
+    /// in the source map
+    pub file: String,
+}
+
+pub struct UleBackend {
+    options: UleOptions,
+}
+
+impl UleBackend {
+    pub fn new(options: UleOptions) -> UleBackend {
+        UleBackend { options }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UleError {
+    FrameWidth { width: usize, required: usize },
+}
+
+impl UleError {
+    pub fn to_message(&self) -> String {
+        match &self {
+            UleError::FrameWidth { width, required }
+            => format!("Frame fields of {} digit(s) are too small, the states and the heap need {} digit(s).", width, required),
+        }
+    }
+}
+
+pub struct UleOutput {
+    pub source: String,
+    /// Digits of the frame fields, 0 if the call stack is not used
+    pub frame_width: usize,
+    /// Original names of the minified variables
+    pub names: Option<MinifyMap>,
+    pub peephole: PeepholeStats,
+    /// Statements the positions of the source were generated from
+    pub source_map: SourceMap,
+    /// Bytes of the source by the part of the program they belong to
+    pub sizes: CodeSizes,
 }
 
-struct UleScope {
-    parent_scope: Option<Box<UleScope>>,
+/// Expression of the ULE target language. Variables include the runtime
+/// variables, the variables generated for hyperULE code and fields of the
+/// device like `OUT1.Data`.
+#[derive(Clone, PartialEq, Debug)]
+pub enum UleExpression {
+    Integer(i32),
+    String(String),
+    Variable(String),
+    /// Call of a ULE built-in function
+    Call(String, Vec<UleExpression>),
+    Binary {
+        left: Box<UleExpression>,
+        operator: Operator,
+        right: Box<UleExpression>,
+    },
 }
 
-struct UleVarDef {
-    scope: Box<UleScope>,
-    name: String,
-    value: String
+impl UleExpression {
+    /// Number of times the expression reads the variable.
+    pub fn reads(&self, name: &str) -> usize {
+        match self {
+            UleExpression::Variable(variable) => (variable == name) as usize,
+            UleExpression::Call(_, arguments) => arguments.iter().map(|argument| argument.reads(name)).sum(),
+            UleExpression::Binary { left, right, .. } => left.reads(name) + right.reads(name),
+            _ => 0,
+        }
+    }
+
+    /// Replaces every read of the variable by `value`.
+    pub fn substitute(&mut self, name: &str, value: &UleExpression) {
+        match self {
+            UleExpression::Variable(variable) if variable == name => *self = value.clone(),
+            UleExpression::Call(_, arguments) => {
+                for argument in arguments {
+                    argument.substitute(name, value);
+                }
+            }
+            UleExpression::Binary { left, right, .. } => {
+                left.substitute(name, value);
+                right.substitute(name, value);
+            }
+            _ => {}
+        }
+    }
+
+    pub fn visit_variables_mut<F: FnMut(&mut String)>(&mut self, visit: &mut F) {
+        match self {
+            UleExpression::Variable(name) => visit(name),
+            UleExpression::Call(_, arguments) => {
+                for argument in arguments {
+                    argument.visit_variables_mut(visit);
+                }
+            }
+            UleExpression::Binary { left, right, .. } => {
+                left.visit_variables_mut(visit);
+                right.visit_variables_mut(visit);
+            }
+            _ => {}
+        }
+    }
 }
 
-struct UleIfStatement {
-    condition: HuleExpression,
-    body: Vec<Box<UleNode>>
+#[derive(Clone, PartialEq, Debug)]
+pub struct UleScope {
+    pub body: Vec<UleNode>,
 }
 
-enum UleNode {
+#[derive(Clone, PartialEq, Debug)]
+pub struct UleVarDef {
+    pub name: String,
+    pub value: UleExpression,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct UleIfStatement {
+    pub condition: UleExpression,
+    pub body: Vec<UleNode>,
+    /// Else branch, an `else if` is an else branch with a single if statement
+    pub else_body: Vec<UleNode>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct UleWhileLoop {
+    pub condition: UleExpression,
+    pub body: Vec<UleNode>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum UleNode {
     VariableDefinition(UleVarDef),
     IfStatement(UleIfStatement),
-    Scope(UleScope)
+    WhileLoop(UleWhileLoop),
+    Scope(UleScope),
+    /// Start of the code of the hyperULE statement at the position
+    Location(SourcePosition),
+    /// Start of the code of a part of the program in the size report
+    Owner(CodeOwner),
+}
+
+/// Part of the program generated code belongs to. Code outside of every
+/// owner, like the state dispatch, belongs to the runtime.
+#[derive(Clone, PartialEq, Debug)]
+pub enum CodeOwner {
+    Runtime,
+    Global(String),
+    Function(String),
+}
+
+impl UleNode {
+    pub fn is_location(&self) -> bool {
+        matches!(self, UleNode::Location(_))
+    }
+
+    /// Locations and owners, which produce no code of their own.
+    pub fn is_marker(&self) -> bool {
+        matches!(self, UleNode::Location(_) | UleNode::Owner(_))
+    }
+
+    /// Calls `visit` for every variable name read or written by the node
+    /// and its children.
+    pub fn visit_variables_mut<F: FnMut(&mut String)>(&mut self, visit: &mut F) {
+        match self {
+            UleNode::VariableDefinition(definition) => {
+                visit(&mut definition.name);
+                definition.value.visit_variables_mut(visit);
+            }
+            UleNode::IfStatement(if_statement) => {
+                if_statement.condition.visit_variables_mut(visit);
+                for node in if_statement.body.iter_mut().chain(&mut if_statement.else_body) {
+                    node.visit_variables_mut(visit);
+                }
+            }
+            UleNode::WhileLoop(while_loop) => {
+                while_loop.condition.visit_variables_mut(visit);
+                for node in &mut while_loop.body {
+                    node.visit_variables_mut(visit);
+                }
+            }
+            UleNode::Scope(scope) => {
+                for node in &mut scope.body {
+                    node.visit_variables_mut(visit);
+                }
+            }
+            UleNode::Location(_) | UleNode::Owner(_) => {}
+        }
+    }
+}
+
+impl HyperBackend<&HuleProgram, Result<UleOutput, UleError>> for UleBackend {
+    fn generate(&self, program: &HuleProgram) -> Result<UleOutput, UleError> {
+        let generator = Generator::new(program, &self.options)?;
+        let frame_width = if generator.uses_stack { generator.width } else { 0 };
+        let mut nodes = vec![UleNode::Scope(UleScope { body: generator.program() })];
+
+        let peephole = if self.options.peephole { peephole::optimize(&mut nodes) } else { PeepholeStats::default() };
+        let names = self.options.minify.then(|| minify(&mut nodes));
+
+        let written = UleWriter::new(self.options.style, &self.options.file).write(&nodes);
+        Ok(UleOutput { source: written.source, frame_width, names, peephole, source_map: written.source_map, sizes: written.sizes })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum StateKind {
+    /// Sets up the variables of a function whose first block is also the
+    /// target of a jump
+    Entry,
+    Block(BlockId),
+    /// Restores the caller once the call ending the block returned
+    Resume(BlockId),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct State {
+    function: FunctionId,
+    kind: StateKind,
+}
+
+struct Generator<'p> {
+    program: &'p HuleProgram,
+    /// ULE name of every variable
+    names: HashMap<VarId, String>,
+    /// Every state of the runtime, the index is the state number
+    states: Vec<State>,
+    /// Whether the program needs the call stack runtime
+    uses_stack: bool,
+    graph: CallGraph,
+    liveness: HashMap<FunctionId, Liveness>,
+    /// Digits of every field on the call stack, state numbers, pointers to
+    /// heap records and lengths of heap records all use the same width
+    width: usize,
+    heap_size: usize,
+    heap_error: String,
+}
+
+impl<'p> Generator<'p> {
+    fn new(program: &'p HuleProgram, options: &UleOptions) -> Result<Generator<'p>, UleError> {
+        let graph = CallGraph::from_program(program);
+        let reachable = graph.reachable_from(FunctionId(0).0);
+        let functions: Vec<&HuleFunction> = program.functions().iter()
+            .filter(|function| reachable[function.id.0])
+            .collect();
+        let uses_stack = functions.iter()
+            .any(|function| function.blocks.iter().any(|block| matches!(block.terminator,
+                Terminator::Call { .. } | Terminator::TailCall { .. })));
+
+        let mut generator = Generator {
+            program,
+            names: HashMap::new(),
+            states: vec![],
+            uses_stack,
+            graph,
+            liveness: functions.iter().map(|function| (function.id, Liveness::compute(function))).collect(),
+            width: 0,
+            heap_size: options.heap_size,
+            heap_error: options.heap_error.clone(),
+        };
+        generator.assign_names(&functions);
+        for function in functions {
+            generator.add_states(function);
+        }
+
+        // the largest field is the last state, a pointer to the end of the
+        // heap or the length of a record filling the heap. Without the call
+        // stack there are no fields.
+        let required = digits(generator.states.len().saturating_sub(1).max(options.heap_size));
+        generator.width = options.frame_width.unwrap_or(required);
+        if generator.uses_stack && generator.width < required {
+            return Err(UleError::FrameWidth { width: generator.width, required });
+        }
+
+        Ok(generator)
+    }
+
+    /// Call stack field of a constant, like `"002"`.
+    fn field(&self, value: usize) -> UleExpression {
+        UleExpression::String(format!("{:0width$}", value, width = self.width))
+    }
+
+    /// Call stack field of an integer expression, `Right("000" + IntToStr(value), 3)`.
+    fn padded(&self, value: UleExpression) -> UleExpression {
+        call("Right", vec![
+            binary(UleExpression::String("0".repeat(self.width)), Operator::Plus, call("IntToStr", vec![value])),
+            self.width(),
+        ])
+    }
+
+    fn width(&self) -> UleExpression {
+        UleExpression::Integer(self.width as i32)
+    }
+
+    /// Globals keep their name, parameters and locals are prefixed with the
+    /// name of their function. Names are made unique with a number.
+    fn assign_names(&mut self, functions: &[&HuleFunction]) {
+        let mut taken: Vec<String> = RUNTIME_NAMES.iter().map(|name| name.to_string()).collect();
+        let mut variables: Vec<(VarId, String)> = self.program.globals().iter()
+            .map(|global| (*global, self.program.variable(*global).name.clone()))
+            .collect();
+        for function in functions {
+            let prefix = function.name.trim_start_matches('_');
+            for variable in function.parameters.iter().chain(&function.locals) {
+                let name = self.program.variable(*variable).name.trim_start_matches('_');
+                variables.push((*variable, format!("{}_{}", prefix, name)));
+            }
+        }
+
+        for (variable, name) in variables {
+            let mut unique = name.clone();
+            let mut suffix = 1;
+            while taken.contains(&unique) || is_reserved(&unique) {
+                unique = format!("{}{}", name, suffix);
+                suffix += 1;
+            }
+            taken.push(unique.clone());
+            self.names.insert(variable, unique);
+        }
+    }
+
+    fn add_states(&mut self, function: &HuleFunction) {
+        let predecessors = predecessor_counts(function);
+        if predecessors[0] > 0 && !self.enter(function).is_empty() {
+            self.states.push(State { function: function.id, kind: StateKind::Entry });
+        }
+        for index in 0..function.blocks.len() {
+            self.states.push(State { function: function.id, kind: StateKind::Block(BlockId(index)) });
+        }
+        for (index, block) in function.blocks.iter().enumerate() {
+            if let Terminator::Call { continuation, .. } = block.terminator {
+                if self.resumed_block(function, continuation).is_none() {
+                    self.states.push(State { function: function.id, kind: StateKind::Resume(BlockId(index)) });
+                }
+            }
+        }
+    }
+
+    fn state(&self, function: FunctionId, kind: StateKind) -> usize {
+        self.states.iter()
+            .position(|state| *state == State { function, kind })
+            .expect("state exists")
+    }
+
+    fn has_state(&self, function: FunctionId, kind: StateKind) -> bool {
+        self.states.contains(&State { function, kind })
+    }
+
+    fn entry_state(&self, function: FunctionId) -> usize {
+        if self.has_state(function, StateKind::Entry) {
+            self.state(function, StateKind::Entry)
+        } else {
+            self.state(function, StateKind::Block(BlockId(0)))
+        }
+    }
+
+    /// Call block whose `Resume` is merged into `block`, because the call is
+    /// the only way to reach it.
+    fn resumed_block(&self, function: &HuleFunction, block: BlockId) -> Option<BlockId> {
+        if block == BlockId(0) || predecessor_counts(function)[block.0] != 1 {
+            return None;
+        }
+        function.blocks.iter()
+            .position(|caller| matches!(caller.terminator, Terminator::Call { continuation, .. } if continuation == block))
+            .map(BlockId)
+    }
+
+    fn program(&self) -> Vec<UleNode> {
+        let mut nodes = vec![];
+        if self.uses_stack {
+            nodes.push(assign("callStack", self.field(self.entry_state(FunctionId(0)))));
+            nodes.push(assign("dynStack", UleExpression::String(String::new())));
+            nodes.push(assign("csSize", UleExpression::Integer(0)));
+            nodes.push(assign("csAppend", UleExpression::String(String::new())));
+            nodes.push(assign("fc", UleExpression::Integer(0)));
+            nodes.push(assign("hp", UleExpression::Integer(0)));
+            nodes.push(assign("hv", UleExpression::String(String::new())));
+            nodes.push(assign("ri", UleExpression::Integer(0)));
+            nodes.push(assign("rs", UleExpression::String(String::new())));
+            nodes.push(assign("hx", UleExpression::Integer(0)));
+        } else if self.states.len() > 1 {
+            nodes.push(assign("fc", UleExpression::Integer(0)));
+        }
+
+        for global in self.program.globals() {
+            let variable = self.program.variable(*global);
+            let value = match &variable.initializer {
+                Some(value) => self.expression(value),
+                None => default_value(&variable.data_type),
+            };
+            // registers are runtime variables shared by all functions
+            let owner = if variable.register { CodeOwner::Runtime } else { CodeOwner::Global(variable.name.clone()) };
+            nodes.push(UleNode::Owner(owner));
+            nodes.push(assign(&self.names[global], value));
+        }
+
+        if !self.uses_stack && self.states.len() == 1 {
+            // a single block, which can only end with `return`
+            nodes.extend(self.state_body(self.states[0]));
+            return nodes;
+        }
+        nodes.push(UleNode::Owner(CodeOwner::Runtime));
+
+        let mut dispatch = vec![];
+        for (index, state) in self.states.iter().enumerate().rev() {
+            dispatch = vec![UleNode::IfStatement(UleIfStatement {
+                condition: binary(variable("fc"), Operator::Equal, UleExpression::Integer(index as i32)),
+                body: self.state_body(*state),
+                else_body: dispatch,
+            })];
+        }
+
+        let loop_node = if self.uses_stack {
+            let mut body = vec![
+                assign("fc", call("StrToInt", vec![call("Right", vec![variable("callStack"), self.width()])])),
+                assign("csSize", call("StrLen", vec![variable("callStack")])),
+            ];
+            body.extend(dispatch);
+            body.push(assign("callStack", binary(
+                call("Left", vec![variable("callStack"), binary(variable("csSize"), Operator::Minus, self.width())]),
+                Operator::Plus,
+                variable("csAppend"))));
+            body.push(assign("csAppend", UleExpression::String(String::new())));
+            UleWhileLoop {
+                condition: binary(
+                    binary(call("StrLen", vec![variable("callStack")]), Operator::GreaterThan, UleExpression::Integer(0)),
+                    Operator::And,
+                    binary(variable("hx"), Operator::Equal, UleExpression::Integer(0))),
+                body,
+            }
+        } else {
+            UleWhileLoop {
+                condition: binary(variable("fc"), Operator::GreaterEqualThan, UleExpression::Integer(0)),
+                body: dispatch,
+            }
+        };
+        nodes.push(UleNode::WhileLoop(loop_node));
+        if self.uses_stack {
+            nodes.push(UleNode::IfStatement(UleIfStatement {
+                condition: binary(variable("hx"), Operator::Equal, UleExpression::Integer(1)),
+                body: vec![assign(&self.heap_error, UleExpression::String(HEAP_OVERFLOW_VALUE.to_string()))],
+                else_body: vec![],
+            }));
+        }
+
+        nodes
+    }
+
+    fn state_body(&self, state: State) -> Vec<UleNode> {
+        let function = self.program.function(state.function);
+        let mut nodes = vec![UleNode::Owner(CodeOwner::Function(function.name.clone()))];
+        nodes.extend(self.state_code(function, state.kind));
+        nodes
+    }
+
+    fn state_code(&self, function: &HuleFunction, kind: StateKind) -> Vec<UleNode> {
+        match kind {
+            StateKind::Entry => {
+                let mut nodes = self.enter(function);
+                nodes.extend(self.jump(self.state(function.id, StateKind::Block(BlockId(0)))));
+                nodes
+            }
+            StateKind::Resume(call_block) => {
+                let mut nodes = self.resume(function, call_block);
+                if let Terminator::Call { continuation, .. } = function.block(call_block).terminator {
+                    nodes.extend(self.jump(self.state(function.id, StateKind::Block(continuation))));
+                }
+                nodes
+            }
+            StateKind::Block(block) => {
+                let mut nodes = vec![];
+                if block == BlockId(0) && !self.has_state(function.id, StateKind::Entry) {
+                    nodes.extend(self.enter(function));
+                }
+                if let Some(call_block) = self.resumed_block(function, block) {
+                    nodes.extend(self.resume(function, call_block));
+                }
+
+                for instruction in &function.block(block).instructions {
+                    match instruction {
+                        IrInstruction::Assign(target, value) => nodes.push(assign(&self.target(target), self.expression(value))),
+                        IrInstruction::Location(position) => nodes.push(UleNode::Location(*position)),
+                        _ => {}
+                    }
+                }
+
+                nodes.extend(self.terminator(function, block));
+                nodes
+            }
+        }
+    }
+
+    /// Loads the parameters of a new frame and resets the locals which are
+    /// read before they are written.
+    fn enter(&self, function: &HuleFunction) -> Vec<UleNode> {
+        let mut nodes = self.load_fields(&function.parameters);
+
+        let mut unset: Vec<VarId> = self.liveness[&function.id].live_in[0].iter()
+            .filter(|variable| !function.parameters.contains(variable))
+            .copied()
+            .collect();
+        unset.sort();
+        for variable in unset {
+            nodes.push(assign(&self.names[&variable], default_value(&self.program.variable(variable).data_type)));
+        }
+
+        nodes
+    }
+
+    /// Loads the variables saved by the call ending `call_block` and stores
+    /// the returned value.
+    fn resume(&self, function: &HuleFunction, call_block: BlockId) -> Vec<UleNode> {
+        // the resumed code belongs to the statement of the call
+        let mut nodes: Vec<UleNode> = function.block(call_block).instructions.iter()
+            .rev()
+            .find_map(|instruction| match instruction {
+                IrInstruction::Location(position) => Some(UleNode::Location(*position)),
+                _ => None,
+            })
+            .into_iter()
+            .collect();
+        nodes.extend(self.load_fields(&self.saved_variables(function, call_block)));
+        if let Terminator::Call { function: callee, result: Some(result), .. } = &function.block(call_block).terminator {
+            let value = match self.program.function(*callee).return_type {
+                HuleType::String => variable("rs"),
+                _ => variable("ri"),
+            };
+            nodes.push(assign(&self.target(result), value));
+        }
+        nodes
+    }
+
+    /// Variables of the caller needed after the call ending `call_block`,
+    /// if the callee may overwrite them by calling the caller again.
+    fn saved_variables(&self, function: &HuleFunction, call_block: BlockId) -> Vec<VarId> {
+        let (callee, result) = match &function.block(call_block).terminator {
+            Terminator::Call { function, result, .. } => (*function, result),
+            _ => return vec![],
+        };
+        if callee != function.id && !self.graph.reaches(callee.0, function.id.0) {
+            return vec![];
+        }
+
+        let mut saved: Vec<VarId> = self.liveness[&function.id].live_out[call_block.0].iter()
+            .filter(|variable| !matches!(result, Some(IrTarget::Variable(result)) if result == *variable))
+            .copied()
+            .collect();
+        saved.sort();
+        saved
+    }
+
+    fn terminator(&self, function: &HuleFunction, block: BlockId) -> Vec<UleNode> {
+        match &function.block(block).terminator {
+            Terminator::Goto(target) => self.jump(self.state(function.id, StateKind::Block(*target))),
+            Terminator::Branch { condition, then_block, else_block } => vec![UleNode::IfStatement(UleIfStatement {
+                condition: self.expression(condition),
+                body: self.jump(self.state(function.id, StateKind::Block(*then_block))),
+                else_body: self.jump(self.state(function.id, StateKind::Block(*else_block))),
+            })],
+            Terminator::Call { function: callee, arguments, continuation, .. } => {
+                let mut nodes = vec![];
+                for saved in self.saved_variables(function, block) {
+                    let data_type = self.program.variable(saved).data_type.clone();
+                    self.push_value(variable(&self.names[&saved]), &data_type, &mut nodes);
+                }
+                let resume_state = if self.has_state(function.id, StateKind::Resume(block)) {
+                    self.state(function.id, StateKind::Resume(block))
+                } else {
+                    self.state(function.id, StateKind::Block(*continuation))
+                };
+                push_field(self.field(resume_state), &mut nodes);
+                self.push_frame(*callee, arguments, &mut nodes);
+                nodes
+            }
+            Terminator::TailCall { function: callee, arguments } => {
+                let mut nodes = vec![];
+                self.push_frame(*callee, arguments, &mut nodes);
+                nodes
+            }
+            Terminator::Return(value) => {
+                let mut nodes = vec![];
+                if let (Some(value), true) = (value, self.uses_stack) {
+                    let register = match function.return_type {
+                        HuleType::String => "rs",
+                        _ => "ri",
+                    };
+                    nodes.push(assign(register, self.expression(value)));
+                }
+                if !self.uses_stack && self.states.len() > 1 {
+                    nodes.push(assign("fc", UleExpression::Integer(-1)));
+                }
+                nodes
+            }
+        }
+    }
+
+    fn jump(&self, state: usize) -> Vec<UleNode> {
+        if self.uses_stack {
+            vec![assign("csAppend", self.field(state))]
+        } else {
+            vec![assign("fc", UleExpression::Integer(state as i32))]
+        }
+    }
+
+    /// Pointers to the arguments followed by the first state of the callee.
+    fn push_frame(&self, callee: FunctionId, arguments: &[IrExpression], nodes: &mut Vec<UleNode>) {
+        for argument in arguments {
+            self.push_value(self.expression(argument), &self.program.expression_type(argument), nodes);
+        }
+        push_field(self.field(self.entry_state(callee)), nodes);
+    }
+
+    /// Stores the value as record on the heap and appends a pointer to it to
+    /// `csAppend`. If the heap is full, nothing is stored and `hx` ends the
+    /// loop after the state, the pointers of the state are never read.
+    fn push_value(&self, value: UleExpression, data_type: &HuleType, nodes: &mut Vec<UleNode>) {
+        let value = match data_type {
+            HuleType::Int | HuleType::Bool => call("IntToStr", vec![value]),
+            _ => value,
+        };
+        nodes.push(assign("hv", value));
+        nodes.push(UleNode::IfStatement(UleIfStatement {
+            condition: binary(
+                binary(variable("hx"), Operator::Equal, UleExpression::Integer(0)),
+                Operator::And,
+                binary(
+                    binary(binary(call("StrLen", vec![variable("dynStack")]), Operator::Plus, self.width()),
+                        Operator::Plus,
+                        call("StrLen", vec![variable("hv")])),
+                    Operator::LowerEqualThan,
+                    UleExpression::Integer(self.heap_size as i32))),
+            body: vec![
+                assign("hp", binary(call("StrLen", vec![variable("dynStack")]), Operator::Plus, UleExpression::Integer(1))),
+                assign("dynStack", binary(
+                    binary(variable("dynStack"), Operator::Plus, self.padded(call("StrLen", vec![variable("hv")]))),
+                    Operator::Plus,
+                    variable("hv"))),
+            ],
+            else_body: vec![assign("hx", UleExpression::Integer(1))],
+        }));
+        push_field(self.padded(variable("hp")), nodes);
+    }
+
+    /// Loads the variables from the heap records the fields below the state
+    /// point to and removes the fields from the frame. The records are the
+    /// last ones on the heap, they are freed by loading them from the last
+    /// to the first and cutting the heap before the first one.
+    fn load_fields(&self, variables: &[VarId]) -> Vec<UleNode> {
+        let mut nodes = vec![];
+        for (index, loaded) in variables.iter().enumerate().rev() {
+            let offset = (variables.len() - index + 1) * self.width - 1;
+            nodes.push(assign("hp", call("StrToInt", vec![call("Mid", vec![
+                variable("callStack"),
+                binary(variable("csSize"), Operator::Minus, UleExpression::Integer(offset as i32)),
+                self.width(),
+            ])])));
+
+            let length = call("StrToInt", vec![call("Mid", vec![variable("dynStack"), variable("hp"), self.width()])]);
+            let value = call("Mid", vec![
+                variable("dynStack"),
+                binary(variable("hp"), Operator::Plus, self.width()),
+                length,
+            ]);
+            let value = match self.program.variable(*loaded).data_type {
+                HuleType::Int | HuleType::Bool => call("StrToInt", vec![value]),
+                _ => value,
+            };
+            nodes.push(assign(&self.names[loaded], value));
+        }
+
+        if !variables.is_empty() {
+            nodes.push(assign("dynStack", call("Left", vec![
+                variable("dynStack"),
+                binary(variable("hp"), Operator::Minus, UleExpression::Integer(1)),
+            ])));
+            let size = (variables.len() * self.width) as i32;
+            nodes.push(assign("csSize", binary(variable("csSize"), Operator::Minus, UleExpression::Integer(size))));
+        }
+        nodes
+    }
+
+    fn target(&self, target: &IrTarget) -> String {
+        match target {
+            IrTarget::Variable(variable) => self.names[variable].clone(),
+            IrTarget::External(name) => name.clone(),
+        }
+    }
+
+    fn expression(&self, expression: &IrExpression) -> UleExpression {
+        match expression {
+            IrExpression::Integer(value) => UleExpression::Integer(*value),
+            IrExpression::String(value) => UleExpression::String(value.clone()),
+            IrExpression::Boolean(value) => UleExpression::Integer(*value as i32),
+            IrExpression::Variable(variable) => UleExpression::Variable(self.names[variable].clone()),
+            IrExpression::External(name) => UleExpression::Variable(name.clone()),
+            IrExpression::Call(..) => panic!("calls of hyperULE functions are terminators"),
+            IrExpression::Builtin(name, arguments) => UleExpression::Call(
+                name.clone(),
+                arguments.iter().map(|argument| self.expression(argument)).collect()),
+            IrExpression::Binary { left, operator, right } => binary(
+                self.expression(left), operator.clone(), self.expression(right)),
+        }
+    }
+}
+
+/// Number of decimal digits of the value.
+fn digits(value: usize) -> usize {
+    value.to_string().len()
+}
+
+fn predecessor_counts(function: &HuleFunction) -> Vec<usize> {
+    let mut counts = vec![0; function.blocks.len()];
+    for block in &function.blocks {
+        for successor in block.terminator.successors() {
+            counts[successor.0] += 1;
+        }
+    }
+    counts
 }
 
-impl HyperBackend<HuleProgram, String> for UleBackend {
-    fn generate(source: HuleProgram) -> String {
-        "{\n}".to_string()
+fn default_value(data_type: &HuleType) -> UleExpression {
+    match data_type {
+        HuleType::Int | HuleType::Bool => UleExpression::Integer(0),
+        _ => UleExpression::String(String::new()),
+    }
+}
+
+/// Appends the value to `csAppend` of the state ending with `nodes`.
+/// Consecutive appends are merged into one assignment, `csAppend` is empty
+/// at the start of every state.
+fn push_field(value: UleExpression, nodes: &mut Vec<UleNode>) {
+    let is_append = |node: &UleNode| matches!(node, UleNode::VariableDefinition(definition) if definition.name == "csAppend");
+    if nodes.last().is_some_and(is_append) {
+        if let Some(UleNode::VariableDefinition(definition)) = nodes.last_mut() {
+            definition.value = binary(definition.value.clone(), Operator::Plus, value);
+        }
+    } else if nodes.iter().any(is_append) {
+        nodes.push(assign("csAppend", binary(variable("csAppend"), Operator::Plus, value)));
+    } else {
+        nodes.push(assign("csAppend", value));
+    }
+}
+
+fn assign(name: &str, value: UleExpression) -> UleNode {
+    UleNode::VariableDefinition(UleVarDef { name: name.to_string(), value })
+}
+
+fn variable(name: &str) -> UleExpression {
+    UleExpression::Variable(name.to_string())
+}
+
+fn call(name: &str, arguments: Vec<UleExpression>) -> UleExpression {
+    UleExpression::Call(name.to_string(), arguments)
+}
+
+fn binary(left: UleExpression, operator: Operator, right: UleExpression) -> UleExpression {
+    UleExpression::Binary { left: Box::new(left), operator, right: Box::new(right) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lowering::{lower, lower_with_locations};
+    use crate::optimizer::{OptimizerLevel, PassManager};
+    use crate::parser::AstParser;
+
+    fn default_options() -> UleOptions {
+        UleOptions {
+            peephole: false,
+            minify: false,
+            frame_width: None,
+            heap_size: DEFAULT_HEAP_SIZE,
+            heap_error: DEFAULT_HEAP_ERROR_FIELD.to_string(),
+            style: UleStyle::Pretty,
+            file: "test.hule".to_string(),
+        }
+    }
+
+    fn to_source(nodes: &[UleNode]) -> String {
+        UleWriter::new(UleStyle::Pretty, "test.hule").write(nodes).source
+    }
+
+    fn generate(input: &str, level: OptimizerLevel, options: &UleOptions) -> Result<Vec<UleNode>, UleError> {
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let mut program = lower(&ast).unwrap();
+        PassManager::new(level, &[], &[]).unwrap().run(&mut program, &mut vec![]);
+        Ok(Generator::new(&program, options)?.program())
+    }
+
+    fn prepare_test(input: &str, level: OptimizerLevel) -> Vec<UleNode> {
+        generate(input, level, &default_options()).unwrap()
+    }
+
+    #[derive(Clone, PartialEq, Debug)]
+    enum Value {
+        Integer(i64),
+        String(String),
     }
-}
\ No newline at end of file
+
+    impl Value {
+        fn integer(&self) -> i64 {
+            match self {
+                Value::Integer(value) => *value,
+                Value::String(value) => value.parse().unwrap_or(0),
+            }
+        }
+
+        fn string(&self) -> String {
+            match self {
+                Value::Integer(value) => value.to_string(),
+                Value::String(value) => value.clone(),
+            }
+        }
+    }
+
+    /// Runs the nodes with the semantics of ULE and returns the variables.
+    fn run(nodes: &[UleNode], variables: &mut HashMap<String, Value>) {
+        for node in nodes {
+            match node {
+                UleNode::VariableDefinition(definition) => {
+                    let value = evaluate(&definition.value, variables);
+                    variables.insert(definition.name.clone(), value);
+                }
+                UleNode::IfStatement(if_statement) => match evaluate(&if_statement.condition, variables).integer() {
+                    0 => run(&if_statement.else_body, variables),
+                    _ => run(&if_statement.body, variables),
+                },
+                UleNode::WhileLoop(while_loop) => {
+                    let mut steps = 0;
+                    while evaluate(&while_loop.condition, variables).integer() != 0 {
+                        run(&while_loop.body, variables);
+                        steps += 1;
+                        assert!(steps < 10000, "runtime loop does not terminate");
+                    }
+                }
+                UleNode::Scope(scope) => run(&scope.body, variables),
+                UleNode::Location(_) | UleNode::Owner(_) => {}
+            }
+        }
+    }
+
+    fn evaluate(expression: &UleExpression, variables: &HashMap<String, Value>) -> Value {
+        match expression {
+            UleExpression::Integer(value) => Value::Integer(*value as i64),
+            UleExpression::String(value) => Value::String(value.clone()),
+            UleExpression::Variable(name) => variables.get(name)
+                .unwrap_or_else(|| panic!("'{}' is read before it is written", name))
+                .clone(),
+            UleExpression::Call(name, arguments) => {
+                let arguments: Vec<Value> = arguments.iter().map(|argument| evaluate(argument, variables)).collect();
+                let text: Vec<char> = arguments[0].string().chars().collect();
+                let slice = |start: i64, length: i64| {
+                    let start = start.clamp(0, text.len() as i64) as usize;
+                    let end = (start as i64 + length.max(0)).min(text.len() as i64) as usize;
+                    Value::String(text[start..end].iter().collect())
+                };
+                match name.as_str() {
+                    "StrLen" => Value::Integer(text.len() as i64),
+                    "Left" => slice(0, arguments[1].integer()),
+                    "Right" => slice(text.len() as i64 - arguments[1].integer(), arguments[1].integer()),
+                    "Mid" => slice(arguments[1].integer() - 1, arguments[2].integer()),
+                    "IntToStr" => Value::String(arguments[0].string()),
+                    "StrToInt" => Value::Integer(arguments[0].integer()),
+                    _ => panic!("unknown built-in '{}'", name),
+                }
+            }
+            UleExpression::Binary { left, operator, right } => {
+                let (left, right) = (evaluate(left, variables), evaluate(right, variables));
+                let is_string = matches!(left, Value::String(_)) || matches!(right, Value::String(_));
+                let compare = |ordering: std::cmp::Ordering, accepted: &[std::cmp::Ordering]| {
+                    Value::Integer(accepted.contains(&ordering) as i64)
+                };
+                let ordering = if is_string {
+                    left.string().cmp(&right.string())
+                } else {
+                    left.integer().cmp(&right.integer())
+                };
+                use std::cmp::Ordering::*;
+                match operator {
+                    Operator::Plus if is_string => Value::String(left.string() + &right.string()),
+                    Operator::Plus => Value::Integer(left.integer() + right.integer()),
+                    Operator::Minus => Value::Integer(left.integer() - right.integer()),
+                    Operator::Multiply => Value::Integer(left.integer() * right.integer()),
+                    Operator::Divide => Value::Integer(left.integer() / right.integer()),
+                    Operator::Equal => compare(ordering, &[Equal]),
+                    Operator::NotEqual => compare(ordering, &[Less, Greater]),
+                    Operator::GreaterThan => compare(ordering, &[Greater]),
+                    Operator::GreaterEqualThan => compare(ordering, &[Greater, Equal]),
+                    Operator::LowerThan => compare(ordering, &[Less]),
+                    Operator::LowerEqualThan => compare(ordering, &[Less, Equal]),
+                    Operator::And => Value::Integer((left.integer() != 0 && right.integer() != 0) as i64),
+                    Operator::Or => Value::Integer((left.integer() != 0 || right.integer() != 0) as i64),
+                }
+            }
+        }
+    }
+
+    /// `OUT1.Data` after running the program at every optimizer level.
+    fn outputs(input: &str, data: &str) -> Vec<String> {
+        [OptimizerLevel::O0, OptimizerLevel::O1, OptimizerLevel::O2, OptimizerLevel::O3].into_iter()
+            .map(|level| {
+                let mut variables = HashMap::from([("OUT1.Data".to_string(), Value::String(data.to_string()))]);
+                run(&prepare_test(input, level), &mut variables);
+                variables["OUT1.Data"].string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_short_circuit_calls() {
+        let input = "bool Count(int n) { OUT1.Data = OUT1.Data + \"f\"; return n > 0 && Count(n - 1); } \
+            entry { Count(2); if (OUT1.Data == \"fff\" || Count(5)) { OUT1.Data = OUT1.Data + \"!\"; } }";
+
+        assert_eq!(outputs(input, ""), vec!["fff!"; 4]);
+    }
+
+    #[test]
+    fn test_inlined_locals_start_empty() {
+        let input = "string f() { string s; s = s + \"x\"; return s; } \
+            entry { int i = 0; while (i < 3) { OUT1.Data = OUT1.Data + f(); i = i + 1; } }";
+
+        assert_eq!(outputs(input, ""), vec!["xxx"; 4]);
+    }
+
+    #[test]
+    fn test_straight_line() {
+        let nodes = prepare_test("entry { OUT1.Data = \"Hello \\\"ULE\\\"\"; }", OptimizerLevel::O0);
+
+        assert_eq!(to_source(&nodes), "OUT1.Data = \"Hello \\\"ULE\\\"\";\n");
+    }
+
+    #[test]
+    fn test_call_runtime() {
+        let input = "string Greet(string name) { return \"Hello \" + name + \"!\"; } \
+            entry { OUT1.Data = Greet(OUT1.Data); }";
+        let source = to_source(&prepare_test(input, OptimizerLevel::O0));
+
+        assert!(source.starts_with("callStack = \"000\";\n"));
+        assert!(source.contains("while (StrLen(callStack) > 0 && hx == 0) {\n"));
+        assert!(source.contains("    callStack = Left(callStack, csSize - 3) + csAppend;\n"));
+        assert_eq!(outputs(input, "ULE"), vec!["Hello ULE!"; 4]);
+    }
+
+    #[test]
+    fn test_recursion_restores_frame() {
+        let input = "int fact(int n) { if (n <= 1) { return 1; } return n * fact(n - 1); } \
+            string count(int n, string text) { if (n == 0) { return text; } return count(n - 1, text + IntToStr(n)); } \
+            entry { OUT1.Data = IntToStr(fact(5)) + \" \" + count(3, OUT1.Data); }";
+
+        assert_eq!(outputs(input, ">"), vec!["120 >321"; 4]);
+    }
+
+    #[test]
+    fn test_state_loop() {
+        let input = "entry { int i = 0; string text = \"\"; while (i < 3) { text = text + IntToStr(i); i = i + 1; } \
+            OUT1.Data = text; }";
+        let source = to_source(&prepare_test(input, OptimizerLevel::O0));
+
+        assert!(source.starts_with("fc = 0;\n"));
+        assert!(!source.contains("callStack"));
+        assert_eq!(outputs(input, ""), vec!["012"; 4]);
+    }
+
+    #[test]
+    fn test_frame_width() {
+        let input = "int twice(int n) { return n * 2; } entry { OUT1.Data = IntToStr(twice(21)); }";
+        let options = UleOptions { heap_size: 20000, ..default_options() };
+        let nodes = generate(input, OptimizerLevel::O0, &options).unwrap();
+
+        let mut variables = HashMap::from([("OUT1.Data".to_string(), Value::String(String::new()))]);
+        run(&nodes, &mut variables);
+        assert!(to_source(&nodes).contains("fc = StrToInt(Right(callStack, 5));"));
+        assert_eq!(variables["OUT1.Data"], Value::String("42".to_string()));
+
+        let options = UleOptions { frame_width: Some(2), ..default_options() };
+        assert_eq!(generate(input, OptimizerLevel::O0, &options).err(), Some(UleError::FrameWidth { width: 2, required: 3 }));
+        // without calls there is no call stack to limit
+        let input = "entry { int i = 0; while (i < 3) { i = i + 1; } OUT1.Data = IntToStr(i); }";
+        assert!(generate(input, OptimizerLevel::O0, &options).is_ok());
+    }
+
+    #[test]
+    fn test_heap_is_reclaimed() {
+        let input = "int depth(int n, string text) { if (n == 0) { return StrLen(text); } \
+            return depth(n - 1, text + \"ab\") + 1; } \
+            entry { int i = 0; int total = 0; while (i < 20) { total = total + depth(3, \"x\"); i = i + 1; } \
+            OUT1.Data = IntToStr(total); }";
+        let nodes = prepare_test(input, OptimizerLevel::O0);
+
+        let mut variables = HashMap::from([("OUT1.Data".to_string(), Value::String(String::new()))]);
+        run(&nodes, &mut variables);
+        assert_eq!(variables["OUT1.Data"], Value::String("200".to_string()));
+        assert_eq!(variables["dynStack"], Value::String(String::new()));
+        assert_eq!(variables["hx"], Value::Integer(0));
+    }
+
+    #[test]
+    fn test_heap_overflow() {
+        let input = "string repeat(int n, string text) { if (n == 0) { return text; } \
+            return repeat(n - 1, text + text) + \"\"; } \
+            entry { OUT2.Data = \"started\"; OUT3.Data = repeat(6, \"abc\"); OUT2.Data = \"done\"; }";
+        let options = UleOptions { heap_size: 100, ..default_options() };
+        let nodes = generate(input, OptimizerLevel::O0, &options).unwrap();
+
+        let mut variables = HashMap::from([
+            ("OUT1.Data".to_string(), Value::String("label".to_string())),
+            ("OUT2.Data".to_string(), Value::String(String::new())),
+            ("OUT3.Data".to_string(), Value::String("unchanged".to_string())),
+        ]);
+        run(&nodes, &mut variables);
+        assert_eq!(variables["hx"], Value::Integer(1));
+        assert_eq!(variables["OUT1.Data"], Value::String(HEAP_OVERFLOW_VALUE.to_string()));
+        assert_eq!(variables["OUT2.Data"], Value::String("started".to_string()));
+        assert_eq!(variables["OUT3.Data"], Value::String("unchanged".to_string()));
+        let Value::String(heap) = &variables["dynStack"] else { panic!("heap is no string") };
+        assert!(heap.len() <= 100);
+
+        let options = UleOptions { heap_size: 100, heap_error: "OUT4.Data".to_string(), ..default_options() };
+        let nodes = generate(input, OptimizerLevel::O0, &options).unwrap();
+        variables.insert("OUT1.Data".to_string(), Value::String("label".to_string()));
+        run(&nodes, &mut variables);
+        assert_eq!(variables["OUT4.Data"], Value::String(HEAP_OVERFLOW_VALUE.to_string()));
+        assert_eq!(variables["OUT1.Data"], Value::String("label".to_string()));
+    }
+
+    #[test]
+    fn test_array_values_on_the_heap() {
+        let input = "string Walk(int n) { string seen[2]; string copy[2]; string result = IntToStr(n); \
+            if (n > 0) { result = result + Walk(n - 1); } copy = seen; seen = copy; return result; } \
+            entry { OUT1.Data = Walk(3); }";
+        let nodes = prepare_test(input, OptimizerLevel::O0);
+
+        let mut variables = HashMap::from([("OUT1.Data".to_string(), Value::String(String::new()))]);
+        run(&nodes, &mut variables);
+        assert!(to_source(&nodes).contains("hv = Walk_seen;"));
+        assert_eq!(variables["OUT1.Data"], Value::String("3210".to_string()));
+        assert_eq!(variables["dynStack"], Value::String(String::new()));
+        assert_eq!(variables["hx"], Value::Integer(0));
+    }
+
+    #[test]
+    fn test_location_comments() {
+        let input = "int Twice(int x) { return x * 2; }\nentry {\n    int i = 0;\n    while (i < 3) {\n        i = Twice(i) + 1;\n    }\n    OUT1.Data = IntToStr(i);\n}";
+        let mut parser = AstParser::new(input);
+        let ast = parser.parse(input).unwrap();
+        let backend = UleBackend::new(UleOptions { peephole: true, style: UleStyle::Compact, ..default_options() });
+
+        let mut sources = vec![];
+        for mut program in [lower(&ast).unwrap(), lower_with_locations(&ast).unwrap()] {
+            PassManager::new(OptimizerLevel::O2, &[], &[]).unwrap().run(&mut program, &mut vec![]);
+            sources.push(backend.generate(&program).unwrap().source);
+        }
+        assert_eq!(sources[0], sources[1]);
+
+        let mut program = lower_with_locations(&ast).unwrap();
+        PassManager::new(OptimizerLevel::O2, &[], &[]).unwrap().run(&mut program, &mut vec![]);
+        let source = UleBackend::new(default_options()).generate(&program).unwrap().source;
+        assert!(source.contains("// hyperULE: test.hule:3\n"));
+        assert!(source.contains("// hyperULE: test.hule:7\n"));
+    }
+}
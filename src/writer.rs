@@ -0,0 +1,329 @@
+//! hyperULE ULE Writer
+//!
+//! Prints the node tree of the ULE backend as source in one of two styles:
+//! - `pretty` indents every level by four spaces and puts a
+//!   `// hyperULE: file:line` comment above the code of every statement,
+//! - `compact` leaves out every comment and all whitespace the ULE syntax
+//!   does not need, which keeps the script on the printer small.
+//!
+//! While writing, every byte is counted for the `CodeOwner` of its node, so
+//! the size report shows the real cost of every part of the program.
+
+use crate::ast::{Operator, SourcePosition};
+use crate::sourcemap::SourceMap;
+use crate::literals::escape_string;
+use crate::ule::{CodeOwner, UleExpression, UleIfStatement, UleNode};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum UleStyle {
+    Pretty,
+    Compact,
+}
+
+impl UleStyle {
+    pub fn from_name(name: &str) -> Option<UleStyle> {
+        match name {
+            "pretty" => Some(UleStyle::Pretty),
+            "compact" => Some(UleStyle::Compact),
+            _ => None,
+        }
+    }
+}
+
+/// Bytes of the written code by the part of the program they belong to.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct CodeSizes {
+    /// Bytes of every owner in order of appearance, without string literals
+    pub owners: Vec<(CodeOwner, usize)>,
+    /// Bytes of every string literal including its quotes, over all uses
+    pub literals: Vec<(String, usize)>,
+}
+
+pub struct WrittenUle {
+    pub source: String,
+    /// Statements the positions of the source were generated from
+    pub source_map: SourceMap,
+    pub sizes: CodeSizes,
+}
+
+struct Output {
+    source: String,
+    /// Byte offset of every node and the statement it belongs to
+    offsets: Vec<(usize, Option<SourcePosition>)>,
+    /// Statement of the nodes written next
+    location: Option<SourcePosition>,
+    /// Owner of the code written next, since `owner_start`
+    owner: CodeOwner,
+    owner_start: usize,
+    /// Bytes of the string literals written since `owner_start`
+    owner_literals: usize,
+    sizes: CodeSizes,
+}
+
+impl Output {
+    /// Counts the code written since the last change for its owner.
+    fn set_owner(&mut self, owner: CodeOwner) {
+        let bytes = self.source.len() - self.owner_start - self.owner_literals;
+        match self.sizes.owners.iter_mut().find(|(known, _)| *known == self.owner) {
+            Some((_, total)) => *total += bytes,
+            None => self.sizes.owners.push((self.owner.clone(), bytes)),
+        }
+
+        self.owner = owner;
+        self.owner_start = self.source.len();
+        self.owner_literals = 0;
+    }
+
+    fn count_literals(&mut self, expression: &UleExpression) {
+        match expression {
+            UleExpression::String(value) => {
+                let bytes = escape_string(value).len() + 2;
+                match self.sizes.literals.iter_mut().find(|(known, _)| known == value) {
+                    Some((_, total)) => *total += bytes,
+                    None => self.sizes.literals.push((value.clone(), bytes)),
+                }
+                self.owner_literals += bytes;
+            }
+            UleExpression::Call(_, arguments) => {
+                for argument in arguments {
+                    self.count_literals(argument);
+                }
+            }
+            UleExpression::Binary { left, right, .. } => {
+                self.count_literals(left);
+                self.count_literals(right);
+            }
+            _ => {}
+        }
+    }
+}
+
+pub struct UleWriter {
+    style: UleStyle,
+    /// Name of the hyperULE source in location comments
+    file: String,
+}
+
+impl UleWriter {
+    pub fn new(style: UleStyle, file: &str) -> UleWriter {
+        UleWriter { style, file: file.to_string() }
+    }
+
+    /// The source, the map of its positions to the hyperULE statements and
+    /// the bytes of every part of the program.
+    pub fn write(&self, nodes: &[UleNode]) -> WrittenUle {
+        let mut output = Output {
+            source: String::new(),
+            offsets: vec![],
+            location: None,
+            owner: CodeOwner::Runtime,
+            owner_start: 0,
+            owner_literals: 0,
+            sizes: CodeSizes::default(),
+        };
+        self.write_nodes(nodes, 0, &mut output);
+        output.set_owner(CodeOwner::Runtime);
+
+        let source_map = SourceMap::from_offsets(&self.file, &output.source, &output.offsets);
+        WrittenUle { source: output.source, source_map, sizes: output.sizes }
+    }
+
+    /// Writes the nodes, a location is only written in front of the code
+    /// it belongs to. Optimizations leave the locations of statements
+    /// without any code of their own.
+    fn write_nodes(&self, nodes: &[UleNode], indent: usize, output: &mut Output) {
+        // locations and owners end with the body they are in, the code
+        // around a state is runtime code
+        let outer = output.location;
+        let outer_owner = output.owner.clone();
+        for (index, node) in nodes.iter().enumerate() {
+            if let UleNode::Location(position) = node {
+                output.location = Some(*position);
+            }
+            if let UleNode::Owner(owner) = node {
+                output.set_owner(owner.clone());
+                continue;
+            }
+            if node.is_location() && nodes.get(index + 1).is_none_or(UleNode::is_location) {
+                continue;
+            }
+            self.write_node(node, indent, output);
+        }
+        output.location = outer;
+        if output.owner != outer_owner {
+            output.set_owner(outer_owner);
+        }
+    }
+
+    fn write_node(&self, node: &UleNode, indent: usize, output: &mut Output) {
+        if let UleNode::Location(position) = node {
+            if self.style == UleStyle::Pretty {
+                self.write_indent(indent, output);
+                output.offsets.push((output.source.len(), Some(*position)));
+                output.source.push_str(&format!("// hyperULE: {}:{}\n", self.file, position.line));
+            }
+            return;
+        }
+
+        self.write_indent(indent, output);
+        output.offsets.push((output.source.len(), output.location));
+        match node {
+            UleNode::VariableDefinition(definition) => {
+                output.count_literals(&definition.value);
+                output.source.push_str(&format!("{}{}={}{};", definition.name, self.space(), self.space(), self.expression(&definition.value)));
+            }
+            UleNode::IfStatement(if_statement) => self.write_if(if_statement, indent, output),
+            UleNode::WhileLoop(while_loop) => {
+                output.count_literals(&while_loop.condition);
+                output.source.push_str(&format!("while{}({}){}", self.space(), self.expression(&while_loop.condition), self.space()));
+                self.write_body(&while_loop.body, indent, output);
+            }
+            UleNode::Scope(scope) => self.write_body(&scope.body, indent, output),
+            UleNode::Location(_) | UleNode::Owner(_) => {}
+        }
+        self.write_line_break(output);
+    }
+
+    /// `{`, the indented nodes and `}` without a line break after it.
+    fn write_body(&self, nodes: &[UleNode], indent: usize, output: &mut Output) {
+        output.source.push('{');
+        self.write_line_break(output);
+        self.write_nodes(nodes, indent + 1, output);
+        self.write_indent(indent, output);
+        // the rest of the line, like `} else if (...)`, belongs to the outer code
+        output.offsets.push((output.source.len(), output.location));
+        output.source.push('}');
+    }
+
+    fn write_if(&self, if_statement: &UleIfStatement, indent: usize, output: &mut Output) {
+        output.count_literals(&if_statement.condition);
+        output.source.push_str(&format!("if{}({}){}", self.space(), self.expression(&if_statement.condition), self.space()));
+        self.write_body(&if_statement.body, indent, output);
+        match if_statement.else_body.as_slice() {
+            [] => {}
+            [UleNode::IfStatement(else_if)] => {
+                output.source.push_str(&format!("{}else ", self.space()));
+                self.write_if(else_if, indent, output);
+            }
+            else_body => {
+                output.source.push_str(&format!("{}else{}", self.space(), self.space()));
+                self.write_body(else_body, indent, output);
+            }
+        }
+    }
+
+    fn write_indent(&self, indent: usize, output: &mut Output) {
+        if self.style == UleStyle::Pretty {
+            output.source.push_str(&"    ".repeat(indent));
+        }
+    }
+
+    fn write_line_break(&self, output: &mut Output) {
+        if self.style == UleStyle::Pretty {
+            output.source.push('\n');
+        }
+    }
+
+    fn space(&self) -> &'static str {
+        match self.style {
+            UleStyle::Pretty => " ",
+            UleStyle::Compact => "",
+        }
+    }
+
+    pub fn expression(&self, expression: &UleExpression) -> String {
+        match expression {
+            UleExpression::Integer(value) => value.to_string(),
+            UleExpression::String(value) => format!("\"{}\"", escape_string(value)),
+            UleExpression::Variable(name) => name.clone(),
+            UleExpression::Call(name, arguments) => format!("{}({})", name, arguments.iter()
+                .map(|argument| self.expression(argument))
+                .collect::<Vec<String>>()
+                .join(&format!(",{}", self.space()))),
+            UleExpression::Binary { left, operator, right } => {
+                let right = self.operand(right, operator, true);
+                // `a - -1` must not become `a--1`
+                let separator = if right.starts_with('-') { " " } else { self.space() };
+                format!("{}{}{}{}{}", self.operand(left, operator, false), self.space(), operator.symbol(), separator, right)
+            }
+        }
+    }
+
+    /// Source of the expression as operand of `parent`, in parentheses if
+    /// it binds weaker. Operators are left associative.
+    fn operand(&self, expression: &UleExpression, parent: &Operator, is_right: bool) -> String {
+        match expression {
+            UleExpression::Binary { operator, .. }
+            if operator.precedence() < parent.precedence() || (is_right && operator.precedence() == parent.precedence())
+            => format!("({})", self.expression(expression)),
+            _ => self.expression(expression),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ule::{UleVarDef, UleWhileLoop};
+
+    fn prepare_test() -> Vec<UleNode> {
+        let condition = UleExpression::Binary {
+            left: Box::new(UleExpression::Variable("fc".to_string())),
+            operator: Operator::GreaterEqualThan,
+            right: Box::new(UleExpression::Integer(0)),
+        };
+        let decrement = UleExpression::Binary {
+            left: Box::new(UleExpression::Variable("fc".to_string())),
+            operator: Operator::Minus,
+            right: Box::new(UleExpression::Integer(-1)),
+        };
+        vec![UleNode::WhileLoop(UleWhileLoop {
+            condition: condition.clone(),
+            body: vec![
+                UleNode::Location(SourcePosition { line: 2, column: 5 }),
+                UleNode::Location(SourcePosition { line: 3, column: 5 }),
+                UleNode::IfStatement(UleIfStatement {
+                    condition,
+                    body: vec![UleNode::VariableDefinition(UleVarDef { name: "fc".to_string(), value: decrement })],
+                    else_body: vec![UleNode::VariableDefinition(UleVarDef {
+                        name: "OUT1.Data".to_string(),
+                        value: UleExpression::Call("Left".to_string(), vec![UleExpression::String("a b".to_string()), UleExpression::Integer(1)]),
+                    })],
+                }),
+                UleNode::Location(SourcePosition { line: 4, column: 5 }),
+            ],
+        })]
+    }
+
+    #[test]
+    fn test_pretty() {
+        let source = UleWriter::new(UleStyle::Pretty, "main.hule").write(&prepare_test()).source;
+
+        assert_eq!(source, concat!(
+            "while (fc >= 0) {\n",
+            "    // hyperULE: main.hule:3\n",
+            "    if (fc >= 0) {\n",
+            "        fc = fc - -1;\n",
+            "    } else {\n",
+            "        OUT1.Data = Left(\"a b\", 1);\n",
+            "    }\n",
+            "}\n"));
+    }
+
+    #[test]
+    fn test_compact() {
+        let source = UleWriter::new(UleStyle::Compact, "main.hule").write(&prepare_test()).source;
+
+        assert_eq!(source, "while(fc>=0){if(fc>=0){fc=fc- -1;}else{OUT1.Data=Left(\"a b\",1);}}");
+    }
+
+    #[test]
+    fn test_source_map() {
+        let map = UleWriter::new(UleStyle::Pretty, "main.hule").write(&prepare_test()).source_map;
+
+        assert_eq!(map.to_map_text(), "file = main.hule\n1:1 = -\n2:5 = 3:5\n8:1 = -\nend = 9:1\n");
+
+        let map = UleWriter::new(UleStyle::Compact, "main.hule").write(&prepare_test()).source_map;
+        assert_eq!(map.to_map_text(), "file = main.hule\n1:1 = -\n1:14 = 3:5\n1:65 = -\nend = 1:66\n");
+    }
+}
@@ -0,0 +1,61 @@
+//! Golden tests
+//!
+//! Compiles every `tests/golden/<name>.hule` with `-O2` and compares the
+//! output with the expected files next to it, `<name>.ir` for `--emit=ir`
+//! and `<name>.ule` for `--emit=ule`.
+//! Run with `UPDATE_GOLDEN=1` to rewrite the expected files after an
+//! intended change of the output.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn golden_inputs() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join("golden");
+    let mut inputs: Vec<PathBuf> = fs::read_dir(directory).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "hule"))
+        .collect();
+    inputs.sort();
+    inputs
+}
+
+/// Compiles within the directory of the input, so the file names in the
+/// comments of the ULE output do not depend on the checkout.
+fn compile(input: &Path, emit: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_hyperULECompiler"))
+        .current_dir(input.parent().unwrap())
+        .arg(input.file_name().unwrap())
+        .arg("-O2")
+        .arg(format!("--emit={}", emit))
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{} failed: {}", input.display(), String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn check_golden(emit: &str, extension: &str) {
+    for input in golden_inputs() {
+        let expected_path = input.with_extension(extension);
+        let actual = compile(&input, emit);
+
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path)
+            .unwrap_or_else(|_| panic!("{} is missing", expected_path.display()));
+        assert_eq!(actual, expected, "output of {} changed", input.display());
+    }
+}
+
+#[test]
+fn test_golden_ir() {
+    check_golden("ir", "ir");
+}
+
+#[test]
+fn test_golden_ule() {
+    check_golden("ule", "ule");
+}